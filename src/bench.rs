@@ -0,0 +1,338 @@
+use std::{fs, io};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use super::{Error, Point, Segment, Scene, SceneTree, SplitTree, VolumeKind, PointsCutter, CutStrategy, QueryStats, SCREEN_WIDTH, SCREEN_HEIGHT};
+use super::{tree_stats, scene_collisions};
+
+const SWEEP_SIZES: &[usize] = &[100, 1_000, 10_000, 100_000];
+const SWEEP_QUERIES: usize = 100;
+const SWEEP_NEAREST: usize = 8;
+const SWEEP_SEED: u64 = 0x5eed;
+/// Sweep builds as `(name, parallel, intersect strategy, nearest strategy)`, every query gets a CSV strategy of its own.
+const SWEEP_BUILDS: &[(&str, bool, &str, &str)] = &[
+    ("sequential", false, "sequential-intersect", "sequential-nearest"),
+    ("parallel", true, "parallel-intersect", "parallel-nearest"),
+];
+pub const MAX_SEGMENT_LENGTH: f64 = 64.;
+const DYNAMIC_FRAMES: usize = 20;
+const DYNAMIC_EVERY: usize = 100;
+const CUTTERS_SIZE: usize = 10_000;
+const HEADLESS_QUERIES: usize = 1_000;
+
+/// Generates `count` random segments no longer than `max_len` with both ends within the `width` x `height` area.
+pub fn random_scene<R>(rng: &mut R, count: usize, width: f64, height: f64, max_len: f64) -> Vec<Segment> where R: Rng {
+    (0 .. count)
+        .map(|_| {
+            let src = Point { x: rng.gen_range(0., width), y: rng.gen_range(0., height), };
+            let dst = Point {
+                x: (src.x + rng.gen_range(-max_len, max_len)).clamp(0., width),
+                y: (src.y + rng.gen_range(-max_len, max_len)).clamp(0., height),
+            };
+            Segment { src, dst, }
+        })
+        .collect()
+}
+
+pub fn seeded_rng(seed: u64) -> XorShiftRng {
+    // xorshift state must not be all zeros
+    SeedableRng::from_seed([(seed >> 32) as u32, seed as u32, 0x9e37_79b9, 0x7f4a_7c15])
+}
+
+/// One benchmark measurement: tree build time and the mean time of a single query.
+pub struct Timing {
+    pub size: usize,
+    pub strategy: &'static str,
+    pub build_ms: f64,
+    pub query_us: f64,
+}
+
+pub fn to_csv(timings: &[Timing]) -> String {
+    let mut csv = "size,strategy,build_ms,query_us\n".to_string();
+    for timing in timings {
+        csv.push_str(&format!("{},{},{:.6},{:.6}\n", timing.size, timing.strategy, timing.build_ms, timing.query_us));
+    }
+    csv
+}
+
+pub fn write_csv<P>(path: P, timings: &[Timing]) -> io::Result<()> where P: AsRef<Path> {
+    fs::write(path, to_csv(timings))
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000. + duration.subsec_nanos() as f64 / 1_000_000.
+}
+
+/// Builds trees of growing sizes and reports build and per query timings as a table.
+pub fn run_sweep(kind: VolumeKind, strategy: CutStrategy, cut_limit: f64) -> Result<Vec<Timing>, Error> {
+    let mut timings = Vec::new();
+    println!(
+        "{:>8} | {:>10} | {:>10} | {:>14} | {:>14} | {:>8}",
+        "size", "build", "build ms", "intersect us", "nearest us", "hits",
+    );
+    for (round, &size) in SWEEP_SIZES.iter().enumerate() {
+        let mut rng = seeded_rng(SWEEP_SEED + round as u64);
+        let (width, height) = (SCREEN_WIDTH as f64, SCREEN_HEIGHT as f64);
+        let obstacles = random_scene(&mut rng, size, width, height, MAX_SEGMENT_LENGTH);
+        let probes = random_scene(&mut rng, SWEEP_QUERIES, width, height, MAX_SEGMENT_LENGTH);
+
+        let mut cutter = PointsCutter::with_strategy(strategy);
+        for &(build, parallel, intersect_name, nearest_name) in SWEEP_BUILDS.iter() {
+            let build_start = Instant::now();
+            let tree = SceneTree::build(kind, &obstacles, &mut cutter, cut_limit, parallel)?;
+            let build_time = build_start.elapsed();
+
+            // every tree is queried on its own, so each row holds a measurement of its own
+            let mut hits = 0;
+            let intersect_start = Instant::now();
+            for probe in probes.iter() {
+                hits += tree.intersects(probe, &mut cutter)?.len();
+            }
+            let intersect_us = millis(intersect_start.elapsed()) * 1000. / SWEEP_QUERIES as f64;
+
+            let nearest_start = Instant::now();
+            for probe in probes.iter() {
+                for neighbour in tree.nearest(probe).take(SWEEP_NEAREST) {
+                    neighbour?;
+                }
+            }
+            let nearest_us = millis(nearest_start.elapsed()) * 1000. / SWEEP_QUERIES as f64;
+
+            println!(
+                "{:>8} | {:>10} | {:>10.3} | {:>14.3} | {:>14.3} | {:>8}",
+                size,
+                build,
+                millis(build_time),
+                intersect_us,
+                nearest_us,
+                hits,
+            );
+            timings.push(Timing { size, strategy: intersect_name, build_ms: millis(build_time), query_us: intersect_us, });
+            timings.push(Timing { size, strategy: nearest_name, build_ms: millis(build_time), query_us: nearest_us, });
+        }
+    }
+    Ok(timings)
+}
+
+/// Moves every `DYNAMIC_EVERY`-th obstacle each frame and compares rebuilding the whole tree
+/// against rebuilding only the dynamic part of a `SplitTree`, both followed by the same queries.
+pub fn run_dynamic(kind: VolumeKind, strategy: CutStrategy, cut_limit: f64) -> Result<Vec<Timing>, Error> {
+    let mut timings = Vec::new();
+    println!("{:>8} | {:>8} | {:>16} | {:>16}", "size", "dynamic", "full frame ms", "split frame ms");
+    for (round, &size) in SWEEP_SIZES.iter().enumerate() {
+        let mut rng = seeded_rng(SWEEP_SEED + round as u64);
+        let (width, height) = (SCREEN_WIDTH as f64, SCREEN_HEIGHT as f64);
+        let mut scene = Scene::new();
+        for segment in random_scene(&mut rng, size, width, height, MAX_SEGMENT_LENGTH) {
+            scene.push(segment, Default::default());
+        }
+        let dynamic: Vec<_> = (0 .. size).step_by(DYNAMIC_EVERY).collect();
+        for &index in dynamic.iter() {
+            scene.set_dynamic(index, true);
+        }
+        let probes = random_scene(&mut rng, SWEEP_QUERIES, width, height, MAX_SEGMENT_LENGTH);
+        let mut cutter = PointsCutter::with_strategy(strategy);
+
+        let move_dynamic = |scene: &mut Scene, rng: &mut XorShiftRng| for &index in dynamic.iter() {
+            let shift = Point { x: rng.gen_range(-2., 2.), y: rng.gen_range(-2., 2.), };
+            let segment = &mut scene.obstacles[index];
+            segment.src = Point { x: segment.src.x + shift.x, y: segment.src.y + shift.y, };
+            segment.dst = Point { x: segment.dst.x + shift.x, y: segment.dst.y + shift.y, };
+        };
+
+        let (mut full_build, mut full_query) = (Duration::default(), Duration::default());
+        for _ in 0 .. DYNAMIC_FRAMES {
+            move_dynamic(&mut scene, &mut rng);
+            let build_start = Instant::now();
+            let tree = SceneTree::build(kind, &scene.obstacles, &mut cutter, cut_limit, false)?;
+            full_build += build_start.elapsed();
+            let query_start = Instant::now();
+            for probe in probes.iter() {
+                tree.intersects(probe, &mut cutter)?;
+            }
+            full_query += query_start.elapsed();
+        }
+
+        let mut split = SplitTree::build(kind, &scene, strategy, cut_limit)?;
+        let (mut split_build, mut split_query) = (Duration::default(), Duration::default());
+        for _ in 0 .. DYNAMIC_FRAMES {
+            move_dynamic(&mut scene, &mut rng);
+            let build_start = Instant::now();
+            split.rebuild_dynamic(&scene.obstacles)?;
+            split_build += build_start.elapsed();
+            let query_start = Instant::now();
+            for probe in probes.iter() {
+                split.intersects(probe, &mut cutter)?;
+            }
+            split_query += query_start.elapsed();
+        }
+
+        println!(
+            "{:>8} | {:>8} | {:>16.3} | {:>16.3}",
+            size,
+            dynamic.len(),
+            millis(full_build + full_query) / DYNAMIC_FRAMES as f64,
+            millis(split_build + split_query) / DYNAMIC_FRAMES as f64,
+        );
+        let frames_queries = (DYNAMIC_FRAMES * SWEEP_QUERIES) as f64;
+        timings.push(Timing {
+            size,
+            strategy: "full",
+            build_ms: millis(full_build) / DYNAMIC_FRAMES as f64,
+            query_us: millis(full_query) * 1000. / frames_queries,
+        });
+        timings.push(Timing {
+            size,
+            strategy: "split",
+            build_ms: millis(split_build) / DYNAMIC_FRAMES as f64,
+            query_us: millis(split_query) * 1000. / frames_queries,
+        });
+    }
+    Ok(timings)
+}
+
+/// Builds trees over the same random scene with every cut strategy and compares how many nodes
+/// and fragments a collision query has to look at, fewer means a better tree for the queries.
+pub fn run_cutters(kind: VolumeKind, cut_limit: f64) -> Result<Vec<Timing>, Error> {
+    let mut timings = Vec::new();
+    let mut rng = seeded_rng(SWEEP_SEED);
+    let (width, height) = (SCREEN_WIDTH as f64, SCREEN_HEIGHT as f64);
+    let obstacles = random_scene(&mut rng, CUTTERS_SIZE, width, height, MAX_SEGMENT_LENGTH);
+    let probes = random_scene(&mut rng, SWEEP_QUERIES, width, height, MAX_SEGMENT_LENGTH);
+    println!(
+        "{:>8} | {:>10} | {:>8} | {:>6} | {:>14} | {:>16} | {:>12}",
+        "strategy", "build ms", "nodes", "depth", "comparisons", "fragment hits", "intersect us",
+    );
+    let strategies = [("mean", CutStrategy::Mean), ("median", CutStrategy::Median), ("sah", CutStrategy::Sah)];
+    for &(name, strategy) in strategies.iter() {
+        let build_start = Instant::now();
+        let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::with_strategy(strategy), cut_limit, false)?;
+        let build_time = build_start.elapsed();
+        let nodes = tree.nodes();
+        let stats = tree_stats(&nodes);
+
+        let mut cutter = PointsCutter::default();
+        let (mut comparisons, mut hits) = (0, 0);
+        let intersect_start = Instant::now();
+        for probe in probes.iter() {
+            let mut stats = QueryStats::default();
+            hits += tree.intersects_counted(probe, &mut cutter, &mut stats)?.len();
+            comparisons += stats.comparisons;
+        }
+        let intersect_time = intersect_start.elapsed();
+
+        let query_us = millis(intersect_time) * 1000. / SWEEP_QUERIES as f64;
+        println!(
+            "{:>8} | {:>10.3} | {:>8} | {:>6} | {:>14.1} | {:>16.1} | {:>12.3}",
+            name,
+            millis(build_time),
+            stats.nodes,
+            stats.depth,
+            comparisons as f64 / SWEEP_QUERIES as f64,
+            hits as f64 / SWEEP_QUERIES as f64,
+            query_us,
+        );
+        timings.push(Timing { size: CUTTERS_SIZE, strategy: name, build_ms: millis(build_time), query_us, });
+    }
+    Ok(timings)
+}
+
+/// Builds the tree over every obstacle of `scene` with capsules inflated, the way the window does.
+fn build_scene_tree(scene: &Scene, kind: VolumeKind, strategy: CutStrategy, cut_limit: f64) -> Result<SceneTree, Error> {
+    let shapes: Vec<_> = (0 .. scene.obstacles.len()).collect();
+    let (bounds, shape_of) = (scene.bounds(false), |i: usize| scene.meta[i].indexed_shape());
+    let mut cutter = PointsCutter::with_strategy(strategy);
+    match kind {
+        VolumeKind::Aabb =>
+            SceneTree::build_aabb(&scene.obstacles, &bounds, shape_of, &shapes, &mut cutter, cut_limit),
+        VolumeKind::Circle =>
+            SceneTree::build_circle(&scene.obstacles, &bounds, shape_of, &shapes, &mut cutter, cut_limit),
+    }
+}
+
+/// Builds the tree over `scene` the way the window does and times `HEADLESS_QUERIES` random collide
+/// and nearest queries within the `width` x `height` area, so the tree can be measured without a display.
+pub fn run_headless(
+    scene: &Scene,
+    kind: VolumeKind,
+    strategy: CutStrategy,
+    cut_limit: f64,
+    seed: u64,
+    (width, height): (u32, u32),
+)
+    -> Result<Vec<Timing>, Error>
+{
+    let build_start = Instant::now();
+    let tree = build_scene_tree(scene, kind, strategy, cut_limit)?;
+    let build_time = build_start.elapsed();
+    let stats = tree_stats(&tree.nodes());
+    println!(
+        "obstacles: {} | build: {:.3} ms | nodes: {} | depth: {}",
+        scene.obstacles.len(), millis(build_time), stats.nodes, stats.depth,
+    );
+
+    let probes = random_scene(&mut seeded_rng(seed), HEADLESS_QUERIES, width as f64, height as f64, MAX_SEGMENT_LENGTH);
+    let mut cutter = PointsCutter::default();
+    let (mut intersections, mut collided) = (0, 0);
+    let intersect_start = Instant::now();
+    for probe in probes.iter() {
+        let collisions = scene_collisions(&tree, scene, probe, &mut cutter, &mut QueryStats::default())?;
+        intersections += collisions.len();
+        let mut shapes: Vec<_> = collisions.iter().map(|collision| collision.shape).collect();
+        shapes.sort();
+        shapes.dedup();
+        collided += shapes.len();
+    }
+    let intersect_time = intersect_start.elapsed();
+
+    let nearest_start = Instant::now();
+    for probe in probes.iter() {
+        for neighbour in tree.nearest(probe).take(SWEEP_NEAREST) {
+            neighbour?;
+        }
+    }
+    let nearest_time = nearest_start.elapsed();
+
+    let intersect_us = millis(intersect_time) * 1000. / HEADLESS_QUERIES as f64;
+    let nearest_us = millis(nearest_time) * 1000. / HEADLESS_QUERIES as f64;
+    println!(
+        "intersect: {} queries | {:.3} us per query | {} intersections, {} obstacles hit in total",
+        HEADLESS_QUERIES, intersect_us, intersections, collided,
+    );
+    println!("nearest: {} queries | {:.3} us per query for the first {}", HEADLESS_QUERIES, nearest_us, SWEEP_NEAREST);
+    Ok(vec![
+        Timing { size: scene.obstacles.len(), strategy: "intersect", build_ms: millis(build_time), query_us: intersect_us, },
+        Timing { size: scene.obstacles.len(), strategy: "nearest", build_ms: millis(build_time), query_us: nearest_us, },
+    ])
+}
+
+/// Repeats the `run_headless` collide queries checking every obstacle directly, prints each obstacle
+/// touched by a probe but missing from the tree results and returns their count. Tree results also
+/// include obstacles only the fragment boxes reach, those are counted as loose hits and are not errors.
+pub fn verify(scene: &Scene, kind: VolumeKind, strategy: CutStrategy, cut_limit: f64, seed: u64, (width, height): (u32, u32)) -> Result<usize, Error> {
+    let tree = build_scene_tree(scene, kind, strategy, cut_limit)?;
+    let probes = random_scene(&mut seeded_rng(seed), HEADLESS_QUERIES, width as f64, height as f64, MAX_SEGMENT_LENGTH);
+    let mut cutter = PointsCutter::default();
+    let (mut missed, mut loose) = (0, 0);
+    for probe in probes.iter() {
+        let mut found: Vec<_> = scene_collisions(&tree, scene, probe, &mut cutter, &mut QueryStats::default())?.iter().map(|collision| collision.shape).collect();
+        found.sort();
+        found.dedup();
+        for (index, (obstacle, meta)) in scene.obstacles.iter().zip(scene.meta.iter()).enumerate() {
+            match (scene.hit_dist(index, probe).is_some(), found.binary_search(&index).is_ok()) {
+                (true, false) => {
+                    println!("MISSED obstacle {} {:?} {:?} by probe {:?}", index, meta.shape, obstacle, probe);
+                    missed += 1;
+                },
+                (false, true) =>
+                    loose += 1,
+                _ =>
+                    (),
+            }
+        }
+    }
+    println!("verify: {} queries | {} missed | {} loose hits", HEADLESS_QUERIES, missed, loose);
+    Ok(missed)
+}
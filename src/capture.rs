@@ -0,0 +1,91 @@
+use std::{io, fs};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use gl;
+use image;
+use piston_window::{PistonWindow, Window, OpenGLWindow};
+
+use super::PistonError;
+
+const MAX_RECORDED_FRAMES: usize = 3000;
+
+/// Loads OpenGL entry points used for framebuffer readback.
+pub fn init(window: &mut PistonWindow) {
+    gl::load_with(|s| window.window.get_proc_address(s) as *const _);
+}
+
+/// Screenshot file name in the working directory stamped with the current time.
+pub fn screenshot_path() -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    PathBuf::from(format!("screenshot-{}-{:03}.png", now.as_secs(), now.subsec_millis()))
+}
+
+/// Writes the frame just drawn (still in the back buffer) to a PNG file.
+pub fn save_screenshot<P>(window: &mut PistonWindow, path: P) -> Result<(), PistonError> where P: AsRef<Path> {
+    let size = window.draw_size();
+    let (width, height) = (size.width as usize, size.height as usize);
+    let mut pixels = vec![0u8; width * height * 4];
+    unsafe {
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, 0);
+        gl::ReadBuffer(gl::BACK);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as gl::types::GLsizei,
+            height as gl::types::GLsizei,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut _,
+        );
+    }
+    // opengl rows go bottom to top
+    let row = width * 4;
+    let flipped: Vec<u8> = pixels.chunks(row).rev().flat_map(|line| line.iter().cloned()).collect();
+    image::save_buffer(path.as_ref(), &flipped, width as u32, height as u32, image::RGBA(8))
+        .map_err(|e| PistonError::SaveScreenshot { file: path.as_ref().to_string_lossy().to_string(), error: e, })
+}
+
+/// Dumps numbered frames into a directory while recording is toggled on.
+pub struct FrameRecorder {
+    dir: PathBuf,
+    recording: bool,
+    frames: usize,
+}
+
+impl FrameRecorder {
+    pub fn new(dir: PathBuf) -> Result<FrameRecorder, io::Error> {
+        fs::create_dir_all(&dir)?;
+        Ok(FrameRecorder { dir, recording: false, frames: 0, })
+    }
+
+    pub fn toggle(&mut self) {
+        if self.recording {
+            info!("frames recording paused after {} frames", self.frames);
+            self.recording = false;
+        } else if self.frames >= MAX_RECORDED_FRAMES {
+            warn!("frames recording limit of {} frames is reached", MAX_RECORDED_FRAMES);
+        } else {
+            warn!("recording frames into {:?}: every frame is a full size PNG, mind the disk usage", self.dir);
+            self.recording = true;
+        }
+    }
+
+    pub fn capture(&mut self, window: &mut PistonWindow) {
+        if !self.recording {
+            return;
+        }
+        let path = self.dir.join(format!("frame_{:05}.png", self.frames));
+        if let Err(e) = save_screenshot(window, &path) {
+            error!("failed to save frame {:?}: {:?}, recording stopped", path, e);
+            self.recording = false;
+            return;
+        }
+        self.frames += 1;
+        if self.frames >= MAX_RECORDED_FRAMES {
+            warn!("frames recording limit of {} frames is reached, recording stopped", MAX_RECORDED_FRAMES);
+            self.recording = false;
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::f64::consts::{FRAC_1_SQRT_2, SQRT_2};
+
+use kdvtree;
+
+use super::{Axis, Point, Segment};
+
+/// Bounding circle of a segment fragment: the circle built on the fragment as a diameter.
+#[derive(Clone, Debug)]
+pub struct BoundingCircle {
+    pub center: Point,
+    pub radius: f64,
+}
+
+impl kdvtree::BoundingVolume<Point> for BoundingCircle {
+    fn min_corner(&self) -> Point {
+        Point { x: self.center.x - self.radius, y: self.center.y - self.radius, }
+    }
+
+    fn max_corner(&self) -> Point {
+        Point { x: self.center.x + self.radius, y: self.center.y + self.radius, }
+    }
+}
+
+fn circle_over(a: Point, b: Point) -> BoundingCircle {
+    BoundingCircle {
+        center: Point { x: (a.x + b.x) / 2., y: (a.y + b.y) / 2., },
+        radius: points_dist(&a, &b) / 2.,
+    }
+}
+
+fn points_dist(a: &Point, b: &Point) -> f64 {
+    (b.x - a.x).hypot(b.y - a.y)
+}
+
+pub fn get_bounding_circle(shape: &Segment) -> BoundingCircle {
+    circle_over(shape.src, shape.dst)
+}
+
+/// Circle obstacle given by a segment from its center to the rim is its own bounding circle.
+pub fn get_circle_obstacle_circle(shape: &Segment) -> BoundingCircle {
+    BoundingCircle { center: shape.src, radius: points_dist(&shape.src, &shape.dst), }
+}
+
+/// Same as `cut_segment_fragment` for circle fragments, fails for a shape with non finite coordinates.
+pub fn cut_segment_circle(
+    shape: &Segment,
+    fragment: &BoundingCircle,
+    cut_axis: &Axis,
+    cut_point: &Point,
+    cut_limit: f64,
+)
+    -> Result<Option<(BoundingCircle, BoundingCircle)>, ()>
+{
+    if !shape.is_finite() {
+        return Err(());
+    }
+    let length = points_dist(&shape.src, &shape.dst);
+    if length == 0. || fragment.radius * 2. < cut_limit {
+        return Ok(None);
+    }
+    // fragment circle is always centered on the shape, so its diameter is the fragment itself
+    let dir_x = (shape.dst.x - shape.src.x) / length;
+    let dir_y = (shape.dst.y - shape.src.y) / length;
+    let a = Point { x: fragment.center.x - dir_x * fragment.radius, y: fragment.center.y - dir_y * fragment.radius, };
+    let b = Point { x: fragment.center.x + dir_x * fragment.radius, y: fragment.center.y + dir_y * fragment.radius, };
+    let (coord_a, coord_b, coord_cut) = match *cut_axis {
+        Axis::X => (a.x, b.x, cut_point.x),
+        Axis::Y => (a.y, b.y, cut_point.y),
+    };
+    let (coord_min, coord_max) = if coord_a < coord_b { (coord_a, coord_b) } else { (coord_b, coord_a) };
+    if coord_cut <= coord_min || coord_cut >= coord_max {
+        return Ok(None);
+    }
+    let factor = (coord_cut - coord_a) / (coord_b - coord_a);
+    let mid = Point { x: a.x + factor * (b.x - a.x), y: a.y + factor * (b.y - a.y), };
+    if !(mid.x.is_finite() && mid.y.is_finite()) {
+        trace!("non finite interpolation cutting {:?}, keeping the fragment whole", shape);
+        return Ok(None);
+    }
+    let (near, far) = if coord_a < coord_b { (a, b) } else { (b, a) };
+    Ok(Some((circle_over(near, mid), circle_over(mid, far))))
+}
+
+pub fn circle_to_cut_point_dist(axis: &Axis, bounding_volume: &BoundingCircle, cut_point: &Point) -> f64 {
+    let (center, cut) = match *axis {
+        Axis::X => (bounding_volume.center.x, cut_point.x),
+        Axis::Y => (bounding_volume.center.y, cut_point.y),
+    };
+    let dist = (center - cut).abs() - bounding_volume.radius;
+    if dist < 0. { 0. } else { dist }
+}
+
+pub fn circle_to_circle_dist(bv_a: &BoundingCircle, bv_b: &BoundingCircle) -> f64 {
+    let dist = points_dist(&bv_a.center, &bv_b.center) - bv_a.radius - bv_b.radius;
+    if dist < 0. { 0. } else { dist }
+}
+
+/// Same as `circle_to_circle_dist` in the Manhattan metric: the shortest way out of the circles sum is
+/// along the diagonal unless the centers are nearly aligned with an axis.
+pub fn circle_to_circle_manhattan_dist(bv_a: &BoundingCircle, bv_b: &BoundingCircle) -> f64 {
+    let radius = bv_a.radius + bv_b.radius;
+    if points_dist(&bv_a.center, &bv_b.center) <= radius {
+        return 0.;
+    }
+    let (dx, dy) = ((bv_b.center.x - bv_a.center.x).abs(), (bv_b.center.y - bv_a.center.y).abs());
+    let (major, minor) = if dx >= dy { (dx, dy) } else { (dy, dx) };
+    if minor >= radius * FRAC_1_SQRT_2 {
+        major + minor - radius * SQRT_2
+    } else {
+        major - (radius * radius - minor * minor).sqrt()
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::PathBuf;
+
+use super::Business;
+
+/// Commands understood by the in-window console.
+pub enum Command {
+    Clear,
+    /// Saves the scene to the given file or to the `--scene` one when omitted.
+    Save(Option<PathBuf>),
+    Mode(Business),
+    /// Limits neighbours mode to the given count of nearest obstacles, `None` shows them all.
+    Nearest(Option<usize>),
+    /// Puts newly constructed obstacles into the given group, numbered from 1.
+    Group(usize),
+    /// Capsule radius of new segment obstacles, zero for plain segments.
+    Width(f64),
+    /// Restricts neighbours mode results to the given group, `None` reports every group.
+    Only(Option<usize>),
+}
+
+pub fn parse(line: &str) -> Result<Command, String> {
+    let mut words = line.split_whitespace();
+    let command = match (words.next(), words.next()) {
+        (Some("clear"), None) =>
+            Command::Clear,
+        (Some("save"), path) =>
+            Command::Save(path.map(PathBuf::from)),
+        (Some("mode"), Some(mode)) =>
+            Command::Mode(mode.parse().map_err(|()| format!("unknown mode {:?}", mode))?),
+        (Some("k"), Some("all")) =>
+            Command::Nearest(None),
+        (Some("k"), Some(count)) =>
+            match count.parse() {
+                Ok(0) | Err(..) =>
+                    return Err(format!("expected a positive count or \"all\", got {:?}", count)),
+                Ok(count) =>
+                    Command::Nearest(Some(count)),
+            },
+        (Some("group"), Some(group)) =>
+            Command::Group(group.parse().map_err(|_| format!("expected a group number, got {:?}", group))?),
+        (Some("width"), Some(width)) =>
+            match width.parse::<f64>() {
+                Ok(width) if width.is_finite() && width >= 0. =>
+                    Command::Width(width),
+                _ =>
+                    return Err(format!("expected a non negative radius, got {:?}", width)),
+            },
+        (Some("only"), Some("all")) =>
+            Command::Only(None),
+        (Some("only"), Some(group)) =>
+            Command::Only(Some(group.parse().map_err(|_| format!("expected a group number or \"all\", got {:?}", group))?)),
+        (Some(word), _) =>
+            return Err(format!("unknown command {:?}, try clear, save [FILE], mode MODE, k N|all, group N, width R or only N|all", word)),
+        (None, _) =>
+            return Err("empty command".to_string()),
+    };
+    if let Some(extra) = words.next() {
+        return Err(format!("unexpected argument {:?}", extra));
+    }
+    Ok(command)
+}
+
+/// Command line state while the console is open.
+pub struct Console {
+    pub input: String,
+    pub status: Option<String>,
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console { input: String::new(), status: None, }
+    }
+
+    pub fn type_text(&mut self, text: &str) {
+        // the toggle key itself arrives as text as well
+        self.input.extend(text.chars().filter(|&c| c != '`' && !c.is_control()));
+    }
+
+    pub fn backspace(&mut self) {
+        self.input.pop();
+    }
+
+    /// Takes the typed line away and parses it, keeping the error message on failure.
+    pub fn submit(&mut self) -> Option<Command> {
+        let line = self.input.split_off(0);
+        match parse(&line) {
+            Ok(command) => {
+                self.status = None;
+                Some(command)
+            },
+            Err(message) => {
+                self.status = Some(message);
+                None
+            },
+        }
+    }
+}
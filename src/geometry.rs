@@ -0,0 +1,261 @@
+use std::cmp::Ordering;
+
+use kdvtree;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Segment {
+    pub src: Point,
+    pub dst: Point,
+}
+
+impl Segment {
+    pub fn length(&self) -> f64 {
+        (self.dst.x - self.src.x).hypot(self.dst.y - self.src.y)
+    }
+
+    pub fn midpoint(&self) -> Point {
+        Point { x: (self.src.x + self.dst.x) / 2., y: (self.src.y + self.dst.y) / 2., }
+    }
+
+    /// Unit vector from `src` to `dst`, `None` for a zero length segment which has no direction.
+    pub fn direction(&self) -> Option<Point> {
+        let length = self.length();
+        if length > 0. && length.is_finite() {
+            Some(Point { x: (self.dst.x - self.src.x) / length, y: (self.dst.y - self.src.y) / length, })
+        } else {
+            None
+        }
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.src.x.is_finite() && self.src.y.is_finite() && self.dst.x.is_finite() && self.dst.y.is_finite()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Axis { X, Y, }
+
+#[derive(Clone, Debug)]
+pub struct Bound {
+    pub lt: Point,
+    pub rb: Point,
+}
+
+impl kdvtree::BoundingVolume<Point> for Bound {
+    fn min_corner(&self) -> Point { self.lt }
+    fn max_corner(&self) -> Point { self.rb }
+}
+
+pub fn merge_bounds(a: &Bound, b: &Bound) -> Bound {
+    Bound {
+        lt: Point { x: a.lt.x.min(b.lt.x), y: a.lt.y.min(b.lt.y), },
+        rb: Point { x: a.rb.x.max(b.rb.x), y: a.rb.y.max(b.rb.y), },
+    }
+}
+
+/// Grows the bound by `radius` on every side.
+pub fn inflate_bound(bound: &Bound, radius: f64) -> Bound {
+    Bound {
+        lt: Point { x: bound.lt.x - radius, y: bound.lt.y - radius, },
+        rb: Point { x: bound.rb.x + radius, y: bound.rb.y + radius, },
+    }
+}
+
+pub fn cmp_points(axis: &Axis, a: &Point, b: &Point) -> Ordering {
+    match axis {
+        &Axis::X =>
+            if a.x < b.x { Ordering::Less } else if a.x > b.x { Ordering::Greater } else { Ordering::Equal },
+        &Axis::Y =>
+            if a.y < b.y { Ordering::Less } else if a.y > b.y { Ordering::Greater } else { Ordering::Equal },
+    }
+}
+
+pub fn get_bounding_volume(shape: &Segment) -> Bound {
+    Bound {
+        lt: Point {
+            x: if shape.src.x < shape.dst.x { shape.src.x } else { shape.dst.x },
+            y: if shape.src.y < shape.dst.y { shape.src.y } else { shape.dst.y },
+        },
+        rb: Point {
+            x: if shape.src.x > shape.dst.x { shape.src.x } else { shape.dst.x },
+            y: if shape.src.y > shape.dst.y { shape.src.y } else { shape.dst.y },
+        },
+    }
+}
+
+/// Splits the `shape` fragment at the cut plane into the bounds of its two parts, fragments narrower
+/// than `cut_limit` along the cut axis are kept whole. Fails for a shape with non finite coordinates.
+pub fn cut_segment_fragment(shape: &Segment, fragment: &Bound, cut_axis: &Axis, cut_point: &Point, cut_limit: f64) -> Result<Option<(Bound, Bound)>, ()> {
+    if !shape.is_finite() {
+        return Err(());
+    }
+    match cut_axis {
+        &Axis::X => if cut_point.x >= fragment.lt.x && cut_point.x <= fragment.rb.x {
+            if fragment.rb.x - fragment.lt.x < cut_limit {
+                Ok(None)
+            } else if shape.dst.x == shape.src.x {
+                // vertical segment lies on the cut plane itself, there is nothing to split
+                Ok(None)
+            } else {
+                let factor = (cut_point.x - shape.src.x) / (shape.dst.x - shape.src.x);
+                let y = shape.src.y + (factor * (shape.dst.y - shape.src.y));
+                if !y.is_finite() {
+                    trace!("non finite interpolation cutting {:?} at x={}, splitting the bound as is", shape, cut_point.x);
+                    return Ok(Some(split_bound(fragment, cut_axis, cut_point)));
+                }
+                // precision loss may push the interpolated point slightly out of the fragment
+                let y = y.clamp(fragment.lt.y, fragment.rb.y);
+                let left_point = if shape.src.x < shape.dst.x { shape.src } else { shape.dst };
+                let left_bound = Bound {
+                    lt: Point {
+                        x: fragment.lt.x,
+                        y: if left_point.y < y { fragment.lt.y } else { y },
+                    },
+                    rb: Point {
+                        x: cut_point.x,
+                        y: if left_point.y < y { y } else { fragment.rb.y },
+                    }
+                };
+                let right_point = if shape.src.x < shape.dst.x { shape.dst } else { shape.src };
+                let right_bound = Bound {
+                    lt: Point {
+                        x: cut_point.x,
+                        y: if right_point.y < y { fragment.lt.y } else { y },
+                    },
+                    rb: Point {
+                        x: fragment.rb.x,
+                        y: if right_point.y < y { y } else { fragment.rb.y },
+                    },
+                };
+                Ok(Some((left_bound, right_bound)))
+            }
+        } else {
+            return Ok(None);
+        },
+        &Axis::Y => if cut_point.y >= fragment.lt.y && cut_point.y <= fragment.rb.y {
+            if fragment.rb.y - fragment.lt.y < cut_limit {
+                Ok(None)
+            } else if shape.dst.y == shape.src.y {
+                // horizontal segment lies on the cut plane itself, there is nothing to split
+                Ok(None)
+            } else {
+                let factor = (cut_point.y - shape.src.y) / (shape.dst.y - shape.src.y);
+                let x = shape.src.x + (factor * (shape.dst.x - shape.src.x));
+                if !x.is_finite() {
+                    trace!("non finite interpolation cutting {:?} at y={}, splitting the bound as is", shape, cut_point.y);
+                    return Ok(Some(split_bound(fragment, cut_axis, cut_point)));
+                }
+                let x = x.clamp(fragment.lt.x, fragment.rb.x);
+                let upper_point = if shape.src.y < shape.dst.y { shape.src } else { shape.dst };
+                let upper_bound = Bound {
+                    lt: Point {
+                        x: if upper_point.x < x { fragment.lt.x } else { x },
+                        y: fragment.lt.y,
+                    },
+                    rb: Point {
+                        x: if upper_point.x < x { x } else { fragment.rb.x },
+                        y: cut_point.y,
+                    }
+                };
+                let lower_point = if shape.src.y < shape.dst.y { shape.dst } else { shape.src };
+                let lower_bound = Bound {
+                    lt: Point {
+                        x: if lower_point.x < x { fragment.lt.x } else { x },
+                        y: cut_point.y,
+                        },
+                    rb: Point {
+                        x: if lower_point.x < x { x } else { fragment.rb.x },
+                        y: fragment.rb.y,
+                    },
+                };
+                Ok(Some((upper_bound, lower_bound)))
+            }
+        } else {
+            return Ok(None);
+        },
+    }
+}
+
+/// Splits `fragment` along the cut line keeping its full extent on the other axis.
+/// Rectangle fills its whole bound, so its fragments are just split at the cut plane.
+pub fn cut_rect_fragment(fragment: &Bound, cut_axis: &Axis, cut_point: &Point, cut_limit: f64) -> Result<Option<(Bound, Bound)>, ()> {
+    let (min, max, cut) = match *cut_axis {
+        Axis::X => (fragment.lt.x, fragment.rb.x, cut_point.x),
+        Axis::Y => (fragment.lt.y, fragment.rb.y, cut_point.y),
+    };
+    if cut <= min || cut >= max || max - min < cut_limit {
+        Ok(None)
+    } else {
+        Ok(Some(split_bound(fragment, cut_axis, cut_point)))
+    }
+}
+
+fn split_bound(fragment: &Bound, cut_axis: &Axis, cut_point: &Point) -> (Bound, Bound) {
+    match *cut_axis {
+        Axis::X => (
+            Bound { lt: fragment.lt, rb: Point { x: cut_point.x, y: fragment.rb.y, }, },
+            Bound { lt: Point { x: cut_point.x, y: fragment.lt.y, }, rb: fragment.rb, },
+        ),
+        Axis::Y => (
+            Bound { lt: fragment.lt, rb: Point { x: fragment.rb.x, y: cut_point.y, }, },
+            Bound { lt: Point { x: fragment.lt.x, y: cut_point.y, }, rb: fragment.rb, },
+        ),
+    }
+}
+
+/// Distance along `axis` from the cut to the nearer face of the bound, zero when the cut passes through it.
+pub fn bound_to_cut_point_dist(axis: &Axis, bounding_volume: &Bound, cut_point: &Point) -> f64 {
+    let (l, r, cut) = match axis {
+        &Axis::X => (bounding_volume.lt.x, bounding_volume.rb.x, cut_point.x),
+        &Axis::Y => (bounding_volume.lt.y, bounding_volume.rb.y, cut_point.y),
+    };
+    if cut < l {
+        l - cut
+    } else if cut > r {
+        cut - r
+    } else {
+        0.
+    }
+}
+
+pub fn bound_to_bound_dist(bv_a: &Bound, bv_b: &Bound) -> f64 {
+    fn dist(xa: f64, ya: f64, xb: f64, yb: f64) -> f64 {
+        // hypot does not overflow on squaring huge coordinate differences
+        (xb - xa).hypot(yb - ya)
+    }
+    let left = bv_b.rb.x < bv_a.lt.x;
+    let right = bv_a.rb.x < bv_b.lt.x;
+    let top = bv_a.rb.y < bv_b.lt.y;
+    let bottom = bv_b.rb.y < bv_a.lt.y;
+    if top && left {
+        dist(bv_a.lt.x, bv_a.rb.y, bv_b.rb.x, bv_b.lt.y)
+    } else if left && bottom {
+        dist(bv_a.lt.x, bv_a.lt.y, bv_b.rb.x, bv_b.rb.y)
+    } else if bottom && right {
+        dist(bv_a.rb.x, bv_a.lt.y, bv_b.lt.x, bv_b.rb.y)
+    } else if right && top {
+        dist(bv_a.rb.x, bv_a.rb.y, bv_b.lt.x, bv_b.lt.y)
+    } else if left {
+        bv_a.lt.x - bv_b.rb.x
+    } else if right {
+        bv_b.lt.x - bv_a.rb.x
+    } else if bottom {
+        bv_a.lt.y - bv_b.rb.y
+    } else if top {
+        bv_b.lt.y - bv_a.rb.y
+    } else {
+        0.
+    }
+}
+
+/// Same as `bound_to_bound_dist` in the Manhattan metric, where the gaps along both axes add up.
+pub fn bound_to_bound_manhattan_dist(bv_a: &Bound, bv_b: &Bound) -> f64 {
+    let gap = |a_lo: f64, a_hi: f64, b_lo: f64, b_hi: f64| (b_lo - a_hi).max(a_lo - b_hi).max(0.);
+    gap(bv_a.lt.x, bv_a.rb.x, bv_b.lt.x, bv_b.rb.x) + gap(bv_a.lt.y, bv_a.rb.y, bv_b.lt.y, bv_b.rb.y)
+}
@@ -0,0 +1,167 @@
+use std::{fs, io};
+use std::io::Write;
+use std::path::Path;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use piston_window::{Event, Input, Button, ButtonArgs, ButtonState, MouseButton, Motion, Key};
+
+/// Keys worth replaying: switching mode, clearing the scene and quitting.
+const LOGGED_KEYS: &[(Key, &str)] = &[(Key::M, "M"), (Key::C, "C"), (Key::Q, "Q")];
+
+#[derive(Debug)]
+pub enum Error {
+    Read { file: String, error: io::Error, },
+    Write { file: String, error: io::Error, },
+    Parse { line: usize, message: &'static str, },
+}
+
+/// The part of the window input the demo reacts to which is recorded and replayed.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputEvent {
+    Cursor(f64, f64),
+    LeftPress,
+    LeftRelease,
+    /// Release of one of the `LOGGED_KEYS`.
+    Key(Key),
+}
+
+impl InputEvent {
+    pub fn from_event(event: &Event) -> Option<InputEvent> {
+        match *event {
+            Event::Input(Input::Move(Motion::MouseCursor(x, y))) =>
+                Some(InputEvent::Cursor(x, y)),
+            Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Left), state: ButtonState::Press, .. })) =>
+                Some(InputEvent::LeftPress),
+            Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Left), state: ButtonState::Release, .. })) =>
+                Some(InputEvent::LeftRelease),
+            Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(key), state: ButtonState::Release, .. }))
+                if LOGGED_KEYS.iter().any(|&(logged, _)| logged == key) =>
+                Some(InputEvent::Key(key)),
+            _ =>
+                None,
+        }
+    }
+
+    pub fn to_event(self) -> Event {
+        let button = |button, state| Event::Input(Input::Button(ButtonArgs { state, button, scancode: None, }));
+        match self {
+            InputEvent::Cursor(x, y) =>
+                Event::Input(Input::Move(Motion::MouseCursor(x, y))),
+            InputEvent::LeftPress =>
+                button(Button::Mouse(MouseButton::Left), ButtonState::Press),
+            InputEvent::LeftRelease =>
+                button(Button::Mouse(MouseButton::Left), ButtonState::Release),
+            InputEvent::Key(key) =>
+                button(Button::Keyboard(key), ButtonState::Release),
+        }
+    }
+}
+
+/// Formats an event happened `at` since the recording start as a `<ms> <event> [args]` line.
+pub fn to_line(at: Duration, event: InputEvent) -> String {
+    let ms = at.as_millis();
+    match event {
+        InputEvent::Cursor(x, y) =>
+            format!("{} move {:?} {:?}", ms, x, y),
+        InputEvent::LeftPress =>
+            format!("{} press", ms),
+        InputEvent::LeftRelease =>
+            format!("{} release", ms),
+        InputEvent::Key(key) => {
+            let name = LOGGED_KEYS.iter().find(|&&(logged, _)| logged == key).map_or("?", |&(_, name)| name);
+            format!("{} key {}", ms, name)
+        },
+    }
+}
+
+pub fn parse_line(line: &str) -> Result<(Duration, InputEvent), &'static str> {
+    let mut words = line.split_whitespace();
+    let ms: u64 = words.next().and_then(|word| word.parse().ok()).ok_or("expected a time in milliseconds")?;
+    let event = match words.next() {
+        Some("move") => {
+            let mut coord = || words.next().and_then(|word| word.parse::<f64>().ok()).filter(|value| value.is_finite());
+            let x = coord().ok_or("expected cursor x")?;
+            let y = coord().ok_or("expected cursor y")?;
+            InputEvent::Cursor(x, y)
+        },
+        Some("press") =>
+            InputEvent::LeftPress,
+        Some("release") =>
+            InputEvent::LeftRelease,
+        Some("key") => {
+            let name = words.next().ok_or("expected a key name")?;
+            let &(key, _) = LOGGED_KEYS.iter().find(|&&(_, logged)| logged == name).ok_or("unknown key")?;
+            InputEvent::Key(key)
+        },
+        _ =>
+            return Err("unknown event"),
+    };
+    if words.next().is_some() {
+        return Err("unexpected trailing words");
+    }
+    Ok((Duration::from_millis(ms), event))
+}
+
+/// Appends the input events handled by the demo to a file as they happen.
+pub struct Recorder {
+    file: fs::File,
+    path: String,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create<P>(path: P) -> Result<Recorder, Error> where P: AsRef<Path> {
+        let path = path.as_ref().to_string_lossy().to_string();
+        let file = fs::File::create(&path).map_err(|e| Error::Write { file: path.clone(), error: e, })?;
+        Ok(Recorder { file, path, start: Instant::now(), })
+    }
+
+    /// Writes `event` down if it is one of the replayable ones.
+    pub fn record(&mut self, event: &Event) -> Result<(), Error> {
+        if let Some(input) = InputEvent::from_event(event) {
+            writeln!(self.file, "{}", to_line(self.start.elapsed(), input))
+                .map_err(|e| Error::Write { file: self.path.clone(), error: e, })?;
+        }
+        Ok(())
+    }
+}
+
+/// Recorded events waiting for their time to come, counted from the replay start.
+pub struct Replay {
+    events: VecDeque<(Duration, InputEvent)>,
+    start: Instant,
+}
+
+impl Replay {
+    /// Reads a recording skipping blank and `#` comment lines.
+    pub fn load<P>(path: P) -> Result<Replay, Error> where P: AsRef<Path> {
+        let text = fs::read_to_string(path.as_ref())
+            .map_err(|e| Error::Read { file: path.as_ref().to_string_lossy().to_string(), error: e, })?;
+        let events = text.lines()
+            .enumerate()
+            .filter(|&(_, line)| !line.trim().is_empty() && !line.trim().starts_with('#'))
+            .map(|(i, line)| parse_line(line).map_err(|message| Error::Parse { line: i + 1, message, }))
+            .collect::<Result<_, _>>()?;
+        Ok(Replay { events, start: Instant::now(), })
+    }
+
+    /// Starts counting event times from now.
+    pub fn restart(&mut self) {
+        self.start = Instant::now();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// Next recorded event when its time has come by `now`.
+    pub fn next_due(&mut self, now: Instant) -> Option<Event> {
+        match self.events.front() {
+            Some(&(at, _)) if now.duration_since(self.start) >= at =>
+                self.events.pop_front().map(|(_, event)| event.to_event()),
+            _ =>
+                None,
+        }
+    }
+}
@@ -1,23 +1,53 @@
+extern crate gl;
 extern crate rand;
+extern crate image;
+extern crate rayon;
 extern crate kdvtree;
 extern crate gfx_core;
 extern crate env_logger;
 extern crate piston_window;
+extern crate serde;
+extern crate serde_json;
+extern crate toml;
+#[macro_use] extern crate serde_derive;
 #[macro_use] extern crate log;
 #[macro_use] extern crate clap;
 
-use std::{io, iter, process};
-use std::path::PathBuf;
+use std::{f64, io, iter, mem, process, thread};
+use std::sync::mpsc;
+use std::path::{Path, PathBuf};
 use std::cmp::Ordering;
-use std::collections::HashSet;
+use std::cell::Cell;
+use std::convert::Infallible;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::collections::{BinaryHeap, HashMap, HashSet, VecDeque};
+
+use clap::{Arg, ArgGroup};
+use rayon::prelude::*;
+use geometry::{Point, Segment, Axis, Bound, merge_bounds, inflate_bound};
+use geometry::{cmp_points, get_bounding_volume, cut_segment_fragment, cut_rect_fragment, bound_to_cut_point_dist, bound_to_bound_dist, bound_to_bound_manhattan_dist};
+use theme::Theme;
+mod watch;
+mod circle;
+mod capture;
+mod input_log;
+mod bench;
+mod scene_file;
+mod console;
+mod selftest;
+mod svg;
+mod geometry;
+mod theme;
 
-use clap::Arg;
 use piston_window::{
     OpenGL,
     PistonWindow,
     WindowSettings,
+    Window,
     TextureSettings,
     Glyphs,
+    G2d,
     Event,
     Input,
     Button,
@@ -26,6 +56,7 @@ use piston_window::{
     MouseButton,
     Motion,
     Key,
+    Loop,
 };
 
 fn main() {
@@ -43,6 +74,16 @@ fn main() {
 #[derive(Debug)]
 enum Error {
     MissingParameter(&'static str),
+    InvalidParameter { name: &'static str, value: String, },
+    MissingAssets { dir: String, font: String, },
+    CreateFramesDir { dir: String, error: io::Error, },
+    WriteBenchCsv { file: String, error: io::Error, },
+    VerifyFailed { missed: usize, },
+    /// Obstacle or probe fragment could not be cut while running the tree `query`.
+    CutFragment { query: &'static str, },
+    SceneFile(scene_file::Error),
+    InputLog(input_log::Error),
+    Theme(theme::Error),
     Piston(PistonError),
 }
 
@@ -50,13 +91,102 @@ enum Error {
 enum PistonError {
     BuildWindow(String),
     LoadFont { file: String, error: io::Error, },
+    LoadEmbeddedFont,
     DrawText(gfx_core::factory::CombinedError),
+    SaveScreenshot { file: String, error: io::Error, },
 }
 
+const FONT_FILE: &str = "FiraSans-Regular.ttf";
+const EMBEDDED_FONT: &[u8] = include_bytes!("../assets/FiraSans-Regular.ttf");
 const KDTREE_CUT_LIMIT: f64 = 32.;
 const CONSOLE_HEIGHT: u32 = 32;
 const SCREEN_WIDTH: u32 = 640;
 const SCREEN_HEIGHT: u32 = 480;
+const MIN_SCREEN_SIDE: u32 = 200;
+const MAX_SCREEN_SIDE: u32 = 8192;
+const NARRATION_LINES: usize = 4;
+const NARRATION_INTERVAL_MS: u64 = 750;
+const NARRATION_TTL_MS: u64 = 5000;
+const NOTICE_TTL_MS: u64 = 3000;
+const ERROR_BANNER_TTL_MS: u64 = 5000;
+const DEFAULT_SCENE_FILE: &str = "scene.json";
+const SVG_EXPORT_FILE: &str = "scene.svg";
+const SCENE_HASH_QUANTUM: f64 = 1e-3;
+const SCALE_BAR_WIDTH: f64 = 120.;
+const POINT_EPSILON: f64 = 1e-6;
+const MIN_OBSTACLE_WEIGHT: f64 = 1. / 16.;
+const MAX_OBSTACLE_WEIGHT: f64 = 16.;
+const COLLISION_PULSE_MS: u64 = 400;
+const PROBE_GRID_MIN_SPACING: f64 = 8.;
+const GHOST_DASH_LENGTH: f64 = 8.;
+const EXPLAIN_LINES: usize = 8;
+const OBSTACLE_GROUPS: usize = 5;
+const HOVER_NEAREST_MAX_OBSTACLES: usize = 10_000;
+const NEAREST_REGION_CELL: f64 = 16.;
+const DISTANCE_FIELD_CELL: f64 = 16.;
+/// Obstacles spanning fewer pixels on screen are left without an index label.
+const LABEL_MIN_SIZE: f64 = 24.;
+/// Trees over at least this many obstacles are rebuilt on a background thread.
+const BACKGROUND_BUILD_MIN_OBSTACLES: usize = 2_000;
+/// Obstacles added since the last full build are kept in a separate small tree up to this count.
+const OVERFLOW_LIMIT: usize = 16;
+const GRAB_DISTANCE: f64 = 8.;
+const FRAME_TIME_SAMPLES: usize = 60;
+const ZOOM_STEP: f64 = 1.1;
+const MIN_ZOOM: f64 = 1. / 8.;
+const MAX_ZOOM: f64 = 8.;
+const SAH_CANDIDATES: usize = 8;
+
+/// Key bindings listed by the <H> help overlay, keep in sync with the events handling in `run`.
+const KEY_BINDINGS: &[(&str, &str)] = &[
+    ("H", "show or hide this help"),
+    ("M", "switch mode: construct, collide, neighbours, edit, range, ray"),
+    ("Left drag", "move a segment end in edit mode"),
+    ("Left click", "start or finish a segment"),
+    ("Shift + click", "finish a rectangle instead of a segment"),
+    ("Ctrl + click", "finish a circle around the first click instead"),
+    ("Alt", "hold to keep the new segment horizontal, vertical or at 45 degrees"),
+    ("W", "construct a polyline from clicks, <Enter> commits it"),
+    ("R", "replace the scene with --count random obstacles"),
+    ("T", "snap the cursor to the grid"),
+    ("K", "show or hide a grid spaced at the --cut-limit"),
+    ("F", "show or hide frames per second and frame time"),
+    ("O", "show or hide the cursor world coordinates"),
+    ("A", "highlight the tree leaf region under the cursor"),
+    ("D", "hold to highlight the obstacle nearest to the cursor, release to delete it"),
+    ("Middle drag", "pan the view"),
+    ("Scroll", "zoom the view around the cursor"),
+    ("Home", "reset pan and zoom"),
+    ("C", "clear the scene"),
+    ("Z / Y", "undo or redo the last constructed obstacle or end drag"),
+    ("Q / Esc", "exit"),
+    ("Space", "pin or unpin the collide probe"),
+    ("Up / Down", "scroll pinned collisions details"),
+    (", / .", "step through nearest results one at a time"),
+    ("+ / -", "show more or fewer nearest neighbours, fewer than one shows them all"),
+    ("[ / ]", "halve or double the weight of new obstacles"),
+    ("G", "sketch planned ghosts instead of obstacles"),
+    ("P", "promote ghosts into obstacles"),
+    ("S", "save the scene to the --scene file or ./scene.json"),
+    ("E", "export obstacles and cuts mesh to ./scene.svg"),
+    ("I", "save a screenshot of the next frame to a timestamped PNG"),
+    ("B", "show or hide the scale bar"),
+    ("L", "switch between light and dark background"),
+    ("`", "open or close the command console"),
+    ("F1 - F5", "show or hide obstacle groups 1 to 5"),
+    ("F6", "normalize neighbours gradient by the farthest result"),
+    ("F7", "color obstacles by length"),
+    ("F8", "show probe grid coverage"),
+    ("F9", "start or stop recording frames"),
+    ("F10", "outline fragments instead of filling them"),
+    ("F11", "show nearest obstacle to each window corner"),
+    ("F12", "color nearest obstacle regions"),
+    ("N", "show a heatmap of the distance to the nearest obstacle"),
+    ("X", "highlight obstacles crossing each other"),
+    ("J", "show or hide obstacle index labels"),
+    ("V", "show how many tree nodes the collide probe visits"),
+    ("U", "outline exact brute-force collisions of the collide probe"),
+];
 
 fn run() -> Result<(), Error> {
     let matches = app_from_crate!()
@@ -64,227 +194,1681 @@ fn run() -> Result<(), Error> {
              .short("a")
              .long("assets-dir")
              .value_name("DIR")
-             .help("Graphics resources directory")
+             .help("Graphics resources directory, the embedded font is used when the default one is missing")
              .default_value("./assets")
              .takes_value(true))
+        .arg(Arg::with_name("font")
+             .long("font")
+             .value_name("FILE")
+             .help("TrueType font file overriding the one from the assets directory")
+             .takes_value(true))
+        .arg(Arg::with_name("narrate")
+             .long("narrate")
+             .help("Annotate the current action with short explanatory sentences"))
+        .arg(Arg::with_name("mode")
+             .short("m")
+             .long("mode")
+             .value_name("MODE")
+             .help("Initial mode")
+             .possible_values(&["construct", "collide", "neighbours", "edit", "range", "ray"])
+             .default_value("construct")
+             .takes_value(true))
+        .arg(Arg::with_name("width")
+             .long("width")
+             .value_name("PIXELS")
+             .help("Initial window width, defaults to 640 and is clamped to 200 ..= 8192")
+             .takes_value(true))
+        .arg(Arg::with_name("height")
+             .long("height")
+             .value_name("PIXELS")
+             .help("Initial window height, defaults to 480 and is clamped to 200 ..= 8192")
+             .takes_value(true))
+        .arg(Arg::with_name("k")
+             .long("k")
+             .value_name("N")
+             .help("Show only N nearest obstacles in neighbours mode, adjustable with <+>/<->")
+             .takes_value(true))
+        .arg(Arg::with_name("bv")
+             .long("bv")
+             .value_name("KIND")
+             .help("Bounding volume used for tree fragments")
+             .possible_values(&["aabb", "circle"])
+             .default_value("aabb")
+             .takes_value(true))
+        .arg(Arg::with_name("metric")
+             .long("metric")
+             .value_name("METRIC")
+             .help("Distance the nearest obstacles are picked by")
+             .possible_values(&["euclidean", "manhattan"])
+             .default_value("euclidean")
+             .takes_value(true))
+        .arg(Arg::with_name("record-frames")
+             .long("record-frames")
+             .value_name("DIR")
+             .help("Directory to dump numbered PNG frames to while recording is toggled with <F9>")
+             .takes_value(true))
+        .arg(Arg::with_name("record")
+             .long("record")
+             .value_name("FILE")
+             .help("Write cursor moves, left clicks and the <M>, <C> and <Q> keys with their timing to a file for --replay")
+             .takes_value(true))
+        .arg(Arg::with_name("replay")
+             .long("replay")
+             .value_name("FILE")
+             .help("Feed input events written by --record into the window on their original timing, real ones are ignored meanwhile")
+             .takes_value(true))
+        .arg(Arg::with_name("probe-grid-spacing")
+             .long("probe-grid-spacing")
+             .value_name("PIXELS")
+             .help("Distance between probes of the coverage grid toggled with <F8>")
+             .default_value("40")
+             .takes_value(true))
+        .arg(Arg::with_name("cut-limit")
+             .long("cut-limit")
+             .value_name("PIXELS")
+             .help("Fragments narrower than this along the cut axis are not split any further, lower values make finer trees")
+             .default_value("32")
+             .takes_value(true))
+        .arg(Arg::with_name("cut-strategy")
+             .long("cut-strategy")
+             .value_name("STRATEGY")
+             .help("How the tree cut point is picked out of the fragment points, median balances clustered scenes better \
+                    and sah weighs a few positions by the fragments perimeter on each side")
+             .possible_values(&["mean", "median", "sah"])
+             .default_value("mean")
+             .takes_value(true))
+        .arg(Arg::with_name("snap-size")
+             .long("snap-size")
+             .value_name("PIXELS")
+             .help("Grid step the cursor is snapped to while snapping is toggled with <T>, defaults to the tree cut limit")
+             .takes_value(true))
+        .arg(Arg::with_name("heatmap-cell")
+             .long("heatmap-cell")
+             .value_name("PIXELS")
+             .help("Cell size of the distance heatmap toggled with <N>, larger cells trade quality for speed")
+             .default_value("16")
+             .takes_value(true))
+        .arg(Arg::with_name("bg")
+             .long("bg")
+             .value_name("R,G,B")
+             .help("Background color components from 0 to 255, <L> switches between it and a contrasting light or dark one")
+             .default_value("0,0,0")
+             .takes_value(true))
+        .arg(Arg::with_name("theme")
+             .long("theme")
+             .value_name("FILE")
+             .help("TOML file with `key = [r, g, b, a]` colors from 0 to 1 for obstacle, cut_x, cut_y, highlight, highlight_pulse and cursor_<mode>")
+             .takes_value(true))
+        .arg(Arg::with_name("pulse")
+             .long("pulse")
+             .help("Briefly flash obstacles brighter the moment they start colliding"))
+        .arg(Arg::with_name("selftest")
+             .long("selftest")
+             .help("Check the core geometry functions on fixed inputs, print PASS/FAIL for each and exit"))
+        .arg(Arg::with_name("bench-dynamic")
+             .long("bench-dynamic")
+             .help("Compare full rebuilds against a static plus dynamic trees split for moving obstacles and exit"))
+        .arg(Arg::with_name("bench-cutters")
+             .long("bench-cutters")
+             .help("Compare tree quality of the cut strategies on a random scene and exit"))
+        .arg(Arg::with_name("bench-sweep")
+             .long("bench-sweep")
+             .help("Print tree build and query timings for growing random scenes and exit"))
+        .arg(Arg::with_name("headless")
+             .long("headless")
+             .help("Without opening a window, build the tree over the loaded scene (or --count random obstacles \
+                    from --seed) and print collide and nearest query timings"))
+        .arg(Arg::with_name("verify")
+             .long("verify")
+             .help("With --headless, also check every collide query against testing each obstacle directly and fail on a missed one")
+             .requires("headless"))
+        .group(ArgGroup::with_name("bench")
+               .args(&["bench-dynamic", "bench-sweep", "bench-cutters", "headless"]))
+        .arg(Arg::with_name("bench-csv")
+             .long("bench-csv")
+             .value_name("FILE")
+             .help("Also write the benchmark timings as CSV (size, strategy, build_ms, query_us) to the file")
+             .requires("bench")
+             .takes_value(true))
+        .arg(Arg::with_name("parallel")
+             .long("parallel")
+             .help("Precompute obstacle bounding volumes on all cores before building the tree"))
+        .arg(Arg::with_name("scene")
+             .short("s")
+             .long("scene")
+             .value_name("FILE")
+             .help("Scene file to load obstacles from on startup (when it exists) and to save them to with <S>, defaults to ./scene.json for saving")
+             .takes_value(true))
+        .arg(Arg::with_name("load")
+             .long("load")
+             .value_name("FILE")
+             .help("Scene file to load obstacles from on startup instead of the --scene one, starts empty when it cannot be read")
+             .takes_value(true))
+        .arg(Arg::with_name("dedup")
+             .long("dedup")
+             .help("Drop obstacles approximately equal to an earlier one when loading the scene file"))
+        .arg(Arg::with_name("seed")
+             .long("seed")
+             .value_name("N")
+             .help("Seed of the random scenes generated with <R>, the n-th scene uses N + n and the seed is shown on screen for replaying")
+             .takes_value(true))
+        .arg(Arg::with_name("count")
+             .long("count")
+             .value_name("N")
+             .help("Number of obstacles in the random scenes generated with <R>")
+             .default_value("50")
+             .takes_value(true))
+        .arg(Arg::with_name("watch")
+             .short("w")
+             .long("watch")
+             .value_name("PATH")
+             .help("File or named pipe to poll for new obstacles, one \"x1 y1 x2 y2\" per line")
+             .takes_value(true))
         .get_matches();
 
     let assets_dir = matches.value_of("assets-dir")
         .ok_or(Error::MissingParameter("assets-dir"))?;
+    let business = matches.value_of("mode")
+        .ok_or(Error::MissingParameter("mode"))?
+        .parse()
+        .unwrap_or_else(|()| unreachable!());
+    let volume_kind = match matches.value_of("bv") {
+        Some("circle") =>
+            VolumeKind::Circle,
+        Some(_) =>
+            VolumeKind::Aabb,
+        None =>
+            return Err(Error::MissingParameter("bv")),
+    };
+    let metric = match matches.value_of("metric") {
+        Some("manhattan") =>
+            Metric::Manhattan,
+        Some(_) =>
+            Metric::Euclidean,
+        None =>
+            return Err(Error::MissingParameter("metric")),
+    };
+
+    let screen_side = |name, default| match matches.value_of(name) {
+        Some(value) =>
+            value.parse::<u32>()
+                .map(|side| screen_side_clamped(name, side))
+                .map_err(|_| Error::InvalidParameter { name, value: value.to_string(), }),
+        None =>
+            Ok(default),
+    };
+    let screen_size = (screen_side("width", SCREEN_WIDTH)?, screen_side("height", SCREEN_HEIGHT)?);
+
+    let parallel = matches.is_present("parallel");
+    let probe_grid_spacing = matches.value_of("probe-grid-spacing")
+        .and_then(|value| value.parse::<f64>().ok())
+        .filter(|&spacing| spacing >= PROBE_GRID_MIN_SPACING)
+        .ok_or(Error::MissingParameter("probe-grid-spacing"))?;
+    let background = matches.value_of("bg")
+        .ok_or(Error::MissingParameter("bg"))
+        .and_then(|value| parse_color(value).ok_or_else(|| Error::InvalidParameter { name: "bg", value: value.to_string(), }))?;
+    let theme = match matches.value_of("theme") {
+        Some(path) => theme::load(path).map_err(Error::Theme)?,
+        None => Theme::default(),
+    };
+    let neighbours_limit = match matches.value_of("k") {
+        Some(value) =>
+            Some(value.parse::<usize>().ok()
+                 .filter(|&k| k > 0)
+                 .ok_or_else(|| Error::InvalidParameter { name: "k", value: value.to_string(), })?),
+        None =>
+            None,
+    };
+    let cut_limit = matches.value_of("cut-limit")
+        .ok_or(Error::MissingParameter("cut-limit"))
+        .and_then(|value| value.parse::<f64>().ok()
+                  .filter(|&limit| limit > 0.)
+                  .ok_or_else(|| Error::InvalidParameter { name: "cut-limit", value: value.to_string(), }))?;
+    let cut_strategy = match matches.value_of("cut-strategy") {
+        Some("median") =>
+            CutStrategy::Median,
+        Some("sah") =>
+            CutStrategy::Sah,
+        Some(_) =>
+            CutStrategy::Mean,
+        None =>
+            return Err(Error::MissingParameter("cut-strategy")),
+    };
+    let snap_size = match matches.value_of("snap-size") {
+        Some(value) =>
+            value.parse::<f64>().ok()
+                .filter(|&size| size > 0.)
+                .ok_or_else(|| Error::InvalidParameter { name: "snap-size", value: value.to_string(), })?,
+        None =>
+            cut_limit,
+    };
+    let heatmap_cell = matches.value_of("heatmap-cell")
+        .ok_or(Error::MissingParameter("heatmap-cell"))
+        .and_then(|value| value.parse::<f64>().ok()
+                  .filter(|&size| size >= 1.)
+                  .ok_or_else(|| Error::InvalidParameter { name: "heatmap-cell", value: value.to_string(), }))?;
+    let random_count = matches.value_of("count")
+        .ok_or(Error::MissingParameter("count"))
+        .and_then(|value| value.parse::<usize>().map_err(|_| Error::InvalidParameter { name: "count", value: value.to_string(), }))?;
+    let random_seed = match matches.value_of("seed") {
+        Some(value) =>
+            value.parse::<u64>().map_err(|_| Error::InvalidParameter { name: "seed", value: value.to_string(), })?,
+        None =>
+            SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0),
+    };
+
+    if matches.is_present("selftest") {
+        process::exit(if selftest::run() { 0 } else { 1 });
+    }
+
+    if matches.is_present("bench") {
+        let timings = if matches.is_present("bench-dynamic") {
+            bench::run_dynamic(volume_kind, cut_strategy, cut_limit)?
+        } else if matches.is_present("bench-cutters") {
+            bench::run_cutters(volume_kind, cut_limit)?
+        } else if matches.is_present("headless") {
+            let (mut scene, _) = load_scene(&matches)?;
+            if scene.obstacles.is_empty() {
+                let mut rng = bench::seeded_rng(random_seed);
+                for segment in bench::random_scene(&mut rng, random_count, screen_size.0 as f64, screen_size.1 as f64, bench::MAX_SEGMENT_LENGTH) {
+                    scene.push(segment, Default::default());
+                }
+                info!("generated {} random obstacles with --seed {}", random_count, random_seed);
+            }
+            let timings = bench::run_headless(&scene, volume_kind, cut_strategy, cut_limit, random_seed, screen_size)?;
+            if matches.is_present("verify") {
+                let missed = bench::verify(&scene, volume_kind, cut_strategy, cut_limit, random_seed, screen_size)?;
+                if missed > 0 {
+                    return Err(Error::VerifyFailed { missed, });
+                }
+            }
+            timings
+        } else {
+            bench::run_sweep(volume_kind, cut_strategy, cut_limit)?
+        };
+        if let Some(file) = matches.value_of("bench-csv") {
+            bench::write_csv(file, &timings)
+                .map_err(|e| Error::WriteBenchCsv { file: file.to_string(), error: e, })?;
+        }
+        return Ok(());
+    }
+
+    // an explicit font or assets directory must be there, otherwise the embedded font is a fallback
+    let font_path = match matches.value_of("font") {
+        Some(font) =>
+            Some(PathBuf::from(font)),
+        None => {
+            let mut font_path = PathBuf::from(assets_dir);
+            font_path.push(FONT_FILE);
+            if font_path.is_file() {
+                Some(font_path)
+            } else if matches.occurrences_of("assets-dir") > 0 {
+                error!("font {:?} not found: --assets-dir should point to a directory containing {}", font_path, FONT_FILE);
+                return Err(Error::MissingAssets {
+                    dir: assets_dir.to_string(),
+                    font: font_path.to_string_lossy().to_string(),
+                });
+            } else {
+                None
+            }
+        },
+    };
 
     let opengl = OpenGL::V4_1;
-    let mut window: PistonWindow = WindowSettings::new("KD-Tree demo", [SCREEN_WIDTH, SCREEN_HEIGHT])
+    let mut window: PistonWindow = WindowSettings::new("KD-Tree demo", [screen_size.0, screen_size.1])
         .exit_on_esc(true)
         .opengl(opengl)
         .build()
         .map_err(PistonError::BuildWindow)
         .map_err(Error::Piston)?;
 
-    let mut font_path = PathBuf::from(assets_dir);
-    font_path.push("FiraSans-Regular.ttf");
-    let mut glyphs = Glyphs::new(&font_path, window.factory.clone(), TextureSettings::new())
-        .map_err(|e| Error::Piston(PistonError::LoadFont {
-            file: font_path.to_string_lossy().to_string(),
-            error: e,
-        }))?;
+    let mut glyphs = match font_path {
+        Some(font_path) =>
+            Glyphs::new(&font_path, window.factory.clone(), TextureSettings::new())
+            .map_err(|e| Error::Piston(PistonError::LoadFont {
+                file: font_path.to_string_lossy().to_string(),
+                error: e,
+            }))?,
+        None => {
+            info!("no {} found in {:?}, using the embedded font", FONT_FILE, assets_dir);
+            Glyphs::from_bytes(EMBEDDED_FONT, window.factory.clone(), TextureSettings::new())
+                .map_err(|()| Error::Piston(PistonError::LoadEmbeddedFont))?
+        },
+    };
+
+    capture::init(&mut window);
+    let mut frame_recorder = match matches.value_of("record-frames") {
+        Some(dir) => {
+            Some(capture::FrameRecorder::new(PathBuf::from(dir))
+                 .map_err(|e| Error::CreateFramesDir { dir: dir.to_string(), error: e, })?)
+        },
+        None =>
+            None,
+    };
+
+    let mut input_recorder = match matches.value_of("record") {
+        Some(path) => Some(input_log::Recorder::create(path).map_err(Error::InputLog)?),
+        None => None,
+    };
+    let mut replay = match matches.value_of("replay") {
+        Some(path) => Some(input_log::Replay::load(path).map_err(Error::InputLog)?),
+        None => None,
+    };
+
+    let watch_rx = matches.value_of("watch")
+        .map(|path| watch::spawn(PathBuf::from(path)));
 
-    let mut obstacles = Vec::new();
-    let mut env = Env::new();
+    let save_path = matches.value_of("scene").map(PathBuf::from).unwrap_or_else(|| PathBuf::from(DEFAULT_SCENE_FILE));
+    let (mut scene, load_warning) = load_scene(&matches)?;
+    let mut env = Env::new(business, matches.is_present("narrate"), matches.is_present("pulse"));
+    if let Some(warning) = load_warning {
+        env.report_error(warning);
+    }
+    env.background = background;
+    env.random_scene = (random_seed, random_count);
+    env.snap_size = snap_size;
+    env.distance_field_cell = heatmap_cell;
+    env.neighbours_limit = neighbours_limit;
+    env.reset(screen_size.0, screen_size.1);
     let mut collide_cutter: PointsCutter = Default::default();
     let mut collide_cache = HashSet::new();
+    let mut visual_cutter = VisualCutter::with_strategy(cut_strategy);
+    // the tree along with the active obstacles it was built for and its shape
+    let mut cached_tree: Option<(Vec<usize>, SceneTree, TreeStats)> = None;
+    // obstacles and their bounds the cached tree indexes by position
+    let mut cached_scene: (Vec<Segment>, Vec<Bound>) = (Vec::new(), Vec::new());
+    // background build in flight, the cached tree keeps serving queries until it is done
+    let mut pending_build: Option<mpsc::Receiver<TreeBuild>> = None;
+    let mut finished_build: Option<TreeBuild> = None;
+    // obstacles and active ones the last full build was started for, newer obstacles go to the overflow tree
+    let mut full_build: Option<(Vec<Segment>, Vec<usize>)> = None;
+    // rectangle bounds of `scene.obstacles` by index, refreshed along with the tree
+    let mut bounds = Vec::new();
 
+    if let Some(ref mut replay) = replay {
+        replay.restart();
+    }
     loop {
-        let mut action: Box<FnMut(&mut Vec<Segment>)> = {
-            let mut visual_cutter = VisualCutter::new();
-            let tree = kdvtree::KdvTree::build(
-                iter::once(Axis::X).chain(iter::once(Axis::Y)),
-                0 .. obstacles.len(),
-                cmp_points,
-                |&shape_index: &_| get_bounding_volume(&obstacles[shape_index]),
-                &mut visual_cutter,
-                |&shape_index: &_, fragment: &_, cut_axis: &_, cut_point: &_| {
-                    cut_segment_fragment(&obstacles[shape_index], fragment, cut_axis, cut_point)
-                },
-            ).unwrap_or_else(|()| unreachable!());
+        let mut action: Box<dyn FnMut(&mut Scene)> = {
+            let build = if env.take_dirty() || cached_tree.is_none() {
+                // a build started for an older scene is of no use anymore
+                finished_build = None;
+                let interrupted = pending_build.take().is_some();
+                bounds = scene.bounds(parallel);
+                let job = TreeBuildJob {
+                    kind: volume_kind,
+                    obstacles: scene.obstacles.clone(),
+                    bounds: bounds.clone(),
+                    shapes: scene.meta.iter().map(ObstacleMeta::indexed_shape).collect(),
+                    active_shapes: scene.active_shapes(&env.active_groups),
+                    strategy: cut_strategy,
+                    metric,
+                    cut_limit,
+                };
+                // the old tree may only stay in use while its indices still point at the same obstacles
+                let keep_old_tree = cached_tree.as_ref()
+                    .is_some_and(|(active_shapes, _, _)| old_tree_usable(&cached_scene.0, &cached_scene.1, active_shapes, &job));
+                let appended = match full_build {
+                    Some((ref built_obstacles, ref built_shapes)) if !interrupted && cached_tree.is_some() =>
+                        appended_shapes(built_obstacles, built_shapes, &job.obstacles, &job.active_shapes)
+                            .filter(|appended| appended.len() <= OVERFLOW_LIMIT),
+                    _ =>
+                        None,
+                };
+                // a failed overflow build falls back to the full one
+                let overflow = appended.and_then(|appended| {
+                    job.run_overflow(&appended)
+                        .map_err(|e| error!("failed to build the overflow tree: {:?}", e))
+                        .ok()
+                });
+                if let Some(overflow) = overflow {
+                    let tree = match cached_tree.take() {
+                        Some((_, tree, _)) => tree.with_overflow(overflow),
+                        None => unreachable!(),
+                    };
+                    let tree_stats = tree_stats(&tree.nodes());
+                    cached_tree = Some((job.active_shapes, tree, tree_stats));
+                    cached_scene = (job.obstacles, job.bounds);
+                    None
+                } else {
+                    full_build = Some((job.obstacles.clone(), job.active_shapes.clone()));
+                    if keep_old_tree && job.active_shapes.len() >= BACKGROUND_BUILD_MIN_OBSTACLES {
+                        pending_build = Some(job.spawn());
+                        None
+                    } else {
+                        Some(job.run())
+                    }
+                }
+            } else {
+                finished_build.take()
+            };
+            if let Some(TreeBuild { active_shapes, tree: built, cutter, obstacles, bounds, }) = build {
+                visual_cutter = cutter;
+                cached_scene = (obstacles, bounds);
+                let (active_shapes, tree) = match built {
+                    Ok(tree) =>
+                        (active_shapes, tree),
+                    Err(e) => {
+                        // keep running with no obstacles in the tree until the scene changes again
+                        error!("failed to build the tree: {:?}", e);
+                        env.report_error(format!("failed to build the tree: {:?}", e));
+                        visual_cutter.clear();
+                        full_build = None;
+                        (Vec::new(), SceneTree::empty(volume_kind, cut_limit).with_metric(metric))
+                    },
+                };
+                let tree_stats = tree_stats(&tree.nodes());
+                cached_tree = Some((active_shapes, tree, tree_stats));
+                env.narrate(format!("building tree with {} obstacles", scene.obstacles.len()));
+                if let Some((cut_seg, axis)) = visual_cutter.cuts.first() {
+                    env.narrate(match *axis {
+                        Axis::X => format!("root X cut at x={:.1}, {} cuts total", cut_seg.src.x, visual_cutter.cuts.len()),
+                        Axis::Y => format!("root Y cut at y={:.1}, {} cuts total", cut_seg.src.y, visual_cutter.cuts.len()),
+                    });
+                }
+            }
+            let (active_shapes, tree, tree_stats) = match cached_tree {
+                Some((ref active_shapes, ref tree, ref tree_stats)) => (active_shapes, tree, tree_stats),
+                None => unreachable!(),
+            };
+            let length_colors = length_gradient(&scene.obstacles);
+            let scene_bound = active_shapes.iter()
+                .map(|&i| &bounds[i])
+                .fold(None, |acc, bound| Some(match acc { Some(acc) => merge_bounds(&acc, bound), None => bound.clone(), }));
+            // nearest regions of the current tree keyed by the visible area they were computed for
+            let mut regions_cache: Option<(Point, [f64; 2], Vec<Option<usize>>)> = None;
+            // same for the distance heatmap
+            let mut field_cache: Option<(Point, [f64; 2], Vec<Option<f64>>)> = None;
+            let mut tree_nodes: Option<Vec<(usize, Option<Bound>)>> = None;
+            let mut crossing_pairs: Option<Vec<(usize, usize)>> = None;
+
+            let x_cuts = visual_cutter.cuts.iter().filter(|&(_, axis)| matches!(*axis, Axis::X)).count();
+            let groups: Vec<_> = (0 .. OBSTACLE_GROUPS)
+                .map(|group| if env.active_groups.contains(&group) { (group + 1).to_string() } else { "_".to_string() })
+                .collect();
+            let stats_line = format!(
+                "scene {:016x} | cuts: {} x, {} y | nodes: {}, depth: {} | groups: {} (new in {})",
+                scene_hash(&scene),
+                x_cuts,
+                visual_cutter.cuts.len() - x_cuts,
+                tree_stats.nodes,
+                tree_stats.depth,
+                groups.join(" "),
+                env.group + 1,
+            );
 
             loop {
-                let event = if let Some(ev) = window.next() {
-                    ev
-                } else {
-                    return Ok(());
+                let (event, replayed) = match replay.as_mut().and_then(|replay| replay.next_due(Instant::now())) {
+                    Some(ev) =>
+                        (ev, true),
+                    None => if let Some(ev) = window.next() {
+                        (ev, false)
+                    } else {
+                        return Ok(());
+                    },
+                };
+                // real input would only disturb the replay until it is over
+                let replaying = replay.as_ref().is_some_and(|replay| !replay.is_finished());
+                if replaying && !replayed && input_log::InputEvent::from_event(&event).is_some() {
+                    continue;
+                }
+                // keys typed into the console are not commands
+                let recorded = match input_recorder {
+                    Some(ref mut recorder) if env.console.is_none() => recorder.record(&event),
+                    _ => Ok(()),
                 };
+                if let Err(e) = recorded {
+                    error!("failed to record input: {:?}", e);
+                    env.report_error("input recording stopped, see the log for details".to_string());
+                    input_recorder = None;
+                }
                 let maybe_result = window.draw_2d(&event, |context, g2d| {
-                    use piston_window::{clear, text, ellipse, line, rectangle, Transformed};
+                    use piston_window::{clear, text, ellipse, line, Transformed};
                     // clear everything
-                    clear([0.0, 0.0, 0.0, 1.0], g2d);
+                    clear(env.background, g2d);
+                    let ink = contrast_color(env.background);
+                    let world = context.transform.trans(env.camera.x, env.camera.y).zoom(env.zoom);
+
+                    // draw cut limit grid
+                    if env.show_grid {
+                        let [width, height] = context.get_view_size();
+                        let color = [ink[0], ink[1], ink[2], 0.08];
+                        for grid_line in grid_lines(&env.screen_to_world(0., 0.), &env.screen_to_world(width, height), cut_limit) {
+                            line(color, 0.5, [grid_line.src.x, grid_line.src.y, grid_line.dst.x, grid_line.dst.y], world, g2d);
+                        }
+                    }
+
+                    // draw distance to the nearest obstacle heatmap
+                    if env.show_distance_field {
+                        let view_size = { let [width, height] = context.get_view_size(); [width / env.zoom, height / env.zoom] };
+                        let lt = env.screen_to_world(0., 0.);
+                        let cell = env.distance_field_cell;
+                        let stale = field_cache.as_ref()
+                            .is_none_or(|&(cached_lt, cached_size, _)| !points_approx_eq(&cached_lt, &lt, 0.) || cached_size != view_size);
+                        if stale {
+                            let dists = env.query_or_report(distance_field(tree, lt, view_size[0], view_size[1], cell));
+                            field_cache = Some((lt, view_size, dists));
+                        }
+                        if let Some((_, _, ref dists)) = field_cache {
+                            let max_dist = dists.iter().flatten().cloned().fold(0., f64::max);
+                            let columns = (view_size[0] / cell).ceil() as usize;
+                            for (i, dist) in dists.iter().enumerate() {
+                                if let Some(dist) = *dist {
+                                    let (x, y) = (lt.x + (i % columns) as f64 * cell, lt.y + (i / columns) as f64 * cell);
+                                    piston_window::rectangle(heat_color(dist, max_dist), [x, y, cell, cell], world, g2d);
+                                }
+                            }
+                        }
+                    }
 
+                    // draw coarse nearest obstacle regions
+                    if env.show_nearest_regions {
+                        let view_size = { let [width, height] = context.get_view_size(); [width / env.zoom, height / env.zoom] };
+                        let lt = env.screen_to_world(0., 0.);
+                        let stale = regions_cache.as_ref()
+                            .is_none_or(|&(cached_lt, cached_size, _)| !points_approx_eq(&cached_lt, &lt, 0.) || cached_size != view_size);
+                        if stale {
+                            let cells = env.query_or_report(nearest_regions(tree, lt, view_size[0], view_size[1], NEAREST_REGION_CELL));
+                            regions_cache = Some((lt, view_size, cells));
+                        }
+                        if let Some((_, _, ref cells)) = regions_cache {
+                            let columns = (view_size[0] / NEAREST_REGION_CELL).ceil() as usize;
+                            for (i, cell) in cells.iter().enumerate() {
+                                if let Some(shape) = *cell {
+                                    let (x, y) = (lt.x + (i % columns) as f64 * NEAREST_REGION_CELL, lt.y + (i / columns) as f64 * NEAREST_REGION_CELL);
+                                    piston_window::rectangle(index_color(shape, 0.35), [x, y, NEAREST_REGION_CELL, NEAREST_REGION_CELL], world, g2d);
+                                }
+                            }
+                        }
+                    }
+                    // draw snapping grid lattice
+                    if env.snap_to_grid {
+                        let [width, height] = context.get_view_size();
+                        let (lt, rb) = (env.screen_to_world(0., CONSOLE_HEIGHT as f64), env.screen_to_world(width, height));
+                        let lattice = snap_point(&lt, env.snap_size);
+                        let color = [ink[0], ink[1], ink[2], 0.35];
+                        let mut y = lattice.y;
+                        while y <= rb.y {
+                            let mut x = lattice.x;
+                            while x <= rb.x {
+                                piston_window::rectangle(color, [x - 1., y - 1., 2., 2.], world, g2d);
+                                x += env.snap_size;
+                            }
+                            y += env.snap_size;
+                        }
+                    }
+                    // draw tree leaf region under the cursor
+                    if let (true, Some(cursor)) = (env.show_leaf_region, env.cursor) {
+                        let nodes = tree_nodes.get_or_insert_with(|| tree.nodes());
+                        if let Some(Bound { lt, rb, }) = leaf_region(nodes, &cursor) {
+                            piston_window::rectangle([0.5, 0.5, 1.0, 0.2], [lt.x, lt.y, rb.x - lt.x, rb.y - lt.y], world, g2d);
+                        }
+                    }
                     // draw kdtree cuts mesh
                     for &(ref cut_seg, ref axis) in visual_cutter.cuts.iter() {
                         let color = match axis {
-                            &Axis::X => [0.25, 0.25, 0., 1.0],
-                            &Axis::Y => [0., 0.25, 0.25, 1.0],
+                            &Axis::X => theme.cut_x,
+                            &Axis::Y => theme.cut_y,
                         };
-                        line(color, 1., [cut_seg.src.x, cut_seg.src.y, cut_seg.dst.x, cut_seg.dst.y], context.transform, g2d);
+                        line(color, 1., [cut_seg.src.x, cut_seg.src.y, cut_seg.dst.x, cut_seg.dst.y], world, g2d);
                     }
                     // draw collisions or neighbours
-                    match (&env.business, env.cursor, env.obj_start) {
-                        (&Business::Collide, Some(src), Some(dst)) => {
-                            let collide_segment = Segment { src, dst };
+                    match (&env.business, env.probe()) {
+                        (&Business::Collide, Some(collide_segment)) => {
                             collide_cache.clear();
-                            for maybe_intersection in tree.intersects(
-                                &collide_segment,
-                                cmp_points,
-                                get_bounding_volume,
-                                &mut collide_cutter,
-                                cut_segment_fragment,
-                            )
-                            {
-                                let kdvtree::Intersection { shape: &shape_index, shape_fragment, needle_fragment } = maybe_intersection
-                                    .unwrap_or_else(|()| unreachable!());
-                                // highlight collided obstacle
-                                if !collide_cache.contains(&shape_index) {
-                                    let obstacle = &obstacles[shape_index];
-                                    line(
-                                        [0.75, 0.75, 0., 1.0],
-                                        4.,
-                                        [obstacle.src.x, obstacle.src.y, obstacle.dst.x, obstacle.dst.y],
-                                        context.transform,
-                                        g2d,
-                                    );
-                                    collide_cache.insert(shape_index);
+                            // fresh counters, so the panel shows this query alone
+                            let mut query_stats = QueryStats::default();
+                            let collisions = env.query_or_report(scene_collisions(tree, &scene, &collide_segment, &mut collide_cutter, &mut query_stats));
+                            for collision in collisions.iter() {
+                                // highlight collided obstacle along with the rest of its polyline
+                                if !collide_cache.contains(&collision.shape) {
+                                    for shape in scene.polyline_members(collision.shape) {
+                                        let color = match env.collision_pulses {
+                                            Some(ref mut pulses) => {
+                                                let started_at = *pulses.entry(shape).or_insert_with(Instant::now);
+                                                pulse_color(theme.highlight, theme.highlight_pulse, started_at.elapsed())
+                                            },
+                                            None =>
+                                                theme.highlight,
+                                        };
+                                        draw_obstacle(color, 4., &scene.obstacles[shape], scene.meta[shape].shape, world, g2d);
+                                        collide_cache.insert(shape);
+                                    }
+                                }
+                            }
+                            // show every collided fragment once, overlapping translucent copies would only darken it
+                            let (shape_fragments, needle_fragments) = unique_fragments(&collisions);
+                            // show collided obstacle bounding volumes
+                            for fragment in shape_fragments {
+                                draw_volume([1., 0., 0., 0.5], fragment, env.fragments_outline, world, g2d);
+                            }
+                            // show collided user segment bounding volumes
+                            for fragment in needle_fragments {
+                                draw_volume([0., 1., 0., 0.5], fragment, env.fragments_outline, world, g2d);
+                            }
+                            // mark where the probe actually meets the collided obstacles
+                            let touched: HashSet<_> = collisions.iter().map(|collision| collision.shape).collect();
+                            for &shape in touched.iter() {
+                                for contact in contact_points(&collide_segment, &scene.obstacles[shape], scene.meta[shape].shape) {
+                                    ellipse([1., 1., 1., 1.0], [contact.x - 3., contact.y - 3., 6., 6.], world, g2d);
+                                }
+                            }
+                            // outline exact hits next to the tree ones, so the obstacles it misses stand out
+                            if env.show_brute_force {
+                                let exact = exact_collisions(&scene, active_shapes, &collide_segment);
+                                for &shape in exact.iter() {
+                                    draw_obstacle([0., 0.9, 1., 0.6], 8., &scene.obstacles[shape], scene.meta[shape].shape, world, g2d);
+                                }
+                                let missed = exact.iter().filter(|shape| !touched.contains(shape)).count();
+                                let loose = touched.iter().filter(|shape| exact.binary_search(shape).is_err()).count();
+                                text::Text::new_color([0., 0.9, 1., 1.0], 12).draw(
+                                    &format!("brute force: {} hits | {} missed by the tree | {} loose", exact.len(), missed, loose),
+                                    &mut glyphs,
+                                    &context.draw_state,
+                                    context.transform.trans(5.0, context.get_view_size()[1] - 38.0),
+                                    g2d
+                                ).map_err(PistonError::DrawText)?;
+                            }
+                            // show what this query made the tree do
+                            if env.show_pruning {
+                                text::Text::new_color([0.75, 0.75, 0.75, 1.0], 12).draw(
+                                    &format!(
+                                        "comparisons: {} | needle cuts: {} | cut point picks: {}",
+                                        query_stats.comparisons,
+                                        query_stats.needle_cuts,
+                                        query_stats.cut_picks,
+                                    ),
+                                    &mut glyphs,
+                                    &context.draw_state,
+                                    context.transform.trans(5.0, context.get_view_size()[1] - 22.0),
+                                    g2d
+                                ).map_err(PistonError::DrawText)?;
+                            }
+                            // explain pinned probe collisions
+                            if env.pinned_probe.is_some() {
+                                let lines = explain_collisions(&collisions, &scene.obstacles, &collide_segment);
+                                let first = env.explain_scroll.min(lines.len().saturating_sub(EXPLAIN_LINES));
+                                env.explain_scroll = first;
+                                let left = context.get_view_size()[0] - 380.;
+                                let header = format!("{} collisions, <Up>/<Down> to scroll", lines.len());
+                                for (i, line) in iter::once(&header).chain(lines.iter().skip(first).take(EXPLAIN_LINES)).enumerate() {
+                                    text::Text::new_color([0.75, 0.75, 0.75, 1.0], 11).draw(
+                                        line,
+                                        &mut glyphs,
+                                        &context.draw_state,
+                                        context.transform.trans(left, (CONSOLE_HEIGHT + 14 + (i as u32 * 14)) as f64),
+                                        g2d
+                                    ).map_err(PistonError::DrawText)?;
                                 }
-                                // show collided obstacle bounding volume
-                                rectangle(
-                                    [1., 0., 0., 0.5],
-                                    [
-                                        shape_fragment.lt.x,
-                                        shape_fragment.lt.y,
-                                        shape_fragment.rb.x - shape_fragment.lt.x,
-                                        shape_fragment.rb.y - shape_fragment.lt.y,
-                                    ],
-                                    context.transform,
-                                    g2d,
-                                );
-                                // show collided user segment bounding volume
-                                rectangle(
-                                    [0., 1., 0., 0.5],
-                                    [
-                                        needle_fragment.lt.x,
-                                        needle_fragment.lt.y,
-                                        needle_fragment.rb.x - needle_fragment.lt.x,
-                                        needle_fragment.rb.y - needle_fragment.lt.y,
-                                    ],
-                                    context.transform,
-                                    g2d,
-                                );
                             }
                         },
-                        (&Business::Neighbours, Some(src), Some(dst)) => {
+                        (&Business::Neighbours, Some(neighbour_segment)) => {
                             let (width, height) = context.viewport.as_ref()
                                 .map(|v| (v.draw_size[0] as f64, v.draw_size[1] as f64))
-                                .unwrap_or((SCREEN_WIDTH as f64, SCREEN_HEIGHT as f64));
-                            let max_dist = ((width * width) + (height * height)).sqrt();
-                            let neighbour_segment = Segment { src, dst };
-                            for maybe_neighbour in tree.nearest(
-                                &neighbour_segment,
-                                cmp_points,
-                                get_bounding_volume,
-                                cut_segment_fragment,
-                                bound_to_cut_point_dist,
-                                bound_to_bound_dist,
-                            )
-                            {
-                                let kdvtree::NearestShape { dist, shape: &_shape_index, shape_fragment, } =
-                                    maybe_neighbour.unwrap_or_else(|()| unreachable!());
-                                let color = if dist < (max_dist * 0.2) {
-                                    [1., 1., 1. - (dist / (max_dist * 0.2)) as f32, 1.]
-                                } else if dist < (max_dist * 0.4) {
-                                    [1., 1. - (dist / (max_dist * 0.4)) as f32, 0., 1.]
-                                } else if dist < (max_dist * 0.6) {
-                                    [1. - (dist / (max_dist * 0.6)) as f32, 0., 0., 1.]
-                                } else {
-                                    [0., 0., 0., 1.]
-                                };
-                                rectangle(
-                                    color,
-                                    [
-                                        shape_fragment.lt.x,
-                                        shape_fragment.lt.y,
-                                        shape_fragment.rb.x - shape_fragment.lt.x,
-                                        shape_fragment.rb.y - shape_fragment.lt.y,
-                                    ],
-                                    context.transform,
-                                    g2d,
-                                );
+                                .unwrap_or((env.screen_size.0 as f64, env.screen_size.1 as f64));
+                            let nearest_group = env.nearest_group;
+                            let in_nearest_group = |maybe_neighbour: &Result<Neighbour, Error>| maybe_neighbour.as_ref()
+                                .map_or(true, |neighbour| nearest_group.is_none_or(|group| scene.meta[neighbour.shape].group == group));
+                            let found = tree.nearest(&neighbour_segment)
+                                .filter(in_nearest_group);
+                            // obstacles are ranked by their exact distances, box ones are only estimates
+                            let neighbours = match env.neighbours_limit {
+                                Some(k) =>
+                                    k_nearest_exact(found, &neighbour_segment, &scene, tree.metric(), k),
+                                None =>
+                                    found.collect::<Result<_, _>>().map(|neighbours| refine_neighbours(neighbours, &neighbour_segment, &scene)),
+                            };
+                            let neighbours = env.query_or_report(neighbours);
+                            let adaptive_dist = if env.adaptive_gradient { adaptive_max_dist(&neighbours) } else { None };
+                            let max_dist = adaptive_dist.unwrap_or_else(|| ((width * width) + (height * height)).sqrt());
+                            if let Some(step) = env.neighbour_step {
+                                // inspect a single result instead of the whole gradient
+                                if let Some(last) = neighbours.len().checked_sub(1) {
+                                    let step = step.min(last);
+                                    env.neighbour_step = Some(step);
+                                    let neighbour = &neighbours[step];
+                                    let obstacle = &scene.obstacles[neighbour.shape];
+                                    draw_obstacle(theme.highlight, 4., obstacle, scene.meta[neighbour.shape].shape, world, g2d);
+                                    draw_volume([ink[0], ink[1], ink[2], 0.5], &neighbour.shape_fragment, env.fragments_outline, world, g2d);
+                                    let label_at = obstacle.midpoint();
+                                    text::Text::new_color(ink, 12).draw(
+                                        &format!("#{} of {}: {:.1}", step + 1, neighbours.len(), neighbour.dist),
+                                        &mut glyphs,
+                                        &context.draw_state,
+                                        world.trans(label_at.x + 6., label_at.y - 6.),
+                                        g2d
+                                    ).map_err(PistonError::DrawText)?;
+                                }
+                            } else {
+                                for neighbour in neighbours.iter() {
+                                    let dist = neighbour.dist;
+                                    let color = if dist < (max_dist * 0.2) {
+                                        [1., 1., 1. - (dist / (max_dist * 0.2)) as f32, 1.]
+                                    } else if dist < (max_dist * 0.4) {
+                                        [1., 1. - (dist / (max_dist * 0.4)) as f32, 0., 1.]
+                                    } else if dist < (max_dist * 0.6) {
+                                        [1. - (dist / (max_dist * 0.6)) as f32, 0., 0., 1.]
+                                    } else {
+                                        [0., 0., 0., 1.]
+                                    };
+                                    draw_volume(color, &neighbour.shape_fragment, env.fragments_outline, world, g2d);
+                                }
+                                // number the few results by rank
+                                if env.neighbours_limit.is_some() {
+                                    for (rank, neighbour) in neighbours.iter().enumerate() {
+                                        let label_at = scene.obstacles[neighbour.shape].midpoint();
+                                        text::Text::new_color(ink, 11).draw(
+                                            &format!("#{}", rank + 1),
+                                            &mut glyphs,
+                                            &context.draw_state,
+                                            world.trans(label_at.x + 6., label_at.y - 6.),
+                                            g2d
+                                        ).map_err(PistonError::DrawText)?;
+                                    }
+                                }
+                            }
+                            // connect the probe with the top ranked obstacle
+                            if let Some(closest) = neighbours.first() {
+                                let contact = closest_points(&neighbour_segment, &scene.obstacles[closest.shape]);
+                                let dist = scene.distance(closest.shape, &neighbour_segment);
+                                let label_at = contact.midpoint();
+                                line(ink, 1., [contact.src.x, contact.src.y, contact.dst.x, contact.dst.y], world, g2d);
+                                text::Text::new_color(ink, 12).draw(
+                                    &format!("{:.1}", dist),
+                                    &mut glyphs,
+                                    &context.draw_state,
+                                    world.trans(label_at.x + 4., label_at.y - 4.),
+                                    g2d
+                                ).map_err(PistonError::DrawText)?;
+                            }
+                            let neighbours_count = neighbours.len();
+                            env.narrate(format!("ranked {} neighbour fragments by distance", neighbours_count));
+                        },
+                        (&Business::Range, Some(corners)) => {
+                            let area = get_bounding_volume(&corners);
+                            let inside = env.query_or_report(obstacles_in_range(tree, &bounds, &area, &mut collide_cutter));
+                            for &shape in inside.iter() {
+                                draw_obstacle(theme.highlight, 4., &scene.obstacles[shape], scene.meta[shape].shape, world, g2d);
+                            }
+                            draw_bound([0.4, 0.8, 0.4, 0.2], &area, false, world, g2d);
+                            env.range_hits = Some(inside.len());
+                        },
+                        (&Business::Ray, Some(aim)) => {
+                            // live probe runs from the cursor back to the first click
+                            let ray = scene_bound.as_ref().and_then(|bound| cast_ray(&aim.dst, &aim.src, bound));
+                            let hit = match ray {
+                                Some(ref ray) => env.query_or_report(first_hit(tree, &scene, ray, &mut collide_cutter)),
+                                None => None,
+                            };
+                            match (ray, hit) {
+                                (Some(ray), Some((shape, dist))) => {
+                                    let contact = match ray.direction() {
+                                        Some(direction) => Point { x: ray.src.x + direction.x * dist, y: ray.src.y + direction.y * dist, },
+                                        None => ray.src,
+                                    };
+                                    draw_obstacle(theme.highlight, 4., &scene.obstacles[shape], scene.meta[shape].shape, world, g2d);
+                                    line([0.4, 0.6, 1.0, 1.0], 2., [ray.src.x, ray.src.y, contact.x, contact.y], world, g2d);
+                                    ellipse([1., 1., 1., 1.0], [contact.x - 4., contact.y - 4., 8., 8.], world, g2d);
+                                    env.narrate(format!("ray hits obstacle {} after {:.1}", shape, dist));
+                                },
+                                (Some(ray), None) =>
+                                    line([0.4, 0.6, 1.0, 0.3], 1., [ray.src.x, ray.src.y, ray.dst.x, ray.dst.y], world, g2d),
+                                (None, _) =>
+                                    (),
                             }
                         },
                         _ =>
                             (),
                     }
+                    if let (&Business::Collide, Some(..)) = (&env.business, env.probe()) {
+                        let collisions_count = collide_cache.len();
+                        env.narrate(format!("found {} collisions", collisions_count));
+                    }
+                    // draw capsule areas under the obstacles
+                    for &i in active_shapes.iter() {
+                        if let Some(radius) = scene.meta[i].capsule_radius() {
+                            let obstacle = &scene.obstacles[i];
+                            piston_window::Line::new_round([theme.obstacle[0], theme.obstacle[1], theme.obstacle[2], 0.15], radius)
+                                .draw([obstacle.src.x, obstacle.src.y, obstacle.dst.x, obstacle.dst.y], &Default::default(), world, g2d);
+                        }
+                    }
                     // draw obstacles
-                    for &Segment { src: Point { x: mx, y: my, }, dst: Point { x: cx, y: cy, }, } in obstacles.iter() {
-                        line([0.75, 0., 0., 1.0], 2., [cx, cy, mx, my], context.transform, g2d);
+                    for &i in active_shapes.iter() {
+                        let color = if env.color_by_length { length_colors[i] } else { theme.obstacle };
+                        draw_obstacle(color, 2., &scene.obstacles[i], scene.meta[i].shape, world, g2d);
+                    }
+                    // draw obstacle index labels
+                    if env.show_labels {
+                        let ink = [ink[0], ink[1], ink[2], 0.75];
+                        for &i in active_shapes.iter() {
+                            if label_worthy(&bounds[i], env.zoom) {
+                                let label_at = scene.obstacles[i].midpoint();
+                                text::Text::new_color(ink, 10).draw(
+                                    &i.to_string(),
+                                    &mut glyphs,
+                                    &context.draw_state,
+                                    world.trans(label_at.x + 3., label_at.y - 3.),
+                                    g2d
+                                ).map_err(PistonError::DrawText)?;
+                            }
+                        }
+                    }
+                    // draw planned obstacles
+                    for ghost in scene.ghosts.iter() {
+                        draw_dashed([0.75, 0.75, 0.75, 0.75], 1., ghost, world, g2d);
+                    }
+                    // draw nearest obstacle to each window corner
+                    if env.show_corners_nearest {
+                        let [width, height] = context.get_view_size();
+                        let corners = [
+                            (env.screen_to_world(0., 0.), [0., 1., 1., 1.0]),
+                            (env.screen_to_world(width, 0.), [1., 0., 1., 1.0]),
+                            (env.screen_to_world(0., height), [1., 0.5, 0., 1.0]),
+                            (env.screen_to_world(width, height), [0.5, 1., 0.5, 1.0]),
+                        ];
+                        for &(corner, color) in corners.iter() {
+                            if let Some(neighbour) = env.query_or_report(tree.nearest_one(corner)) {
+                                let target = closest_point_on_segment(&corner, &scene.obstacles[neighbour.shape]);
+                                let label_at = Segment { src: corner, dst: target, }.midpoint();
+                                line(color, 1., [corner.x, corner.y, target.x, target.y], world, g2d);
+                                text::Text::new_color(color, 12).draw(
+                                    &format!("{:.1}", neighbour.dist),
+                                    &mut glyphs,
+                                    &context.draw_state,
+                                    world.trans(label_at.x, label_at.y),
+                                    g2d
+                                ).map_err(PistonError::DrawText)?;
+                            }
+                        }
+                    }
+                    // draw probes grid coverage
+                    if env.show_probe_grid {
+                        let [width, height] = context.get_view_size();
+                        let probes = probe_grid(
+                            env.screen_to_world(0., CONSOLE_HEIGHT as f64),
+                            width / env.zoom,
+                            (height - CONSOLE_HEIGHT as f64) / env.zoom,
+                            probe_grid_spacing,
+                        );
+                        for probe in probes.iter() {
+                            line([0., 0.5, 0.5, 0.5], 1., [probe.src.x, probe.src.y, probe.dst.x, probe.dst.y], world, g2d);
+                        }
+                        let hit = env.query_or_report(tree.collided_by(&probes, &mut collide_cutter));
+                        text::Text::new_color([0., 0.75, 0.75, 1.0], 12).draw(
+                            &format!("coverage: {} of {} obstacles ({:.1}%)", hit.len(), scene.obstacles.len(), coverage_percent(hit.len(), scene.obstacles.len())),
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(5.0, height - 22.0),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                    }
+                    // draw obstacles crossing each other
+                    if env.show_crossing_pairs {
+                        if crossing_pairs.is_none() {
+                            crossing_pairs = Some(env.query_or_report(intersecting_pairs(tree, &scene, active_shapes, &mut collide_cutter)));
+                        }
+                        let pairs = crossing_pairs.as_deref().unwrap_or(&[]);
+                        for &(a, b) in pairs {
+                            for &shape in [a, b].iter() {
+                                draw_obstacle(theme.highlight, 4., &scene.obstacles[shape], scene.meta[shape].shape, world, g2d);
+                            }
+                        }
+                        text::Text::new_color(theme.highlight, 12).draw(
+                            &format!("{} crossing pairs", pairs.len()),
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(context.get_view_size()[0] - 260.0, CONSOLE_HEIGHT as f64 + 48.0),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                    }
+                    // draw pinned probe
+                    if let (&Business::Collide, Some(probe)) = (&env.business, env.pinned_probe) {
+                        line([0., 0.5, 0., 1.0], 3., [probe.src.x, probe.src.y, probe.dst.x, probe.dst.y], world, g2d);
+                    }
+                    // draw obstacle to be deleted
+                    if let (true, Some(cursor)) = (env.delete_pending, env.cursor) {
+                        if let Some(neighbour) = env.query_or_report(tree.nearest_one(cursor)) {
+                            for shape in scene.polyline_members(neighbour.shape) {
+                                draw_obstacle([1.0, 0., 1.0, 1.0], 5., &scene.obstacles[shape], scene.meta[shape].shape, world, g2d);
+                            }
+                        }
+                    }
+                    // draw polyline under construction
+                    if let Some(ref points) = env.polyline {
+                        for pair in points.windows(2) {
+                            draw_obstacle([1.0, 0., 0., 1.0], 3., &Segment { src: pair[0], dst: pair[1], }, Shape::Segment, world, g2d);
+                        }
                     }
                     // draw cursor
                     if let Some(Point { x: mx, y: my, }) = env.cursor {
-                        let color = match env.business {
-                            Business::Construct =>
-                                [1.0, 0., 0., 1.0],
-                            Business::Collide =>
-                                [0., 0.25, 0., 1.0],
-                            Business::Neighbours =>
-                                [0.824, 0.706, 0.549, 1.0],
-                        };
-                        if let Some(Point { x: cx, y: cy, }) = env.obj_start {
-                            line(color, 3., [cx, cy, mx, my], context.transform, g2d);
+                        let color = theme.cursor(&env.business);
+                        // mark the segment end which is dragged or would be grabbed by a click
+                        if let Business::Edit = env.business {
+                            let shapes = scene.active_shapes(&env.active_groups);
+                            let handle = env.grabbed.or_else(|| nearest_endpoint(&scene.obstacles, &shapes, &Point { x: mx, y: my, }, GRAB_DISTANCE));
+                            if let Some((index, endpoint)) = handle {
+                                let Point { x, y, } = endpoint.of(&scene.obstacles[index]);
+                                piston_window::Rectangle::new_border(color, 1.)
+                                    .draw([x - GRAB_DISTANCE, y - GRAB_DISTANCE, GRAB_DISTANCE * 2., GRAB_DISTANCE * 2.], &Default::default(), world, g2d);
+                            }
+                        }
+                        if let Some(start) = env.obj_start {
+                            let shape = match env.business {
+                                Business::Construct => env.new_shape(),
+                                Business::Range => Shape::Rect,
+                                _ => Shape::Segment,
+                            };
+                            draw_obstacle(color, 3., &Segment { src: start, dst: Point { x: mx, y: my, }, }, shape, world, g2d);
                         } else {
                             ellipse(
                                 color,
                                 [mx - 5., my - 5., 10., 10.,],
-                                context.transform,
+                                world,
                                 g2d,
                             );
+                            // hovering readout, skipped for huge scenes to keep mouse moves cheap
+                            if let Business::Neighbours = env.business {
+                                if active_shapes.len() <= HOVER_NEAREST_MAX_OBSTACLES {
+                                    if let Some(neighbour) = env.query_or_report(tree.nearest_one(Point { x: mx, y: my, })) {
+                                        text::Text::new_color(color, 12).draw(
+                                            &format!("{:.1}", neighbour.dist),
+                                            &mut glyphs,
+                                            &context.draw_state,
+                                            world.trans(mx + 8., my - 8.),
+                                            g2d
+                                        ).map_err(PistonError::DrawText)?;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    // draw scale bar
+                    if env.show_scale_bar {
+                        let [width, height] = context.get_view_size();
+                        let world_per_pixel = (env.screen_to_world(SCALE_BAR_WIDTH, 0.).x - env.screen_to_world(0., 0.).x) / SCALE_BAR_WIDTH;
+                        let units = round_scale(SCALE_BAR_WIDTH * world_per_pixel);
+                        let bar_width = units / world_per_pixel;
+                        let (right, base) = (width - 20., height - 20.);
+                        let color = [0.75, 0.75, 0.75, 1.0];
+                        line(color, 1., [right - bar_width, base, right, base], context.transform, g2d);
+                        line(color, 1., [right - bar_width, base - 4., right - bar_width, base + 4.], context.transform, g2d);
+                        line(color, 1., [right, base - 4., right, base + 4.], context.transform, g2d);
+                        text::Text::new_color(color, 12).draw(
+                            &format!("{} units", units),
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(right - bar_width, base - 8.),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                    }
+                    // draw console
+                    if let Some(ref console) = env.console {
+                        let [width, height] = context.get_view_size();
+                        piston_window::rectangle([0.1, 0.1, 0.1, 0.9], [0., height - 54., width, 20.], context.transform, g2d);
+                        text::Text::new_color([0.9, 0.9, 0.9, 1.0], 14).draw(
+                            &format!("> {}_", console.input),
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(5.0, height - 39.0),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                        if let Some(ref status) = console.status {
+                            text::Text::new_color([1.0, 0.5, 0.25, 1.0], 12).draw(
+                                status,
+                                &mut glyphs,
+                                &context.draw_state,
+                                context.transform.trans(5.0, height - 60.0),
+                                g2d
+                            ).map_err(PistonError::DrawText)?;
+                        }
+                    }
+                    // draw notice
+                    if let (None, Some(notice)) = (&env.console, env.current_notice()) {
+                        text::Text::new_color([1.0, 0.85, 0.4, 1.0], 13).draw(
+                            notice,
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(5.0, context.get_view_size()[1] - 38.0),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                    }
+                    // draw help overlay
+                    if env.show_help {
+                        let [width, _] = context.get_view_size();
+                        let (left, top) = (40., CONSOLE_HEIGHT as f64 + 8.);
+                        piston_window::rectangle(
+                            [0.05, 0.05, 0.05, 0.85],
+                            [left - 10., top, width - 2. * (left - 10.), (KEY_BINDINGS.len() as f64 + 2.) * 16.],
+                            context.transform,
+                            g2d,
+                        );
+                        let mode = format!("{} mode, clicks are ignored until the help is hidden", env.business.name());
+                        let header = ("Mode", mode.as_str());
+                        for (i, &(key, action)) in iter::once(&header).chain(KEY_BINDINGS.iter()).enumerate() {
+                            let baseline = top + 20. + i as f64 * 16.;
+                            for &(text, x, color) in [(key, left, [1.0, 0.85, 0.4, 1.0]), (action, left + 110., [0.9, 0.9, 0.9, 1.0])].iter() {
+                                text::Text::new_color(color, 13).draw(
+                                    text,
+                                    &mut glyphs,
+                                    &context.draw_state,
+                                    context.transform.trans(x, baseline),
+                                    g2d
+                                ).map_err(PistonError::DrawText)?;
+                            }
                         }
                     }
                     // draw menu
                     text::Text::new_color([0.0, 1.0, 0.0, 1.0], 16).draw(
-                        &env.business.info_line(),
+                        &env.info_line(),
                         &mut glyphs,
                         &context.draw_state,
                         context.transform.trans(5.0, 20.0),
                         g2d
                     ).map_err(PistonError::DrawText)?;
+                    // draw scene stats
+                    text::Text::new_color([0.5, 0.5, 0.5, 1.0], 12).draw(
+                        &format!("{} | weight: {}", stats_line, env.weight),
+                        &mut glyphs,
+                        &context.draw_state,
+                        context.transform.trans(5.0, context.get_view_size()[1] - 6.0),
+                        g2d
+                    ).map_err(PistonError::DrawText)?;
+                    // draw frame rate
+                    if let (true, Some(frame_time)) = (env.show_fps, env.frame_timer.mean()) {
+                        let frame_ms = frame_time.as_secs() as f64 * 1000. + frame_time.subsec_nanos() as f64 / 1_000_000.;
+                        text::Text::new_color([0.75, 0.75, 0.75, 1.0], 12).draw(
+                            &format!("{:.1} fps | {:.2} ms", 1000. / frame_ms, frame_ms),
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(context.get_view_size()[0] - 130.0, CONSOLE_HEIGHT as f64 + 16.0),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                    }
+                    // draw background build indicator
+                    if pending_build.is_some() {
+                        text::Text::new_color([1.0, 0.85, 0.4, 1.0], 12).draw(
+                            "rebuilding...",
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(context.get_view_size()[0] - 260.0, CONSOLE_HEIGHT as f64 + 64.0),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                    }
+                    // draw cursor coordinates
+                    if let (true, Some(readout)) = (env.show_coords, env.cursor_readout()) {
+                        text::Text::new_color([0.75, 0.75, 0.75, 1.0], 12).draw(
+                            &readout,
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(context.get_view_size()[0] - 260.0, CONSOLE_HEIGHT as f64 + 32.0),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                    }
+                    // draw narration
+                    if let Some(ref narrator) = env.narrator {
+                        for (i, line) in narrator.lines().enumerate() {
+                            text::Text::new_color([0.5, 0.75, 1.0, 1.0], 14).draw(
+                                line,
+                                &mut glyphs,
+                                &context.draw_state,
+                                context.transform.trans(5.0, (CONSOLE_HEIGHT + 16 + (i as u32 * 18)) as f64),
+                                g2d
+                            ).map_err(PistonError::DrawText)?;
+                        }
+                    }
+                    // draw error banner
+                    if let Some(message) = env.current_error() {
+                        let [width, _] = context.get_view_size();
+                        piston_window::rectangle([0.6, 0.0, 0.0, 0.9], [0., 0., width, CONSOLE_HEIGHT as f64], context.transform, g2d);
+                        text::Text::new_color([1.0, 1.0, 1.0, 1.0], 14).draw(
+                            message,
+                            &mut glyphs,
+                            &context.draw_state,
+                            context.transform.trans(5.0, CONSOLE_HEIGHT as f64 - 10.0),
+                            g2d
+                        ).map_err(PistonError::DrawText)?;
+                    }
 
                     Ok(())
                 });
                 if let Some(result) = maybe_result {
-                    let () = result.map_err(Error::Piston)?;
+                    match result {
+                        Ok(()) =>
+                            (),
+                        // the rest of the frame is lost but the next one may well succeed
+                        Err(PistonError::DrawText(e)) => {
+                            error!("failed to draw text: {:?}", e);
+                            env.report_error("failed to draw text, see the log for details".to_string());
+                        },
+                        Err(e) =>
+                            return Err(Error::Piston(e)),
+                    }
+                    env.frame_timer.tick(Instant::now());
+                    if let Some(ref mut recorder) = frame_recorder {
+                        recorder.capture(&mut window);
+                    }
+                    if env.screenshot_pending {
+                        env.screenshot_pending = false;
+                        let path = capture::screenshot_path();
+                        match capture::save_screenshot(&mut window, &path) {
+                            Ok(()) =>
+                                env.notify(format!("saved screenshot to {}", path.display())),
+                            Err(e) => {
+                                error!("failed to save screenshot: {:?}", e);
+                                env.report_error(format!("failed to save screenshot to {}", path.display()));
+                            },
+                        }
+                    }
                 }
 
                 match event {
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Backquote), state: ButtonState::Release, .. })) =>
+                        env.toggle_console(),
+                    Event::Input(Input::Text(ref text)) =>
+                        if let Some(ref mut console) = env.console {
+                            console.type_text(text);
+                        },
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::LShift), state, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::RShift), state, .. })) =>
+                        env.rect_modifier = matches!(state, ButtonState::Press),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::LCtrl), state, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::RCtrl), state, .. })) =>
+                        env.circle_modifier = matches!(state, ButtonState::Press),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::LAlt), state, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::RAlt), state, .. })) =>
+                        env.set_angle_modifier(matches!(state, ButtonState::Press)),
+                    // other keys are not handled while the console is focused
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(key), state, .. })) if env.console.is_some() =>
+                        if let (ButtonState::Press, Some(command)) = (state, env.console_key(key)) {
+                            match command {
+                                console::Command::Clear =>
+                                    break Box::new(|scene| env.clear_scene(scene)),
+                                console::Command::Save(path) =>
+                                    env.save_scene(path.as_ref().unwrap_or(&save_path), &scene),
+                                console::Command::Mode(business) =>
+                                    env.set_mode(business),
+                                console::Command::Nearest(limit) =>
+                                    env.neighbours_limit = limit,
+                                console::Command::Width(width) =>
+                                    env.width = width,
+                                console::Command::Group(group) if (1 ..= OBSTACLE_GROUPS).contains(&group) =>
+                                    env.group = group - 1,
+                                console::Command::Group(..) =>
+                                    env.console_status(format!("groups are numbered from 1 to {}", OBSTACLE_GROUPS)),
+                                console::Command::Only(Some(group)) if (1 ..= OBSTACLE_GROUPS).contains(&group) =>
+                                    env.nearest_group = Some(group - 1),
+                                console::Command::Only(Some(..)) =>
+                                    env.console_status(format!("groups are numbered from 1 to {}", OBSTACLE_GROUPS)),
+                                console::Command::Only(None) =>
+                                    env.nearest_group = None,
+                            }
+                        },
                     Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Q), state: ButtonState::Release, .. })) =>
                         return Ok(()),
                     Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::C), state: ButtonState::Release, .. })) =>
-                        break Box::new(|obstacles| {
-                            obstacles.clear();
-                            env.reset_cursor();
-                        }),
+                        break Box::new(|scene| env.clear_scene(scene)),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Z), state: ButtonState::Release, .. })) =>
+                        break Box::new(|scene| env.undo(scene)),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Y), state: ButtonState::Release, .. })) =>
+                        break Box::new(|scene| env.redo(scene)),
                     Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::M), state: ButtonState::Release, .. })) =>
                         env.toggle_mode(),
-                    Event::Input(Input::Move(Motion::MouseCursor(x, y))) =>
-                        env.set_cursor(x, y),
-                    Event::Input(Input::Cursor(false)) =>
-                        env.reset_cursor(),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Space), state: ButtonState::Release, .. })) =>
+                        env.toggle_pinned_probe(),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Up), state: ButtonState::Release, .. })) =>
+                        env.explain_scroll = env.explain_scroll.saturating_sub(1),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Down), state: ButtonState::Release, .. })) =>
+                        env.explain_scroll += 1,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::LeftBracket), state: ButtonState::Release, .. })) =>
+                        env.scale_weight(0.5),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::RightBracket), state: ButtonState::Release, .. })) =>
+                        env.scale_weight(2.),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Equals), state: ButtonState::Release, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::NumPadPlus), state: ButtonState::Release, .. })) =>
+                        env.step_neighbours_limit(true),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Minus), state: ButtonState::Release, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::NumPadMinus), state: ButtonState::Release, .. })) =>
+                        env.step_neighbours_limit(false),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Period), state: ButtonState::Release, .. })) =>
+                        env.step_neighbour(true),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Comma), state: ButtonState::Release, .. })) =>
+                        env.step_neighbour(false),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::S), state: ButtonState::Release, .. })) =>
+                        env.save_scene(&save_path, &scene),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::E), state: ButtonState::Release, .. })) => {
+                        let size = window.size();
+                        let view = Bound {
+                            lt: env.screen_to_world(0., 0.),
+                            rb: env.screen_to_world(size.width as f64, size.height as f64),
+                        };
+                        let obstacles: Vec<_> = active_shapes.iter().map(|&i| (scene.obstacles[i], scene.meta[i].shape)).collect();
+                        let svg = svg::render(&obstacles, &visual_cutter.cuts, &view, env.background, &theme);
+                        env.export_svg(Path::new(SVG_EXPORT_FILE), &svg);
+                    },
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::I), state: ButtonState::Release, .. })) =>
+                        env.screenshot_pending = true,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::G), state: ButtonState::Release, .. })) =>
+                        env.toggle_ghost_layer(),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::W), state: ButtonState::Release, .. })) =>
+                        env.toggle_polyline(),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::T), state: ButtonState::Release, .. })) =>
+                        env.toggle_snap(),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::K), state: ButtonState::Release, .. })) =>
+                        env.show_grid = !env.show_grid,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::F), state: ButtonState::Release, .. })) =>
+                        env.show_fps = !env.show_fps,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::O), state: ButtonState::Release, .. })) =>
+                        env.show_coords = !env.show_coords,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::A), state: ButtonState::Release, .. })) =>
+                        env.show_leaf_region = !env.show_leaf_region,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::R), state: ButtonState::Release, .. })) =>
+                        break Box::new(|scene| env.randomize_scene(scene)),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::D), state: ButtonState::Press, .. })) =>
+                        env.delete_pending = true,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::D), state: ButtonState::Release, .. })) => {
+                        env.delete_pending = false;
+                        // nothing is deleted while the cursor is off the scene
+                        let nearest = env.cursor.map(|cursor| tree.nearest_one(cursor)).unwrap_or(Ok(None));
+                        if let Some(index) = env.query_or_report(nearest).map(|neighbour| neighbour.shape) {
+                            let env = &mut env;
+                            break Box::new(move |scene| env.delete_obstacle(scene, index));
+                        }
+                    },
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Return), state: ButtonState::Release, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::NumPadEnter), state: ButtonState::Release, .. })) if env.polyline.is_some() =>
+                        break Box::new(|scene| env.commit_polyline(scene)),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::P), state: ButtonState::Release, .. })) if !scene.ghosts.is_empty() =>
+                        break Box::new(|scene| {
+                            let promoted = scene.promote_ghosts();
+                            env.dirty |= promoted > 0;
+                            env.narrate(format!("promoted {} ghosts to obstacles", promoted));
+                        }),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::L), state: ButtonState::Release, .. })) =>
+                        env.toggle_background(),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::H), state: ButtonState::Release, .. })) =>
+                        env.show_help = !env.show_help,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::B), state: ButtonState::Release, .. })) =>
+                        env.show_scale_bar = !env.show_scale_bar,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(key @ Key::F1), state: ButtonState::Release, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(key @ Key::F2), state: ButtonState::Release, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(key @ Key::F3), state: ButtonState::Release, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(key @ Key::F4), state: ButtonState::Release, .. })) |
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(key @ Key::F5), state: ButtonState::Release, .. })) =>
+                    {
+                        env.toggle_group(key as usize - Key::F1 as usize);
+                        break Box::new(|_| ());
+                    },
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::F12), state: ButtonState::Release, .. })) =>
+                        env.show_nearest_regions = !env.show_nearest_regions,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::N), state: ButtonState::Release, .. })) =>
+                        env.show_distance_field = !env.show_distance_field,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::X), state: ButtonState::Release, .. })) =>
+                        env.show_crossing_pairs = !env.show_crossing_pairs,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::J), state: ButtonState::Release, .. })) =>
+                        env.show_labels = !env.show_labels,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::V), state: ButtonState::Release, .. })) =>
+                        env.show_pruning = !env.show_pruning,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::U), state: ButtonState::Release, .. })) =>
+                        env.show_brute_force = !env.show_brute_force,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::F6), state: ButtonState::Release, .. })) =>
+                        env.adaptive_gradient = !env.adaptive_gradient,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::F7), state: ButtonState::Release, .. })) =>
+                        env.color_by_length = !env.color_by_length,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::F8), state: ButtonState::Release, .. })) =>
+                        env.show_probe_grid = !env.show_probe_grid,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::F9), state: ButtonState::Release, .. })) =>
+                        if let Some(ref mut recorder) = frame_recorder {
+                            recorder.toggle();
+                        },
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::F10), state: ButtonState::Release, .. })) =>
+                        env.fragments_outline = !env.fragments_outline,
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::F11), state: ButtonState::Release, .. })) =>
+                        env.show_corners_nearest = !env.show_corners_nearest,
+                    Event::Input(Input::Move(Motion::MouseScroll(_, dy))) =>
+                        env.zoom_at_mouse(dy),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::Home), state: ButtonState::Release, .. })) =>
+                        env.reset_view(),
+                    Event::Input(Input::Move(Motion::MouseCursor(x, y))) => {
+                        env.set_cursor(x, y);
+                        if env.grabbed.is_some() {
+                            break Box::new(|scene| env.drag_endpoint(scene));
+                        }
+                    },
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Middle), state: ButtonState::Press, .. })) =>
+                        env.start_pan(),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Middle), state: ButtonState::Release, .. })) =>
+                        env.stop_pan(),
+                    Event::Input(Input::Cursor(false)) => {
+                        env.stop_pan();
+                        env.reset_cursor();
+                    },
+                    // keep clicks from constructing anything behind the help overlay, a drag in progress may still end
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Left), .. })) if env.show_help && env.grabbed.is_none() =>
+                        (),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Left), state: ButtonState::Press, .. })) =>
+                        env.grab_endpoint(&scene),
+                    Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Left), state: ButtonState::Release, .. })) if env.grabbed.is_some() =>
+                        break Box::new(|scene| env.release_endpoint(scene)),
                     Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Left), state: ButtonState::Release, .. })) =>
-                        break Box::new(|obstacles| env.toggle_obj(obstacles)),
+                        break Box::new(|scene| env.toggle_obj(scene)),
                     Event::Input(Input::Resize(width, height)) =>
                         env.reset(width, height),
+                    Event::Loop(Loop::Update(..)) =>
+                        env.expire_pulses(&collide_cache),
                     _ =>
                         (),
                 }
+
+                if let Some(ref rx) = pending_build {
+                    match rx.try_recv() {
+                        Ok(build) => {
+                            pending_build = None;
+                            finished_build = Some(build);
+                            break Box::new(|_| ());
+                        },
+                        Err(mpsc::TryRecvError::Empty) =>
+                            (),
+                        Err(mpsc::TryRecvError::Disconnected) => {
+                            error!("background tree build thread quit without a result");
+                            env.report_error("failed to rebuild the tree, see the log for details".to_string());
+                            pending_build = None;
+                            full_build = None;
+                        },
+                    }
+                }
+
+                if let Some(ref rx) = watch_rx {
+                    let incoming: Vec<_> = rx.try_iter().collect();
+                    if !incoming.is_empty() {
+                        env.narrate(format!("received {} obstacles from watch", incoming.len()));
+                        env.dirty = true;
+                        break Box::new(move |scene| {
+                            for &segment in incoming.iter() {
+                                scene.push(segment, Default::default());
+                            }
+                        });
+                    }
+                }
             }
         };
-        action(&mut obstacles);
+        action(&mut scene);
+    }
+}
+
+/// Scene from the `--load` file, or the `--scene` one when it exists, empty otherwise.
+/// Also returns the message to show when the `--load` file could not be read.
+fn load_scene(matches: &clap::ArgMatches) -> Result<(Scene, Option<String>), Error> {
+    let mut warning = None;
+    // an unreadable --load file is not fatal unlike the --scene one
+    let loaded = match (matches.value_of("load"), matches.value_of("scene").map(PathBuf::from)) {
+        (Some(path), _) =>
+            scene_file::load(path)
+                .map(|layers| Some((PathBuf::from(path), layers)))
+                .or_else(|e| {
+                    error!("failed to load scene {:?}: {:?}, starting with an empty scene", path, e);
+                    warning = Some(format!("failed to load {}, starting with an empty scene", path));
+                    Ok(None)
+                }),
+        (None, Some(ref path)) if path.exists() =>
+            scene_file::load(path).map(|layers| Some((path.clone(), layers))),
+        (None, _) =>
+            Ok(None),
+    };
+    let mut scene = Scene::new();
+    if let Some((path, layers)) = loaded.map_err(Error::SceneFile)? {
+        // files older than version 5 have no metadata, only the layer tells the shape
+        let shaped = |segments: Vec<Segment>, shape| segments.into_iter().map(move |segment| (segment, ObstacleMeta { shape, ..Default::default() }));
+        let mut obstacles: Vec<_> = shaped(layers.obstacles, Shape::Segment)
+            .chain(shaped(layers.rects, Shape::Rect))
+            .chain(shaped(layers.circles, Shape::Circle))
+            .collect();
+        if !layers.meta.is_empty() {
+            for ((_, meta), &stored) in obstacles.iter_mut().zip(layers.meta.iter()) {
+                *meta = stored;
+            }
+        }
+        if matches.is_present("dedup") {
+            // only obstacles of the same shape duplicate each other
+            let mut removed = 0;
+            for &shape in [Shape::Segment, Shape::Rect, Shape::Circle].iter() {
+                let segments: Vec<_> = obstacles.iter().filter(|(_, meta)| meta.shape == shape).map(|&(segment, _)| segment).collect();
+                let mut duplicates = duplicate_obstacles(&segments, POINT_EPSILON).into_iter();
+                obstacles.retain(|(_, meta)| meta.shape != shape || !duplicates.next().unwrap_or(false));
+                removed += segments.len() - obstacles.iter().filter(|(_, meta)| meta.shape == shape).count();
+            }
+            info!("removed {} duplicate obstacles from {:?}", removed, path);
+        }
+        for (segment, meta) in obstacles {
+            scene.push(segment, meta);
+        }
+        scene.ghosts = layers.ghosts;
+        info!("loaded {} obstacles and {} ghosts from {:?}", scene.obstacles.len(), scene.ghosts.len(), path);
+    }
+    Ok((scene, warning))
+}
+
+/// Keeps a window side given on the command line within `MIN_SCREEN_SIDE ..= MAX_SCREEN_SIDE`.
+fn screen_side_clamped(name: &str, side: u32) -> u32 {
+    let clamped = side.clamp(MIN_SCREEN_SIDE, MAX_SCREEN_SIDE);
+    if clamped != side {
+        warn!("--{} {} is out of {} ..= {}, using {}", name, side, MIN_SCREEN_SIDE, MAX_SCREEN_SIDE, clamped);
+    }
+    clamped
+}
+
+/// Parses `R,G,B` components from 0 to 255 into an opaque color.
+fn parse_color(value: &str) -> Option<[f32; 4]> {
+    let components: Vec<_> = value.split(',')
+        .map(|component| component.trim().parse::<u8>().ok().map(|c| c as f32 / 255.))
+        .collect::<Option<_>>()?;
+    match components[..] {
+        [r, g, b] => Some([r, g, b, 1.0]),
+        _ => None,
+    }
+}
+
+/// Black for light colors and white for dark ones, used for text and highlights drawn over the background.
+fn contrast_color(color: [f32; 4]) -> [f32; 4] {
+    let luma = 0.299 * color[0] + 0.587 * color[1] + 0.114 * color[2];
+    if luma > 0.5 { [0.0, 0.0, 0.0, 1.0] } else { [1.0, 1.0, 1.0, 1.0] }
+}
+
+/// Fades from `flash` back to `base` color during `COLLISION_PULSE_MS` since the pulse start.
+fn pulse_color(base: [f32; 4], flash: [f32; 4], elapsed: Duration) -> [f32; 4] {
+    let elapsed_ms = elapsed.as_secs() as f32 * 1000. + elapsed.subsec_millis() as f32;
+    let t = (elapsed_ms / COLLISION_PULSE_MS as f32).min(1.);
+    let mut color = base;
+    for (c, (&b, &f)) in color.iter_mut().zip(base.iter().zip(flash.iter())) {
+        *c = f + (b - f) * t;
+    }
+    color
+}
+
+fn describe_volume(volume: &Volume) -> String {
+    match *volume {
+        Volume::Aabb(Bound { lt, rb, }) =>
+            format!("[{:.1}, {:.1}]-[{:.1}, {:.1}]", lt.x, lt.y, rb.x, rb.y),
+        Volume::Circle(circle::BoundingCircle { center, radius, }) =>
+            format!("({:.1}, {:.1}) r {:.1}", center.x, center.y, radius),
+    }
+}
+
+/// One text line per collision: shape index, both fragments and the actual crossing point when there is one.
+fn explain_collisions(collisions: &[Collision], obstacles: &[Segment], probe: &Segment) -> Vec<String> {
+    collisions.iter()
+        .map(|collision| {
+            let crossing = match segment_intersection(&obstacles[collision.shape], probe) {
+                Some(p) => format!("({:.1}, {:.1})", p.x, p.y),
+                None => "none".to_string(),
+            };
+            format!(
+                "#{} shape {} needle {} at {}",
+                collision.shape,
+                describe_volume(&collision.shape_fragment),
+                describe_volume(&collision.needle_fragment),
+                crossing,
+            )
+        })
+        .collect()
+}
+
+/// Index of the nearest obstacle to the center of every `cell` sized square covering the `width` x `height`
+/// area starting at `lt`, row by row. Forms an approximate Voronoi diagram of the obstacles.
+fn nearest_regions(tree: &SceneTree, lt: Point, width: f64, height: f64, cell: f64) -> Result<Vec<Option<usize>>, Error> {
+    let (columns, rows) = ((width / cell).ceil() as usize, (height / cell).ceil() as usize);
+    (0 .. rows)
+        .flat_map(|row| (0 .. columns).map(move |column| (column, row)))
+        .map(|(column, row)| {
+            let center = Point { x: lt.x + (column as f64 + 0.5) * cell, y: lt.y + (row as f64 + 0.5) * cell, };
+            tree.nearest_one(center).map(|maybe_neighbour| maybe_neighbour.map(|neighbour| neighbour.shape))
+        })
+        .collect()
+}
+
+/// Distance to the nearest obstacle from the center of every `cell` sized square, laid out as in `nearest_regions`.
+fn distance_field(tree: &SceneTree, lt: Point, width: f64, height: f64, cell: f64) -> Result<Vec<Option<f64>>, Error> {
+    let (columns, rows) = ((width / cell).ceil() as usize, (height / cell).ceil() as usize);
+    (0 .. rows)
+        .flat_map(|row| (0 .. columns).map(move |column| (column, row)))
+        .map(|(column, row)| {
+            let center = Point { x: lt.x + (column as f64 + 0.5) * cell, y: lt.y + (row as f64 + 0.5) * cell, };
+            tree.nearest_one(center).map(|maybe_neighbour| maybe_neighbour.map(|neighbour| neighbour.dist))
+        })
+        .collect()
+}
+
+/// Heatmap color going from red right at an obstacle to blue at `max_dist` away.
+fn heat_color(dist: f64, max_dist: f64) -> [f32; 4] {
+    let t = if max_dist > 0. { (dist / max_dist).min(1.) as f32 } else { 0. };
+    [1. - t, 0.25 * (1. - t), t, 0.45]
+}
+
+/// Whether an obstacle with the `bound` is large enough on screen at `zoom` to carry its index label.
+fn label_worthy(bound: &Bound, zoom: f64) -> bool {
+    (bound.rb.x - bound.lt.x).max(bound.rb.y - bound.lt.y) * zoom >= LABEL_MIN_SIZE
+}
+
+/// Stable distinct looking color for an obstacle index: hues are spread with the golden ratio.
+fn index_color(index: usize, alpha: f32) -> [f32; 4] {
+    let hue = (index as f64 * 0.618_033_988_75).fract() * 6.;
+    let x = (1. - (hue % 2. - 1.).abs()) as f32;
+    let (r, g, b) = match hue as usize {
+        0 => (1., x, 0.),
+        1 => (x, 1., 0.),
+        2 => (0., 1., x),
+        3 => (0., x, 1.),
+        4 => (x, 0., 1.),
+        _ => (1., 0., x),
+    };
+    [r, g, b, alpha]
+}
+
+/// Lays a diagonal probe across each cell of a regular grid with `spacing` step over the `width` x `height` area starting at `lt`.
+fn probe_grid(lt: Point, width: f64, height: f64, spacing: f64) -> Vec<Segment> {
+    let (columns, rows) = ((width / spacing).ceil() as usize, (height / spacing).ceil() as usize);
+    (0 .. rows)
+        .flat_map(|row| (0 .. columns).map(move |column| (column, row)))
+        .map(|(column, row)| {
+            let src = Point { x: lt.x + column as f64 * spacing, y: lt.y + row as f64 * spacing, };
+            Segment { src, dst: Point { x: src.x + spacing, y: src.y + spacing, }, }
+        })
+        .collect()
+}
+
+fn coverage_percent(hit: usize, total: usize) -> f64 {
+    if total == 0 { 0. } else { hit as f64 * 100. / total as f64 }
+}
+
+/// Colors obstacles from blue for the shortest one to red for the longest one.
+fn length_gradient(obstacles: &[Segment]) -> Vec<[f32; 4]> {
+    let lengths: Vec<_> = obstacles.iter()
+        .map(Segment::length)
+        .collect();
+    let min = lengths.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = lengths.iter().cloned().fold(0., f64::max);
+    lengths.iter()
+        .map(|&length| {
+            let t = if max > min { ((length - min) / (max - min)) as f32 } else { 0. };
+            [t, 0.25 * (1. - t), 1. - t, 1.0]
+        })
+        .collect()
+}
+
+/// Rounds `length` down to the nearest 1, 2 or 5 times a power of ten.
+fn round_scale(length: f64) -> f64 {
+    let magnitude = 10f64.powf(length.log10().floor());
+    let mantissa = length / magnitude;
+    let nice = if mantissa >= 5. { 5. } else if mantissa >= 2. { 2. } else { 1. };
+    nice * magnitude
+}
+
+fn draw_volume(color: [f32; 4], volume: &Volume, outline: bool, transform: piston_window::math::Matrix2d, g2d: &mut G2d) {
+    match *volume {
+        Volume::Aabb(ref bound) =>
+            draw_bound(color, bound, outline, transform, g2d),
+        Volume::Circle(circle::BoundingCircle { center, radius, }) => {
+            let rect = [center.x - radius, center.y - radius, radius * 2., radius * 2.];
+            if outline {
+                piston_window::Ellipse::new_border(color, 0.5).draw(rect, &Default::default(), transform, g2d);
+            } else {
+                piston_window::ellipse(color, rect, transform, g2d);
+            }
+        },
+    }
+}
+
+fn draw_obstacle(color: [f32; 4], radius: f64, segment: &Segment, shape: Shape, transform: piston_window::math::Matrix2d, g2d: &mut G2d) {
+    match shape {
+        Shape::Segment =>
+            piston_window::line(color, radius, [segment.src.x, segment.src.y, segment.dst.x, segment.dst.y], transform, g2d),
+        Shape::Rect => {
+            let Bound { lt, rb, } = get_bounding_volume(segment);
+            piston_window::Rectangle::new_border(color, radius / 2.)
+                .draw([lt.x, lt.y, rb.x - lt.x, rb.y - lt.y], &Default::default(), transform, g2d);
+        },
+        Shape::Circle => {
+            let Bound { lt, rb, } = get_circle_obstacle_bound(segment);
+            piston_window::Ellipse::new_border(color, radius / 2.)
+                .draw([lt.x, lt.y, rb.x - lt.x, rb.y - lt.y], &Default::default(), transform, g2d);
+        },
+    }
+}
+
+fn draw_dashed(color: [f32; 4], radius: f64, segment: &Segment, transform: piston_window::math::Matrix2d, g2d: &mut G2d) {
+    let direction = match segment.direction() {
+        Some(direction) => direction,
+        None => return,
+    };
+    let length = segment.length();
+    let dashes = (length / (GHOST_DASH_LENGTH * 2.)).ceil() as usize;
+    for i in 0 .. dashes {
+        let from = i as f64 * GHOST_DASH_LENGTH * 2.;
+        let to = (from + GHOST_DASH_LENGTH).min(length);
+        piston_window::line(
+            color,
+            radius,
+            [
+                segment.src.x + direction.x * from,
+                segment.src.y + direction.y * from,
+                segment.src.x + direction.x * to,
+                segment.src.y + direction.y * to,
+            ],
+            transform,
+            g2d,
+        );
+    }
+}
+
+fn draw_bound(color: [f32; 4], bound: &Bound, outline: bool, transform: piston_window::math::Matrix2d, g2d: &mut G2d) {
+    use piston_window::{line, rectangle};
+    if outline {
+        let Bound { lt: Point { x: l, y: t, }, rb: Point { x: r, y: b, }, } = *bound;
+        line(color, 1., [l, t, r, t], transform, g2d);
+        line(color, 1., [r, t, r, b], transform, g2d);
+        line(color, 1., [r, b, l, b], transform, g2d);
+        line(color, 1., [l, b, l, t], transform, g2d);
+    } else {
+        rectangle(color, [bound.lt.x, bound.lt.y, bound.rb.x - bound.lt.x, bound.rb.y - bound.lt.y], transform, g2d);
     }
 }
 
@@ -292,17 +1876,64 @@ enum Business {
     Construct,
     Collide,
     Neighbours,
+    /// Existing obstacles are reshaped by dragging their ends.
+    Edit,
+    /// Obstacles overlapping a rectangle spanned by two clicks are reported.
+    Range,
+    /// Only the first obstacle hit by a ray from the first click through the cursor is reported.
+    Ray,
 }
 
 impl Business {
     fn info_line(&self) -> String {
         match self {
             &Business::Construct =>
-                "[ constructing ] <M> switch to collide mode, <C> to clear or <Q> to exit".to_string(),
+                "[ constructing ] <M> switch to collide mode, <C> to clear, <H> help or <Q> to exit".to_string(),
             &Business::Collide =>
-                "[ colliding ] <M> switch to neighbours mode, <C> to clear or <Q> to exit".to_string(),
+                "[ colliding ] <M> switch to neighbours mode, <C> to clear, <H> help or <Q> to exit".to_string(),
             &Business::Neighbours =>
-                "[ finding neighbours ] <M> switch to construct mode, <C> to clear or <Q> to exit".to_string(),
+                "[ finding neighbours ] <M> switch to edit mode, <,>/<.> step, <C> to clear, <H> help or <Q> to exit".to_string(),
+            &Business::Edit =>
+                "[ editing ] drag segment ends around, <M> switch to range mode, <C> to clear, <H> help or <Q> to exit".to_string(),
+            &Business::Range =>
+                "[ range query ] <M> switch to ray mode, <C> to clear, <H> help or <Q> to exit".to_string(),
+            &Business::Ray =>
+                "[ casting ray ] <M> switch to construct mode, <C> to clear, <H> help or <Q> to exit".to_string(),
+        }
+    }
+
+    /// Name accepted by `--mode` and the console `mode` command.
+    fn name(&self) -> &'static str {
+        match *self {
+            Business::Construct => "construct",
+            Business::Collide => "collide",
+            Business::Neighbours => "neighbours",
+            Business::Edit => "edit",
+            Business::Range => "range",
+            Business::Ray => "ray",
+        }
+    }
+}
+
+impl FromStr for Business {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Business, ()> {
+        match s {
+            "construct" =>
+                Ok(Business::Construct),
+            "collide" =>
+                Ok(Business::Collide),
+            "neighbours" =>
+                Ok(Business::Neighbours),
+            "edit" =>
+                Ok(Business::Edit),
+            "range" =>
+                Ok(Business::Range),
+            "ray" =>
+                Ok(Business::Ray),
+            _ =>
+                Err(()),
         }
     }
 }
@@ -311,41 +1942,559 @@ struct Env {
     business: Business,
     cursor: Option<Point>,
     obj_start: Option<Point>,
+    narrator: Option<Narrator>,
+    fragments_outline: bool,
+    show_corners_nearest: bool,
+    show_scale_bar: bool,
+    show_help: bool,
+    background: [f32; 4],
+    /// Background swapped in by the light/dark toggle.
+    alt_background: Option<[f32; 4]>,
+    ghost_layer: bool,
+    show_probe_grid: bool,
+    show_nearest_regions: bool,
+    show_distance_field: bool,
+    show_crossing_pairs: bool,
+    show_labels: bool,
+    /// Cell size of the distance heatmap, see `distance_field`.
+    distance_field_cell: f64,
+    show_pruning: bool,
+    /// Checks the collide probe against every obstacle too, slow on large scenes.
+    show_brute_force: bool,
+    color_by_length: bool,
+    collision_pulses: Option<HashMap<usize, Instant>>,
+    weight: f64,
+    /// Capsule radius of new segment obstacles.
+    width: f64,
+    pinned_probe: Option<Segment>,
+    explain_scroll: usize,
+    neighbour_step: Option<usize>,
+    neighbours_limit: Option<usize>,
+    adaptive_gradient: bool,
+    /// Group assigned to newly constructed obstacles.
+    group: usize,
+    /// Groups which are drawn and take part in the tree.
+    active_groups: HashSet<usize>,
+    /// Neighbours mode reports obstacles of this group only.
+    nearest_group: Option<usize>,
+    console: Option<console::Console>,
+    /// Raised whenever the obstacles in the tree change, see `take_dirty`.
+    dirty: bool,
+    notice: Option<(String, Instant)>,
+    /// Recoverable error shown in a red banner at the top of the window, see `report_error`.
+    error_message: Option<(String, Instant)>,
+    /// Next drawn frame is saved to a PNG file.
+    screenshot_pending: bool,
+    /// <Shift> is held down.
+    rect_modifier: bool,
+    /// <Ctrl> is held down.
+    circle_modifier: bool,
+    /// <Alt> is held down, see `constrain_angle`.
+    angle_modifier: bool,
+    /// Points of the polyline being constructed, `None` unless polyline construction is on.
+    polyline: Option<Vec<Point>>,
+    /// Seed and obstacles count of the next random scene generated with <R>.
+    random_scene: (u64, usize),
+    /// <D> is held down, so the obstacle it is going to delete is highlighted.
+    delete_pending: bool,
+    /// Obstacle end being dragged in edit mode.
+    grabbed: Option<(usize, Endpoint)>,
+    /// The grabbed end along with the polyline ends joined to it, see `joined_endpoints`.
+    drag: Option<DragEdit>,
+    /// Finished end drags, undone once every obstacle constructed after them is.
+    drags: Vec<DragEdit>,
+    /// Cursor is rounded to the nearest multiple of `snap_size`.
+    snap_to_grid: bool,
+    snap_size: f64,
+    show_grid: bool,
+    show_fps: bool,
+    /// Cursor world coordinates readout, see `cursor_readout`.
+    show_coords: bool,
+    frame_timer: FrameTimer,
+    show_leaf_region: bool,
+    /// Obstacles count found by the last range query drawn.
+    range_hits: Option<usize>,
+    /// Undone obstacles and end drags, dropped as soon as a new one is made.
+    redo: Vec<Undone>,
+    /// Window size as of the last resize.
+    screen_size: (u32, u32),
+    camera: Point,
+    /// Screen pixels per world unit.
+    zoom: f64,
+    mouse: Option<Point>,
+    pan_anchor: Option<(Point, Point)>,
 }
 
 impl Env {
-    fn new() -> Env {
+    fn new(business: Business, narrate: bool, pulse: bool) -> Env {
         Env {
-            business: Business::Construct,
+            business,
             cursor: None,
             obj_start: None,
+            narrator: if narrate { Some(Narrator::new()) } else { None },
+            fragments_outline: false,
+            show_corners_nearest: false,
+            show_scale_bar: false,
+            show_help: false,
+            background: [0.0, 0.0, 0.0, 1.0],
+            alt_background: None,
+            ghost_layer: false,
+            show_probe_grid: false,
+            show_nearest_regions: false,
+            show_distance_field: false,
+            show_crossing_pairs: false,
+            show_labels: false,
+            distance_field_cell: DISTANCE_FIELD_CELL,
+            show_pruning: false,
+            show_brute_force: false,
+            color_by_length: false,
+            collision_pulses: if pulse { Some(HashMap::new()) } else { None },
+            weight: 1.,
+            width: 0.,
+            pinned_probe: None,
+            explain_scroll: 0,
+            neighbour_step: None,
+            neighbours_limit: None,
+            adaptive_gradient: false,
+            group: 0,
+            active_groups: (0 .. OBSTACLE_GROUPS).collect(),
+            nearest_group: None,
+            console: None,
+            dirty: false,
+            notice: None,
+            error_message: None,
+            screenshot_pending: false,
+            rect_modifier: false,
+            circle_modifier: false,
+            angle_modifier: false,
+            polyline: None,
+            random_scene: (0, 50),
+            delete_pending: false,
+            grabbed: None,
+            drag: None,
+            drags: Vec::new(),
+            snap_to_grid: false,
+            snap_size: KDTREE_CUT_LIMIT,
+            show_grid: false,
+            show_fps: false,
+            show_coords: false,
+            frame_timer: FrameTimer::new(),
+            show_leaf_region: false,
+            range_hits: None,
+            redo: Vec::new(),
+            screen_size: (SCREEN_WIDTH, SCREEN_HEIGHT),
+            camera: Point { x: 0., y: 0., },
+            zoom: 1.,
+            mouse: None,
+            pan_anchor: None,
         }
     }
 
-    fn reset(&mut self, _width: u32, _height: u32) {
-        self.reset_cursor();
+    fn narrate(&mut self, message: String) {
+        if let Some(ref mut narrator) = self.narrator {
+            narrator.say(message);
+        }
     }
 
-    fn set_cursor(&mut self, x: f64, y: f64) {
+    fn toggle_background(&mut self) {
+        let alt = self.alt_background.unwrap_or_else(|| contrast_color(self.background));
+        self.alt_background = Some(self.background);
+        self.background = alt;
+    }
+
+    fn toggle_group(&mut self, group: usize) {
+        if !self.active_groups.remove(&group) {
+            self.active_groups.insert(group);
+        }
+        // the tree is rebuilt over the new set of active obstacles
+        self.dirty = true;
+    }
+
+    fn clear_scene(&mut self, scene: &mut Scene) {
+        scene.clear();
+        self.reset_cursor();
+        if let Some(ref mut points) = self.polyline {
+            points.clear();
+        }
+        self.redo.clear();
+        self.drags.clear();
+        self.dirty = true;
+    }
+
+    /// Drops the last point of a polyline under construction or cancels a started segment if there is one,
+    /// otherwise takes back the last obstacle, the whole last polyline or the last end drag.
+    fn undo(&mut self, scene: &mut Scene) {
+        if let Some(ref mut points) = self.polyline {
+            if points.pop().is_some() {
+                self.obj_start = points.last().cloned();
+                return;
+            }
+        }
+        if self.obj_start.take().is_some() {
+            return;
+        }
+        if self.drags.last().is_some_and(|drag| drag.scene_len == scene.obstacles.len()) {
+            if let Some(drag) = self.drags.pop() {
+                // backwards, so an obstacle with both ends moved ends up as it was before the drag
+                for &(index, _, before, _) in drag.ends.iter().rev() {
+                    scene.obstacles[index] = before;
+                }
+                self.redo.push(Undone::Drag(drag));
+                self.dirty = true;
+            }
+            return;
+        }
+        match scene.pop() {
+            Some((segment, meta)) => {
+                self.redo.push(Undone::Obstacle(segment, meta));
+                while meta.polyline.is_some() && scene.meta.last().map(|last| last.polyline) == Some(meta.polyline) {
+                    self.redo.extend(scene.pop().map(|(segment, meta)| Undone::Obstacle(segment, meta)));
+                }
+                self.dirty = true;
+            },
+            None =>
+                debug!("nothing to undo"),
+        }
+    }
+
+    fn redo(&mut self, scene: &mut Scene) {
+        match self.redo.pop() {
+            Some(Undone::Obstacle(segment, meta)) => {
+                scene.push(segment, meta);
+                while meta.polyline.is_some() && matches!(self.redo.last(), Some(&Undone::Obstacle(_, last)) if last.polyline == meta.polyline) {
+                    if let Some(Undone::Obstacle(segment, meta)) = self.redo.pop() {
+                        scene.push(segment, meta);
+                    }
+                }
+                self.dirty = true;
+            },
+            Some(Undone::Drag(drag)) => {
+                for &(index, _, _, after) in drag.ends.iter() {
+                    scene.obstacles[index] = after;
+                }
+                self.drags.push(drag);
+                self.dirty = true;
+            },
+            None =>
+                debug!("nothing to redo"),
+        }
+    }
+
+    /// Replaces the scene with random obstacles, every call moves on to the next seed.
+    fn randomize_scene(&mut self, scene: &mut Scene) {
+        let (seed, count) = self.random_scene;
+        self.clear_scene(scene);
+        let mut rng = bench::seeded_rng(seed);
+        let (width, height) = (self.screen_size.0 as f64, self.screen_size.1 as f64);
+        for segment in bench::random_scene(&mut rng, count, width, height, bench::MAX_SEGMENT_LENGTH) {
+            scene.push(segment, ObstacleMeta { group: self.group, ..Default::default() });
+        }
+        self.random_scene.0 = seed.wrapping_add(1);
+        self.notify(format!("generated {} random obstacles, replay with --seed {}", count, seed));
+    }
+
+    /// Removes the obstacle at `index` along with the rest of its polyline.
+    fn delete_obstacle(&mut self, scene: &mut Scene, index: usize) {
+        let members = scene.polyline_members(index);
+        scene.remove(&members);
+        // recorded drags and undone obstacles refer to indices which have just shifted
+        self.drags.clear();
+        self.redo.clear();
+        self.dirty = true;
+        self.narrate(format!("deleted {} obstacles", members.len()));
+    }
+
+    /// Grabs the obstacle end within `GRAB_DISTANCE` of the cursor in edit mode.
+    fn grab_endpoint(&mut self, scene: &Scene) {
+        if let (&Business::Edit, Some(cursor)) = (&self.business, self.cursor) {
+            let shapes = scene.active_shapes(&self.active_groups);
+            self.grabbed = nearest_endpoint(&scene.obstacles, &shapes, &cursor, GRAB_DISTANCE);
+            self.drag = self.grabbed.map(|(index, endpoint)| DragEdit {
+                scene_len: scene.obstacles.len(),
+                ends: joined_endpoints(scene, index, endpoint).into_iter()
+                    .map(|(member, end)| (member, end, scene.obstacles[member], scene.obstacles[member]))
+                    .collect(),
+            });
+        }
+    }
+
+    /// Moves the grabbed end with the ones joined to it to the cursor, the tree is left as it is until the end is released.
+    fn drag_endpoint(&mut self, scene: &mut Scene) {
+        if let (Some(drag), Some(cursor)) = (self.drag.as_mut(), self.cursor) {
+            for &mut (index, endpoint, _, ref mut after) in drag.ends.iter_mut() {
+                let segment = &mut scene.obstacles[index];
+                match endpoint {
+                    Endpoint::Src => segment.src = cursor,
+                    Endpoint::Dst => segment.dst = cursor,
+                }
+                *after = *segment;
+            }
+        }
+    }
+
+    fn release_endpoint(&mut self, scene: &mut Scene) {
+        self.drag_endpoint(scene);
+        self.finish_drag();
+    }
+
+    /// Lets go of the grabbed end, keeping the drag for `undo` if it moved anything.
+    fn finish_drag(&mut self) {
+        if self.grabbed.take().is_some() {
+            self.dirty = true;
+        }
+        if let Some(drag) = self.drag.take() {
+            if drag.ends.iter().any(|&(_, _, before, after)| !segment_approx_eq(&before, &after, 0.)) {
+                self.drags.push(drag);
+                self.redo.clear();
+            }
+        }
+    }
+
+    /// Reports whether obstacles changed since the last call, resetting the flag.
+    fn take_dirty(&mut self) -> bool {
+        mem::replace(&mut self.dirty, false)
+    }
+
+    fn toggle_console(&mut self) {
+        self.console = match self.console {
+            Some(..) => None,
+            None => Some(console::Console::new()),
+        };
+    }
+
+    /// Edits the console line, returning the parsed command once it is submitted with <Enter>.
+    fn console_key(&mut self, key: Key) -> Option<console::Command> {
+        let console = self.console.as_mut()?;
+        match key {
+            Key::Backspace =>
+                console.backspace(),
+            Key::Return | Key::NumPadEnter =>
+                return console.submit(),
+            _ =>
+                (),
+        }
+        None
+    }
+
+    fn console_status(&mut self, message: String) {
+        if let Some(ref mut console) = self.console {
+            console.status = Some(message);
+        }
+    }
+
+    fn save_scene(&mut self, path: &Path, scene: &Scene) {
+        let of_shape = |shape| -> Vec<_> {
+            scene.obstacles.iter().zip(scene.meta.iter()).filter(|(_, meta)| meta.shape == shape).map(|(&segment, _)| segment).collect()
+        };
+        let layers = scene_file::SceneLayers {
+            obstacles: of_shape(Shape::Segment),
+            rects: of_shape(Shape::Rect),
+            circles: of_shape(Shape::Circle),
+            ghosts: scene.ghosts.clone(),
+            meta: [Shape::Segment, Shape::Rect, Shape::Circle].iter()
+                .flat_map(|&shape| scene.meta.iter().filter(move |meta| meta.shape == shape).cloned())
+                .collect(),
+        };
+        match scene_file::save(path, &layers) {
+            Ok(()) => {
+                let message = format!("saved {} obstacles and {} ghosts to {}", scene.obstacles.len(), scene.ghosts.len(), path.display());
+                self.notify(message.clone());
+                self.narrate(message);
+            },
+            Err(e) => {
+                error!("failed to save scene: {:?}", e);
+                self.report_error(format!("failed to save {}", path.display()));
+                self.console_status(format!("failed to save {}", path.display()));
+            },
+        }
+    }
+
+    fn export_svg(&mut self, path: &Path, svg: &str) {
+        match svg::save(path, svg) {
+            Ok(()) =>
+                self.notify(format!("exported svg to {}", path.display())),
+            Err(e) => {
+                error!("failed to export svg: {:?}", e);
+                self.report_error(format!("failed to export {}", path.display()));
+            },
+        }
+    }
+
+    /// Shows a short lived message at the bottom of the window.
+    fn notify(&mut self, message: String) {
+        self.notice = Some((message, Instant::now()));
+    }
+
+    fn current_notice(&self) -> Option<&str> {
+        self.notice.as_ref()
+            .filter(|&(_, shown_at)| shown_at.elapsed() < Duration::from_millis(NOTICE_TTL_MS))
+            .map(|(message, _)| message.as_str())
+    }
+
+    /// Shows an error which is not worth terminating for in a red banner for a few seconds.
+    fn report_error(&mut self, message: String) {
+        self.error_message = Some((message, Instant::now()));
+    }
+
+    /// Result of a tree query, or an empty one after showing the query error in the banner.
+    fn query_or_report<T>(&mut self, result: Result<T, Error>) -> T where T: Default {
+        result.unwrap_or_else(|e| {
+            error!("tree query failed: {:?}", e);
+            self.report_error(format!("tree query failed: {:?}", e));
+            T::default()
+        })
+    }
+
+    fn current_error(&self) -> Option<&str> {
+        self.error_message.as_ref()
+            .filter(|&(_, shown_at)| shown_at.elapsed() < Duration::from_millis(ERROR_BANNER_TTL_MS))
+            .map(|(message, _)| message.as_str())
+    }
+
+    /// Forgets pulse starts of obstacles which are not collided anymore, so they flash again on the next hit.
+    fn expire_pulses(&mut self, collided: &HashSet<usize>) {
+        let colliding = matches!((&self.business, self.probe()), (&Business::Collide, Some(..)));
+        if let Some(ref mut pulses) = self.collision_pulses {
+            if colliding {
+                pulses.retain(|shape, _| collided.contains(shape));
+            } else {
+                pulses.clear();
+            }
+        }
+    }
+
+    fn reset(&mut self, width: u32, height: u32) {
+        self.screen_size = (width, height);
+        self.reset_cursor();
+    }
+
+    fn set_cursor(&mut self, x: f64, y: f64) {
+        self.mouse = Some(Point { x, y, });
+        if let Some((anchor_mouse, anchor_camera)) = self.pan_anchor {
+            self.camera = Point {
+                x: anchor_camera.x + x - anchor_mouse.x,
+                y: anchor_camera.y + y - anchor_mouse.y,
+            };
+            return;
+        }
         self.cursor = if y < CONSOLE_HEIGHT as f64 {
             None
+        } else if self.snap_to_grid {
+            Some(snap_point(&self.screen_to_world(x, y), self.snap_size))
         } else {
-            Some(Point { x, y, })
+            Some(self.screen_to_world(x, y))
+        };
+        if let (&Business::Construct, true, Some(start), Some(cursor)) = (&self.business, self.angle_modifier, self.obj_start, self.cursor) {
+            self.cursor = Some(constrain_angle(&start, &cursor));
+        }
+    }
+
+    /// Holding <Alt> constrains the segment under construction, the cursor follows right away.
+    fn set_angle_modifier(&mut self, held: bool) {
+        self.angle_modifier = held;
+        if let Some(mouse) = self.mouse {
+            self.set_cursor(mouse.x, mouse.y);
+        }
+    }
+
+    /// Switches grid snapping, a segment started before snapping is on gets its start snapped as well.
+    fn toggle_snap(&mut self) {
+        self.snap_to_grid = !self.snap_to_grid;
+        if self.snap_to_grid {
+            let size = self.snap_size;
+            self.obj_start = self.obj_start.map(|start| snap_point(&start, size));
+            if let Some(mouse) = self.mouse {
+                self.set_cursor(mouse.x, mouse.y);
+            }
+        }
+    }
+
+    fn screen_to_world(&self, x: f64, y: f64) -> Point {
+        Point { x: (x - self.camera.x) / self.zoom, y: (y - self.camera.y) / self.zoom, }
+    }
+
+    /// World coordinates under the mouse with the grid cell it snaps to while snapping is on.
+    fn cursor_readout(&self) -> Option<String> {
+        let mouse = self.mouse?;
+        let world = self.screen_to_world(mouse.x, mouse.y);
+        let mut readout = format!("x: {:.1} y: {:.1}", world.x, world.y);
+        if self.snap_to_grid {
+            let snapped = snap_point(&world, self.snap_size);
+            readout.push_str(&format!(
+                " | cell {}, {} at {:.1}, {:.1}",
+                (snapped.x / self.snap_size).round(),
+                (snapped.y / self.snap_size).round(),
+                snapped.x,
+                snapped.y,
+            ));
+        }
+        Some(readout)
+    }
+
+    /// Scales the view by `ZOOM_STEP` per scroll step keeping the world point under the mouse in place.
+    fn zoom_at_mouse(&mut self, steps: f64) {
+        if let Some(mouse) = self.mouse {
+            let anchor = self.screen_to_world(mouse.x, mouse.y);
+            self.zoom = (self.zoom * ZOOM_STEP.powf(steps)).clamp(MIN_ZOOM, MAX_ZOOM);
+            self.camera = Point { x: mouse.x - anchor.x * self.zoom, y: mouse.y - anchor.y * self.zoom, };
+            self.set_cursor(mouse.x, mouse.y);
+        }
+    }
+
+    fn reset_view(&mut self) {
+        self.camera = Point { x: 0., y: 0., };
+        self.zoom = 1.;
+        self.pan_anchor = None;
+        if let Some(mouse) = self.mouse {
+            self.set_cursor(mouse.x, mouse.y);
         }
     }
 
+    fn start_pan(&mut self) {
+        if let Some(mouse) = self.mouse {
+            self.pan_anchor = Some((mouse, self.camera));
+        }
+    }
+
+    fn stop_pan(&mut self) {
+        self.pan_anchor = None;
+    }
+
     fn reset_cursor(&mut self) {
         self.cursor = None;
         self.obj_start = None;
     }
 
-    fn toggle_obj(&mut self, obstacles: &mut Vec<Segment>) {
+    fn toggle_obj(&mut self, scene: &mut Scene) {
+        if let Business::Edit = self.business {
+            return;
+        }
+        if let (&Business::Construct, Some(ref mut points), Some(point)) = (&self.business, &mut self.polyline, self.cursor) {
+            if points.last().is_none_or(|last| !points_approx_eq(last, &point, POINT_EPSILON)) {
+                points.push(point);
+            }
+            self.obj_start = Some(point);
+            return;
+        }
         if let Some(src) = self.cursor {
             self.obj_start = if let Some(dst) = self.obj_start {
                 match self.business {
-                    Business::Construct =>
-                        obstacles.push(Segment { src, dst, }),
-                    Business::Collide | Business::Neighbours =>
+                    Business::Construct if points_approx_eq(&src, &dst, POINT_EPSILON) =>
+                        debug!("skipping zero length obstacle at {:?}", src),
+                    Business::Construct if self.ghost_layer && scene.ghosts.iter().any(|s| segment_approx_eq(s, &Segment { src, dst, }, POINT_EPSILON)) =>
+                        debug!("skipping duplicate ghost {:?} -> {:?}", src, dst),
+                    Business::Construct if self.ghost_layer =>
+                        scene.ghosts.push(Segment { src, dst, }),
+                    Business::Construct if scene.obstacles.iter().zip(scene.meta.iter())
+                        .any(|(s, meta)| meta.shape == self.new_shape() && segment_approx_eq(s, &Segment { src, dst, }, POINT_EPSILON)) =>
+                        debug!("skipping duplicate obstacle {:?} -> {:?}", src, dst),
+                    Business::Construct => {
+                        let meta = ObstacleMeta { shape: self.new_shape(), width: self.width, weight: self.weight, group: self.group, ..Default::default() };
+                        scene.push(Segment { src, dst, }, meta);
+                        self.redo.clear();
+                        self.dirty = true;
+                    },
+                    Business::Collide | Business::Neighbours | Business::Edit | Business::Range | Business::Ray =>
                         (),
                 }
                 None
@@ -355,248 +2504,3720 @@ impl Env {
         }
     }
 
+    /// Shape of the obstacle being constructed: holding <Shift> makes a rectangle from the two clicked corners,
+    /// holding <Ctrl> makes a circle around the first click passing through the second one.
+    fn new_shape(&self) -> Shape {
+        match (self.ghost_layer || self.polyline.is_some(), self.rect_modifier, self.circle_modifier) {
+            (false, true, _) => Shape::Rect,
+            (false, false, true) => Shape::Circle,
+            _ => Shape::Segment,
+        }
+    }
+
+    /// Switches construct mode between committing obstacles and sketching planned ghosts.
+    fn toggle_ghost_layer(&mut self) {
+        if let Business::Construct = self.business {
+            self.ghost_layer = !self.ghost_layer;
+            self.polyline = None;
+        }
+    }
+
+    /// Switches construct mode between single segments and polylines, dropping an uncommitted polyline.
+    fn toggle_polyline(&mut self) {
+        if let (&Business::Construct, false) = (&self.business, self.ghost_layer) {
+            self.polyline = match self.polyline {
+                Some(..) => None,
+                None => Some(Vec::new()),
+            };
+            self.obj_start = None;
+        }
+    }
+
+    /// Turns the polyline under construction into obstacles sharing a new polyline id.
+    fn commit_polyline(&mut self, scene: &mut Scene) {
+        let points = match self.polyline {
+            Some(ref mut points) => mem::take(points),
+            None => return,
+        };
+        self.obj_start = None;
+        if points.len() < 2 {
+            debug!("skipping polyline with {} points", points.len());
+            return;
+        }
+        let meta = ObstacleMeta { width: self.width, weight: self.weight, group: self.group, polyline: Some(scene.next_polyline_id()), ..Default::default() };
+        for pair in points.windows(2) {
+            scene.push(Segment { src: pair[0], dst: pair[1], }, meta);
+        }
+        self.redo.clear();
+        self.dirty = true;
+    }
+
+    fn info_line(&self) -> String {
+        match self.business {
+            Business::Construct if self.ghost_layer =>
+                "[ sketching ghosts ] <G> back to obstacles, <P> promote ghosts, <C> to clear, <H> help or <Q> to exit".to_string(),
+            Business::Construct if self.polyline.is_some() =>
+                "[ constructing polyline ] <Enter> to commit, <Z> drops a point, <W> back to segments, <H> help or <Q> to exit".to_string(),
+            Business::Range if self.live_probe().is_some() =>
+                format!("[ range query ] {} obstacles in the rectangle, <M> switch to ray mode, <H> help or <Q> to exit", self.range_hits.unwrap_or(0)),
+            ref business =>
+                business.info_line(),
+        }
+    }
+
+    fn live_probe(&self) -> Option<Segment> {
+        match (self.cursor, self.obj_start) {
+            (Some(src), Some(dst)) =>
+                Some(Segment { src, dst, }),
+            _ =>
+                None,
+        }
+    }
+
+    /// Query segment for the current mode: a pinned probe takes precedence over the cursor in collide mode.
+    fn probe(&self) -> Option<Segment> {
+        match self.business {
+            Business::Collide =>
+                self.pinned_probe.or_else(|| self.live_probe()),
+            Business::Construct | Business::Neighbours | Business::Edit | Business::Range | Business::Ray =>
+                self.live_probe(),
+        }
+    }
+
+    fn toggle_pinned_probe(&mut self) {
+        self.explain_scroll = 0;
+        self.pinned_probe = match (&self.business, self.pinned_probe) {
+            (_, Some(..)) =>
+                None,
+            (&Business::Collide, None) =>
+                self.live_probe(),
+            (_, None) =>
+                None,
+        };
+    }
+
+    /// Moves through the sorted nearest results one at a time; retreating past the first one returns to the gradient view.
+    fn step_neighbour(&mut self, forward: bool) {
+        if let Business::Neighbours = self.business {
+            self.neighbour_step = match (self.neighbour_step, forward) {
+                (None, true) =>
+                    Some(0),
+                (Some(step), true) =>
+                    Some(step + 1),
+                (None, false) | (Some(0), false) =>
+                    None,
+                (Some(step), false) =>
+                    Some(step - 1),
+            };
+        }
+    }
+
+    /// Shows one nearest neighbour more or less; going below one shows all of them again.
+    fn step_neighbours_limit(&mut self, more: bool) {
+        self.neighbours_limit = match (self.neighbours_limit, more) {
+            (None, true) =>
+                Some(1),
+            (Some(k), true) =>
+                Some(k + 1),
+            (None, false) | (Some(1), false) =>
+                None,
+            (Some(k), false) =>
+                Some(k - 1),
+        };
+    }
+
+    fn scale_weight(&mut self, factor: f64) {
+        self.weight = (self.weight * factor).clamp(MIN_OBSTACLE_WEIGHT, MAX_OBSTACLE_WEIGHT);
+    }
+
     fn toggle_mode(&mut self) {
-        self.business = match self.business {
+        let next = match self.business {
             Business::Construct =>
                 Business::Collide,
             Business::Collide =>
                 Business::Neighbours,
             Business::Neighbours =>
+                Business::Edit,
+            Business::Edit =>
+                Business::Range,
+            Business::Range =>
+                Business::Ray,
+            Business::Ray =>
                 Business::Construct,
         };
+        self.set_mode(next);
+    }
+
+    fn set_mode(&mut self, business: Business) {
+        self.neighbour_step = None;
+        self.ghost_layer = false;
+        self.polyline = None;
+        // a drag interrupted by a mode switch still changed the obstacle
+        self.finish_drag();
+        self.business = business;
     }
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Point {
-    x: f64,
-    y: f64,
+/// Vertical and horizontal lines at multiples of `step` crossing the `lt` - `rb` area.
+fn grid_lines(lt: &Point, rb: &Point, step: f64) -> Vec<Segment> {
+    let mut lines = Vec::new();
+    let mut x = (lt.x / step).ceil() * step;
+    while x <= rb.x {
+        lines.push(Segment { src: Point { x, y: lt.y, }, dst: Point { x, y: rb.y, }, });
+        x += step;
+    }
+    let mut y = (lt.y / step).ceil() * step;
+    while y <= rb.y {
+        lines.push(Segment { src: Point { x: lt.x, y, }, dst: Point { x: rb.x, y, }, });
+        y += step;
+    }
+    lines
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Segment {
-    src: Point,
-    dst: Point,
+/// Projects `point` onto the nearest of the horizontal, vertical and diagonal lines through `start`.
+fn constrain_angle(start: &Point, point: &Point) -> Point {
+    let (dx, dy) = (point.x - start.x, point.y - start.y);
+    let octant = (dy.atan2(dx) / f64::consts::FRAC_PI_4).round() as i64;
+    let diagonal = f64::consts::FRAC_1_SQRT_2;
+    let (ux, uy) = match octant.rem_euclid(8) {
+        0 => (1., 0.),
+        1 => (diagonal, diagonal),
+        2 => (0., 1.),
+        3 => (-diagonal, diagonal),
+        4 => (-1., 0.),
+        5 => (-diagonal, -diagonal),
+        6 => (0., -1.),
+        _ => (diagonal, -diagonal),
+    };
+    let along = dx * ux + dy * uy;
+    Point { x: start.x + along * ux, y: start.y + along * uy, }
 }
 
-#[derive(Clone, Debug)]
-enum Axis { X, Y, }
+/// Rounds both coordinates to the nearest multiple of `size`.
+fn snap_point(point: &Point, size: f64) -> Point {
+    Point { x: (point.x / size).round() * size, y: (point.y / size).round() * size, }
+}
 
-fn cmp_points(axis: &Axis, a: &Point, b: &Point) -> Ordering {
-    match axis {
-        &Axis::X =>
-            if a.x < b.x { Ordering::Less } else if a.x > b.x { Ordering::Greater } else { Ordering::Equal },
-        &Axis::Y =>
-            if a.y < b.y { Ordering::Less } else if a.y > b.y { Ordering::Greater } else { Ordering::Equal },
+/// One of the two ends of an obstacle segment.
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Endpoint {
+    Src,
+    Dst,
+}
+
+impl Endpoint {
+    fn of(self, segment: &Segment) -> Point {
+        match self {
+            Endpoint::Src => segment.src,
+            Endpoint::Dst => segment.dst,
+        }
     }
 }
 
+/// Obstacle ends moved together by one drag in edit mode.
 #[derive(Clone, Debug)]
-struct Bound {
-    lt: Point,
-    rb: Point,
+struct DragEdit {
+    /// Obstacles count at the time of the drag, any obstacle constructed later is undone before it.
+    scene_len: usize,
+    /// Every moved end as `(index, endpoint, before, after)` with its obstacle before and after the drag.
+    ends: Vec<(usize, Endpoint, Segment, Segment)>,
 }
 
-impl kdvtree::BoundingVolume<Point> for Bound {
-    fn min_corner(&self) -> Point { self.lt }
-    fn max_corner(&self) -> Point { self.rb }
+/// A step taken back by `undo`, kept around for `redo`.
+enum Undone {
+    Obstacle(Segment, ObstacleMeta),
+    Drag(DragEdit),
 }
 
-fn get_bounding_volume(shape: &Segment) -> Bound {
-    Bound {
-        lt: Point {
-            x: if shape.src.x < shape.dst.x { shape.src.x } else { shape.dst.x },
-            y: if shape.src.y < shape.dst.y { shape.src.y } else { shape.dst.y },
-        },
-        rb: Point {
-            x: if shape.src.x > shape.dst.x { shape.src.x } else { shape.dst.x },
-            y: if shape.src.y > shape.dst.y { shape.src.y } else { shape.dst.y },
-        },
+/// Ends of the polyline the `index` obstacle belongs to which lie on its `endpoint`, so dragging it keeps the polyline joined.
+fn joined_endpoints(scene: &Scene, index: usize, endpoint: Endpoint) -> Vec<(usize, Endpoint)> {
+    let point = endpoint.of(&scene.obstacles[index]);
+    scene.polyline_members(index).into_iter()
+        .flat_map(|member| iter::once((member, Endpoint::Src)).chain(iter::once((member, Endpoint::Dst))))
+        .filter(|&(member, end)| points_approx_eq(&end.of(&scene.obstacles[member]), &point, POINT_EPSILON))
+        .collect()
+}
+
+/// Finds the end of one of `shapes` closest to `point` but no farther than `max_dist`.
+fn nearest_endpoint(obstacles: &[Segment], shapes: &[usize], point: &Point, max_dist: f64) -> Option<(usize, Endpoint)> {
+    shapes.iter()
+        .flat_map(|&index| iter::once((index, Endpoint::Src)).chain(iter::once((index, Endpoint::Dst))))
+        .map(|(index, endpoint)| (Segment { src: *point, dst: endpoint.of(&obstacles[index]), }.length(), index, endpoint))
+        .filter(|&(dist, _, _)| dist <= max_dist)
+        .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal))
+        .map(|(_, index, endpoint)| (index, endpoint))
+}
+
+/// Obstacle geometry kind, every kind is stored as a `Segment`: segments as they are, rects by their diagonal, circles from the center to the rim.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Shape {
+    Segment,
+    /// Axis aligned rectangle with the segment as its diagonal.
+    Rect,
+    /// Circle with the segment going from its center to the rim.
+    Circle,
+}
+
+/// Per obstacle attributes, only `shape` and `width` affect the tree geometry.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct ObstacleMeta {
+    shape: Shape,
+    /// Capsule radius around a segment obstacle, anything that close to it collides. Other shapes ignore it.
+    width: f64,
+    /// Heavier obstacles are treated as closer by nearest queries.
+    weight: f64,
+    /// Obstacles of hidden groups are neither drawn nor put into the tree.
+    group: usize,
+    /// Obstacle is expected to move often, so it is kept out of the static tree of `SplitTree`.
+    #[serde(skip)]
+    dynamic: bool,
+    /// Segments of one polyline share the id, so they are highlighted and undone together.
+    polyline: Option<usize>,
+}
+
+impl Default for ObstacleMeta {
+    fn default() -> ObstacleMeta {
+        ObstacleMeta { shape: Shape::Segment, width: 0., weight: 1., group: 0, dynamic: false, polyline: None, }
     }
 }
 
-#[derive(Default)]
-struct PointsCutter {
-    point_min: Option<Point>,
-    point_max: Option<Point>,
+impl ObstacleMeta {
+    /// Radius of a segment obstacle with a width.
+    fn capsule_radius(&self) -> Option<f64> {
+        if self.shape == Shape::Segment && self.width > 0. { Some(self.width) } else { None }
+    }
+
+    /// Shape the tree indexes the obstacle as: a capsule is kept in its inflated box and split like a rectangle.
+    fn indexed_shape(&self) -> Shape {
+        if self.capsule_radius().is_some() { Shape::Rect } else { self.shape }
+    }
 }
 
-impl<'s> kdvtree::GetCutPoint<Axis, Point> for &'s mut PointsCutter {
-    fn cut_point<I>(&mut self, _cut_axis: &Axis, points: I) -> Option<Point> where I: Iterator<Item = Point> {
-        self.point_min = None;
-        self.point_max = None;
-        let mut point_sum = Point { x: 0., y: 0., };
-        let mut total = 0;
-        for p in points {
-            let pmin = self.point_min.get_or_insert(p);
-            if p.x < pmin.x { pmin.x = p.x; }
-            if p.y < pmin.y { pmin.y = p.y; }
-            let pmax = self.point_max.get_or_insert(p);
-            if p.x > pmax.x { pmax.x = p.x; }
-            if p.y > pmax.y { pmax.y = p.y; }
-            point_sum.x += p.x;
-            point_sum.y += p.y;
-            total += 1;
+/// Obstacles along with their metadata kept in parallel vectors, so the geometry
+/// is still available as a plain `&[Segment]`.
+struct Scene {
+    obstacles: Vec<Segment>,
+    meta: Vec<ObstacleMeta>,
+    /// Planned segments which are drawn but left out of the tree until promoted.
+    ghosts: Vec<Segment>,
+}
+
+impl Scene {
+    fn new() -> Scene {
+        Scene {
+            obstacles: Vec::new(),
+            meta: Vec::new(),
+            ghosts: Vec::new(),
         }
-        if total == 0 {
-            None
-        } else {
-            Some(Point {
-                x: point_sum.x / total as f64,
-                y: point_sum.y / total as f64,
-            })
+    }
+
+    fn push(&mut self, segment: Segment, meta: ObstacleMeta) {
+        self.obstacles.push(segment);
+        self.meta.push(meta);
+    }
+
+    fn pop(&mut self) -> Option<(Segment, ObstacleMeta)> {
+        let segment = self.obstacles.pop()?;
+        self.meta.pop().map(|meta| (segment, meta))
+    }
+
+    fn next_polyline_id(&self) -> usize {
+        self.meta.iter().filter_map(|meta| meta.polyline).max().map_or(0, |id| id + 1)
+    }
+
+    /// Indices of all the segments of the polyline `index` belongs to, or just `index` for standalone obstacles.
+    fn polyline_members(&self, index: usize) -> Vec<usize> {
+        match self.meta[index].polyline {
+            Some(id) =>
+                self.meta.iter().enumerate().filter(|&(_, meta)| meta.polyline == Some(id)).map(|(i, _)| i).collect(),
+            None =>
+                vec![index],
         }
     }
-}
 
-fn cut_segment_fragment(shape: &Segment, fragment: &Bound, cut_axis: &Axis, cut_point: &Point) -> Result<Option<(Bound, Bound)>, ()> {
-    match cut_axis {
-        &Axis::X => if cut_point.x >= fragment.lt.x && cut_point.x <= fragment.rb.x {
-            if fragment.rb.x - fragment.lt.x < KDTREE_CUT_LIMIT {
-                Ok(None)
-            } else {
-                let factor = (cut_point.x - shape.src.x) / (shape.dst.x - shape.src.x);
-                let y = shape.src.y + (factor * (shape.dst.y - shape.src.y));
-                let left_point = if shape.src.x < shape.dst.x { shape.src } else { shape.dst };
-                let left_bound = Bound {
-                    lt: Point {
-                        x: fragment.lt.x,
-                        y: if left_point.y < y { fragment.lt.y } else { y },
-                    },
-                    rb: Point {
-                        x: cut_point.x,
-                        y: if left_point.y < y { y } else { fragment.rb.y },
-                    }
-                };
-                let right_point = if shape.src.x < shape.dst.x { shape.dst } else { shape.src };
-                let right_bound = Bound {
-                    lt: Point {
-                        x: cut_point.x,
-                        y: if right_point.y < y { fragment.lt.y } else { y },
-                    },
-                    rb: Point {
-                        x: fragment.rb.x,
-                        y: if right_point.y < y { y } else { fragment.rb.y },
-                    },
-                };
-                Ok(Some((left_bound, right_bound)))
-            }
-        } else {
-            return Ok(None);
-        },
-        &Axis::Y => if cut_point.y >= fragment.lt.y && cut_point.y <= fragment.rb.y {
-            if fragment.rb.y - fragment.lt.y < KDTREE_CUT_LIMIT {
-                Ok(None)
-            } else {
-                let factor = (cut_point.y - shape.src.y) / (shape.dst.y - shape.src.y);
-                let x = shape.src.x + (factor * (shape.dst.x - shape.src.x));
-                let upper_point = if shape.src.y < shape.dst.y { shape.src } else { shape.dst };
-                let upper_bound = Bound {
-                    lt: Point {
-                        x: if upper_point.x < x { fragment.lt.x } else { x },
-                        y: fragment.lt.y,
-                    },
-                    rb: Point {
-                        x: if upper_point.x < x { x } else { fragment.rb.x },
-                        y: cut_point.y,
-                    }
-                };
-                let lower_point = if shape.src.y < shape.dst.y { shape.dst } else { shape.src };
-                let lower_bound = Bound {
-                    lt: Point {
-                        x: if lower_point.x < x { fragment.lt.x } else { x },
-                        y: cut_point.y,
-                        },
-                    rb: Point {
-                        x: if lower_point.x < x { x } else { fragment.rb.x },
-                        y: fragment.rb.y,
-                    },
-                };
-                Ok(Some((upper_bound, lower_bound)))
+    /// Removes obstacles at the given ascending `indices`, shifting the later ones down.
+    fn remove(&mut self, indices: &[usize]) {
+        for &index in indices.iter().rev() {
+            self.obstacles.remove(index);
+            self.meta.remove(index);
+        }
+    }
+
+    fn set_dynamic(&mut self, index: usize, dynamic: bool) {
+        self.meta[index].dynamic = dynamic;
+    }
+
+    /// Bounds of all the obstacles by index as the tree indexes them, capsules inflated by their radius.
+    fn bounds(&self, parallel: bool) -> Vec<Bound> {
+        let mut bounds = obstacle_bounds(&self.obstacles, |i| self.meta[i].indexed_shape(), parallel);
+        for (bound, meta) in bounds.iter_mut().zip(self.meta.iter()) {
+            if let Some(radius) = meta.capsule_radius() {
+                *bound = inflate_bound(bound, radius);
             }
-        } else {
-            return Ok(None);
-        },
+        }
+        bounds
     }
-}
 
-fn bound_to_cut_point_dist(axis: &Axis, bounding_volume: &Bound, cut_point: &Point) -> f64 {
-    match axis {
-        &Axis::X => {
-            let l = (bounding_volume.lt.x - cut_point.x).abs();
-            let r = (bounding_volume.rb.x - cut_point.x).abs();
-            if l < r { l } else { r }
-        },
-        &Axis::Y => {
-            let t = (bounding_volume.lt.y - cut_point.y).abs();
-            let b = (bounding_volume.rb.y - cut_point.y).abs();
-            if t < b { t } else { b }
-        },
+    /// Bound of the obstacle `index` as the tree indexes it, see `bounds`.
+    fn bound(&self, index: usize) -> Bound {
+        let bound = obstacle_bound(&self.obstacles[index], self.meta[index].indexed_shape());
+        match self.meta[index].capsule_radius() {
+            Some(radius) => inflate_bound(&bound, radius),
+            None => bound,
+        }
     }
-}
 
-fn bound_to_bound_dist(bv_a: &Bound, bv_b: &Bound) -> f64 {
-    fn dist(xa: f64, ya: f64, xb: f64, yb: f64) -> f64 {
-        ((xb - xa) * (xb - xa) + (yb - ya) * (yb - ya)).sqrt()
-    }
-    let left = bv_b.rb.x < bv_a.lt.x;
-    let right = bv_a.rb.x < bv_b.lt.x;
-    let top = bv_a.rb.y < bv_b.lt.y;
-    let bottom = bv_b.rb.y < bv_a.lt.y;
-    if top && left {
-        dist(bv_a.lt.x, bv_a.rb.y, bv_b.rb.x, bv_b.lt.y)
-    } else if left && bottom {
-        dist(bv_a.lt.x, bv_a.lt.y, bv_b.rb.x, bv_b.rb.y)
-    } else if bottom && right {
-        dist(bv_a.rb.x, bv_a.lt.y, bv_b.lt.x, bv_b.rb.y)
-    } else if right && top {
-        dist(bv_a.rb.x, bv_a.rb.y, bv_b.lt.x, bv_b.lt.y)
-    } else if left {
-        bv_a.lt.x - bv_b.rb.x
-    } else if right {
-        bv_b.lt.x - bv_a.rb.x
-    } else if bottom {
-        bv_a.lt.y - bv_b.rb.y
-    } else if top {
-        bv_b.lt.y - bv_a.rb.y
-    } else {
-        0.
+    /// `ray_hit_dist` to the obstacle `index`, a capsule is hit at its radius.
+    fn hit_dist(&self, index: usize, ray: &Segment) -> Option<f64> {
+        match self.meta[index].capsule_radius() {
+            Some(radius) => capsule_hit_dist(ray, &self.obstacles[index], radius),
+            None => ray_hit_dist(ray, &self.obstacles[index], self.meta[index].shape),
+        }
     }
-}
 
-struct VisualCutter {
-    cuts: Vec<(Segment, Axis)>,
-    base_cutter: PointsCutter,
-}
+    /// `obstacle_distance` from `needle` to the obstacle `index`, a capsule starts at its radius.
+    fn distance(&self, index: usize, needle: &Segment) -> f64 {
+        match self.meta[index].capsule_radius() {
+            Some(radius) => (segment_segment_distance(needle, &self.obstacles[index]) - radius).max(0.),
+            None => obstacle_distance(needle, &self.obstacles[index], self.meta[index].shape),
+        }
+    }
 
-impl VisualCutter {
-    fn new() -> VisualCutter {
-        VisualCutter {
-            cuts: Vec::new(),
-            base_cutter: Default::default(),
+    /// `obstacles_touch` for the obstacles `a` and `b`, a capsule touches whatever comes within its radius.
+    fn touch(&self, a: usize, b: usize) -> bool {
+        let (a_obstacle, a_meta, b_obstacle, b_meta) = (&self.obstacles[a], &self.meta[a], &self.obstacles[b], &self.meta[b]);
+        match (a_meta.capsule_radius(), b_meta.capsule_radius()) {
+            (Some(a_radius), Some(b_radius)) =>
+                segment_segment_distance(a_obstacle, b_obstacle) <= a_radius + b_radius,
+            (Some(radius), None) =>
+                obstacle_distance(a_obstacle, b_obstacle, b_meta.shape) <= radius,
+            (None, Some(radius)) =>
+                obstacle_distance(b_obstacle, a_obstacle, a_meta.shape) <= radius,
+            (None, None) =>
+                obstacles_touch(a_obstacle, a_meta.shape, b_obstacle, b_meta.shape),
         }
     }
-}
 
-impl<'s> kdvtree::GetCutPoint<Axis, Point> for &'s mut VisualCutter {
+    /// Indices of obstacles belonging to `active_groups`.
+    fn active_shapes(&self, active_groups: &HashSet<usize>) -> Vec<usize> {
+        self.meta.iter()
+            .enumerate()
+            .filter(|&(_, meta)| active_groups.contains(&meta.group))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    fn clear(&mut self) {
+        self.obstacles.clear();
+        self.meta.clear();
+        self.ghosts.clear();
+    }
+
+    /// Moves all the ghosts into obstacles, returns how many were promoted.
+    fn promote_ghosts(&mut self) -> usize {
+        let ghosts: Vec<_> = self.ghosts.drain(..).collect();
+        for &ghost in ghosts.iter() {
+            self.push(ghost, Default::default());
+        }
+        ghosts.len()
+    }
+}
+
+/// Rolling average of the time between drawn frames over the last `FRAME_TIME_SAMPLES` ones.
+struct FrameTimer {
+    last_frame: Option<Instant>,
+    samples: VecDeque<Duration>,
+}
+
+impl FrameTimer {
+    fn new() -> FrameTimer {
+        FrameTimer { last_frame: None, samples: VecDeque::new(), }
+    }
+
+    fn tick(&mut self, now: Instant) {
+        if let Some(last_frame) = self.last_frame.replace(now) {
+            if self.samples.len() >= FRAME_TIME_SAMPLES {
+                self.samples.pop_front();
+            }
+            self.samples.push_back(now.duration_since(last_frame));
+        }
+    }
+
+    fn mean(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        Some(self.samples.iter().sum::<Duration>() / self.samples.len() as u32)
+    }
+}
+
+struct Narrator {
+    lines: VecDeque<(String, Instant)>,
+    last_message: Option<String>,
+    last_spoken: Option<Instant>,
+}
+
+impl Narrator {
+    fn new() -> Narrator {
+        Narrator {
+            lines: VecDeque::new(),
+            last_message: None,
+            last_spoken: None,
+        }
+    }
+
+    fn say(&mut self, message: String) {
+        let now = Instant::now();
+        while let Some(&(_, spoken_at)) = self.lines.front() {
+            if now.duration_since(spoken_at) < Duration::from_millis(NARRATION_TTL_MS) {
+                break;
+            }
+            self.lines.pop_front();
+        }
+        // skip repeated sentences and keep the pace readable
+        if self.last_message.as_ref() == Some(&message) {
+            return;
+        }
+        if let Some(spoken_at) = self.last_spoken {
+            if now.duration_since(spoken_at) < Duration::from_millis(NARRATION_INTERVAL_MS) {
+                return;
+            }
+        }
+        if self.lines.len() >= NARRATION_LINES {
+            self.lines.pop_front();
+        }
+        self.lines.push_back((message.clone(), now));
+        self.last_message = Some(message);
+        self.last_spoken = Some(now);
+    }
+
+    fn lines(&self) -> impl Iterator<Item = &str> {
+        let now = Instant::now();
+        self.lines.iter()
+            .filter(move |&&(_, spoken_at)| now.duration_since(spoken_at) < Duration::from_millis(NARRATION_TTL_MS))
+            .map(|(line, _)| line.as_str())
+    }
+}
+
+/// Square enclosing a circle obstacle given by a segment from its center to the rim.
+fn get_circle_obstacle_bound(shape: &Segment) -> Bound {
+    let radius = shape.length();
+    Bound {
+        lt: Point { x: shape.src.x - radius, y: shape.src.y - radius, },
+        rb: Point { x: shape.src.x + radius, y: shape.src.y + radius, },
+    }
+}
+
+/// Box around the whole obstacle area: circles extend past their center to rim segment.
+fn obstacle_bound(segment: &Segment, shape: Shape) -> Bound {
+    match shape {
+        Shape::Circle => get_circle_obstacle_bound(segment),
+        Shape::Segment | Shape::Rect => get_bounding_volume(segment),
+    }
+}
+
+/// Way `PointsCutter` picks the cut point out of the fragment points.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+enum CutStrategy {
+    #[default]
+    Mean,
+    /// Coordinate wise median, splits clustered points more evenly than the mean.
+    Median,
+    /// Surface area heuristic, see `SahCutter`.
+    Sah,
+}
+
+/// Distance nearest queries are ordered by.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+enum Metric {
+    #[default]
+    Euclidean,
+    /// Sum of the distances along both axes.
+    Manhattan,
+}
+
+// distances to a cut are measured along a single axis, so they are the same in both metrics
+impl Metric {
+    fn bound_dist(self) -> fn(&Bound, &Bound) -> f64 {
+        match self {
+            Metric::Euclidean => bound_to_bound_dist,
+            Metric::Manhattan => bound_to_bound_manhattan_dist,
+        }
+    }
+
+    /// Smallest euclidean distance a `dist` measured in this metric may stand for.
+    fn euclidean_floor(self, dist: f64) -> f64 {
+        match self {
+            Metric::Euclidean => dist,
+            Metric::Manhattan => dist / 2f64.sqrt(),
+        }
+    }
+
+    fn circle_dist(self) -> fn(&circle::BoundingCircle, &circle::BoundingCircle) -> f64 {
+        match self {
+            Metric::Euclidean => circle::circle_to_circle_dist,
+            Metric::Manhattan => circle::circle_to_circle_manhattan_dist,
+        }
+    }
+}
+
+#[derive(Default)]
+struct PointsCutter {
+    strategy: CutStrategy,
+    point_min: Option<Point>,
+    point_max: Option<Point>,
+    /// Coordinates buffers reused by the median and sah strategies.
+    xs: Vec<f64>,
+    ys: Vec<f64>,
+    sah: SahCutter,
+}
+
+impl PointsCutter {
+    fn with_strategy(strategy: CutStrategy) -> PointsCutter {
+        PointsCutter { strategy, ..Default::default() }
+    }
+}
+
+/// Middle of the `values`, the mean of both middle ones for an even count.
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let middle = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        values[middle - 1] / 2. + values[middle] / 2.
+    } else {
+        values[middle]
+    }
+}
+
+impl<'s> kdvtree::GetCutPoint<Axis, Point> for &'s mut PointsCutter {
     fn cut_point<I>(&mut self, cut_axis: &Axis, points: I) -> Option<Point> where I: Iterator<Item = Point> {
-        if let Some(point_mid) = kdvtree::GetCutPoint::cut_point(&mut &mut self.base_cutter, cut_axis, points) {
-            if let (Some(pmin), Some(pmax)) = (self.base_cutter.point_min, self.base_cutter.point_max) {
-                let cut_seg = match cut_axis {
-                    &Axis::X => Segment {
-                        src: Point { x: point_mid.x, y: pmin.y, },
-                        dst: Point { x: point_mid.x, y: pmax.y, },
-                    },
-                    &Axis::Y => Segment {
-                        src: Point { x: pmin.x, y: point_mid.y, },
-                        dst: Point { x: pmax.x, y: point_mid.y, },
-                    },
-                };
-                self.cuts.push((cut_seg, cut_axis.clone()));
+        self.point_min = None;
+        self.point_max = None;
+        self.xs.clear();
+        self.ys.clear();
+        let mut point_sum = Point { x: 0., y: 0., };
+        let mut total = 0;
+        for p in points {
+            if self.strategy != CutStrategy::Mean {
+                self.xs.push(p.x);
+                self.ys.push(p.y);
             }
-            Some(point_mid)
-        } else {
+            let pmin = self.point_min.get_or_insert(p);
+            if p.x < pmin.x { pmin.x = p.x; }
+            if p.y < pmin.y { pmin.y = p.y; }
+            let pmax = self.point_max.get_or_insert(p);
+            if p.x > pmax.x { pmax.x = p.x; }
+            if p.y > pmax.y { pmax.y = p.y; }
+            point_sum.x += p.x;
+            point_sum.y += p.y;
+            total += 1;
+        }
+        if total == 0 {
             None
+        } else if let CutStrategy::Median = self.strategy {
+            Some(Point { x: median(&mut self.xs), y: median(&mut self.ys), })
+        } else if let CutStrategy::Sah = self.strategy {
+            let points = self.xs.iter().zip(self.ys.iter()).map(|(&x, &y)| Point { x, y, });
+            kdvtree::GetCutPoint::cut_point(&mut &mut self.sah, cut_axis, points)
+        } else {
+            let mean = Point {
+                x: point_sum.x / total as f64,
+                y: point_sum.y / total as f64,
+            };
+            if mean.x.is_finite() && mean.y.is_finite() {
+                Some(mean)
+            } else {
+                // coordinates sum overflowed: use the center of the points span instead
+                let (pmin, pmax) = (self.point_min?, self.point_max?);
+                trace!("points sum overflow, cutting at the span center");
+                Some(Point { x: pmin.x / 2. + pmax.x / 2., y: pmin.y / 2. + pmax.y / 2., })
+            }
+        }
+    }
+}
+
+/// Surface area heuristic cutter. Fragment boxes are restored from the points, which come as min and max
+/// corner pairs, and out of `SAH_CANDIDATES` evenly spaced positions along the axis the one with the least
+/// half perimeter of each node side weighted by the fragments on that side wins. Fragments crossing the
+/// cut count on both sides.
+#[derive(Default)]
+struct SahCutter {
+    boxes: Vec<Bound>,
+}
+
+impl kdvtree::GetCutPoint<Axis, Point> for &mut SahCutter {
+    fn cut_point<I>(&mut self, cut_axis: &Axis, mut points: I) -> Option<Point> where I: Iterator<Item = Point> {
+        self.boxes.clear();
+        while let (Some(lt), Some(rb)) = (points.next(), points.next()) {
+            self.boxes.push(Bound { lt, rb, });
+        }
+        let node = self.boxes.iter().skip(1).fold(self.boxes.first()?.clone(), |node, fragment| merge_bounds(&node, fragment));
+        let along = |point: &Point| match *cut_axis { Axis::X => point.x, Axis::Y => point.y, };
+        let (lo, hi) = (along(&node.lt), along(&node.rb));
+        let across = match *cut_axis {
+            Axis::X => node.rb.y - node.lt.y,
+            Axis::Y => node.rb.x - node.lt.x,
+        };
+        let boxes = &self.boxes;
+        let cost = |cut: f64| {
+            let (left, right) = boxes.iter().fold((0, 0), |(left, right), fragment| {
+                let on_right = along(&fragment.rb) > cut;
+                let on_left = along(&fragment.lt) < cut || !on_right;
+                (left + on_left as usize, right + on_right as usize)
+            });
+            (cut - lo + across) * left as f64 + (hi - cut + across) * right as f64
+        };
+        let best = (1 ..= SAH_CANDIDATES)
+            .map(|i| lo + (hi - lo) * i as f64 / (SAH_CANDIDATES + 1) as f64)
+            .map(|cut| (cut, cost(cut)))
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal))?
+            .0;
+        let center = Segment { src: node.lt, dst: node.rb, }.midpoint();
+        Some(match *cut_axis {
+            Axis::X => Point { x: best, y: center.y, },
+            Axis::Y => Point { x: center.x, y: best, },
+        })
+    }
+}
+
+/// Orders neighbours in `BinaryHeap` by distance, ties broken by obstacle index.
+struct RankedNeighbour(Neighbour);
+
+impl PartialEq for RankedNeighbour {
+    fn eq(&self, other: &RankedNeighbour) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedNeighbour {}
+
+impl PartialOrd for RankedNeighbour {
+    fn partial_cmp(&self, other: &RankedNeighbour) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedNeighbour {
+    fn cmp(&self, other: &RankedNeighbour) -> Ordering {
+        self.0.dist.partial_cmp(&other.0.dist).unwrap_or(Ordering::Equal).then(self.0.shape.cmp(&other.0.shape))
+    }
+}
+
+/// Picks `k` distinct obstacles of `scene` nearest to `needle` by exact weighted distance out of `neighbours`
+/// yielded by ascending `metric` box distance, keeping the best ones found so far in a bounded max-heap. A box is
+/// never farther than its obstacle, so the lazy iterator is left as soon as even the heaviest possible weight
+/// could not bring the next box ahead of the worst kept obstacle.
+fn k_nearest_exact<I>(neighbours: I, needle: &Segment, scene: &Scene, metric: Metric, k: usize) -> Result<Vec<Neighbour>, Error>
+    where I: IntoIterator<Item = Result<Neighbour, Error>>
+{
+    let mut best = BinaryHeap::with_capacity(k + 1);
+    let mut seen = HashSet::new();
+    for maybe_neighbour in neighbours {
+        let neighbour = maybe_neighbour?;
+        let full = best.len() >= k;
+        let closest_possible = metric.euclidean_floor(neighbour.dist) / MAX_OBSTACLE_WEIGHT;
+        if full && best.peek().is_none_or(|worst: &RankedNeighbour| closest_possible > worst.0.dist) {
+            break;
+        }
+        if !seen.insert(neighbour.shape) {
+            continue;
+        }
+        let dist = scene.distance(neighbour.shape, needle) / scene.meta[neighbour.shape].weight;
+        best.push(RankedNeighbour(Neighbour { dist, ..neighbour }));
+        if best.len() > k {
+            best.pop();
+        }
+    }
+    Ok(best.into_sorted_vec().into_iter().map(|ranked| ranked.0).collect())
+}
+
+/// Gradient scale which makes the farthest of `neighbours` reach the end of the colored range
+/// (60% of the scale), `None` when there is nothing to normalize against.
+fn adaptive_max_dist(neighbours: &[Neighbour]) -> Option<f64> {
+    let farthest = neighbours.iter().map(|neighbour| neighbour.dist).fold(0., f64::max);
+    if farthest > 0. && farthest.is_finite() { Some(farthest / 0.6) } else { None }
+}
+
+fn points_approx_eq(a: &Point, b: &Point, eps: f64) -> bool {
+    (a.x - b.x).abs() <= eps && (a.y - b.y).abs() <= eps
+}
+
+/// Compares segments geometrically, so a reversed segment is equal to the original one.
+fn segment_approx_eq(a: &Segment, b: &Segment, eps: f64) -> bool {
+    (points_approx_eq(&a.src, &b.src, eps) && points_approx_eq(&a.dst, &b.dst, eps)) ||
+        (points_approx_eq(&a.src, &b.dst, eps) && points_approx_eq(&a.dst, &b.src, eps))
+}
+
+/// Flags segments approximately equal to an earlier one.
+/// Candidates are only compared within `eps` of their leftmost x.
+fn duplicate_obstacles(obstacles: &[Segment], eps: f64) -> Vec<bool> {
+    let left_x = |s: &Segment| s.src.x.min(s.dst.x);
+    let mut order: Vec<_> = (0 .. obstacles.len()).collect();
+    order.sort_by(|&a, &b| left_x(&obstacles[a]).partial_cmp(&left_x(&obstacles[b])).unwrap_or(Ordering::Equal));
+    let mut position = vec![0; obstacles.len()];
+    for (pos, &index) in order.iter().enumerate() {
+        position[index] = pos;
+    }
+    let mut duplicate = vec![false; obstacles.len()];
+    for index in 0 .. obstacles.len() {
+        if duplicate[index] {
+            continue;
+        }
+        let (pos, x) = (position[index], left_x(&obstacles[index]));
+        let before = order[.. pos].iter().rev().take_while(|&&other| x - left_x(&obstacles[other]) <= eps);
+        let after = order[pos + 1 ..].iter().take_while(|&&other| left_x(&obstacles[other]) - x <= eps);
+        for &other in before.chain(after) {
+            if other > index && segment_approx_eq(&obstacles[index], &obstacles[other], eps) {
+                duplicate[other] = true;
+            }
+        }
+    }
+    duplicate
+}
+
+fn closest_point_on_segment(point: &Point, segment: &Segment) -> Point {
+    let (dx, dy) = (segment.dst.x - segment.src.x, segment.dst.y - segment.src.y);
+    let length_sq = dx * dx + dy * dy;
+    if length_sq == 0. {
+        return segment.src;
+    }
+    let t = ((point.x - segment.src.x) * dx + (point.y - segment.src.y) * dy) / length_sq;
+    let t = t.clamp(0., 1.);
+    Point { x: segment.src.x + t * dx, y: segment.src.y + t * dy, }
+}
+
+/// Whether `needle` touches the area of a circle obstacle given by a segment from its center to the rim.
+fn segment_hits_circle(needle: &Segment, circle: &Segment) -> bool {
+    let closest = closest_point_on_segment(&circle.src, needle);
+    Segment { src: circle.src, dst: closest, }.length() <= circle.length()
+}
+
+/// Point of `a` closest to `a.src` which also lies on `b`, for parallel segments only. Those
+/// touch when they are on the same line and their projections on it overlap.
+fn collinear_overlap(a: &Segment, b: &Segment) -> Option<Point> {
+    // the longer segment gives the line direction, so a zero length one is still tested against it
+    let line = if a.length() >= b.length() { a } else { b };
+    let (dx, dy) = (line.dst.x - line.src.x, line.dst.y - line.src.y);
+    let length_sq = dx * dx + dy * dy;
+    if length_sq == 0. {
+        // both are points
+        return if a.src.x == b.src.x && a.src.y == b.src.y { Some(a.src) } else { None };
+    }
+    let on_line = |p: &Point| (p.x - line.src.x) * dy - (p.y - line.src.y) * dx == 0.;
+    if !(on_line(&a.src) && on_line(&a.dst) && on_line(&b.src) && on_line(&b.dst)) {
+        return None;
+    }
+    let along = |p: &Point| ((p.x - line.src.x) * dx + (p.y - line.src.y) * dy) / length_sq;
+    let (a_src, a_dst, b_src, b_dst) = (along(&a.src), along(&a.dst), along(&b.src), along(&b.dst));
+    let lo = a_src.min(a_dst).max(b_src.min(b_dst));
+    let hi = a_src.max(a_dst).min(b_src.max(b_dst));
+    if lo > hi {
+        return None;
+    }
+    let t = a_src.clamp(lo, hi);
+    Some(Point { x: line.src.x + t * dx, y: line.src.y + t * dy, })
+}
+
+/// Edges of a rectangle obstacle given by its diagonal, clockwise from the top left corner.
+fn rect_edges(obstacle: &Segment) -> [Segment; 4] {
+    let Bound { lt, rb, } = get_bounding_volume(obstacle);
+    let (lb, rt) = (Point { x: lt.x, y: rb.y, }, Point { x: rb.x, y: lt.y, });
+    [Segment { src: lt, dst: rt, }, Segment { src: rt, dst: rb, }, Segment { src: rb, dst: lb, }, Segment { src: lb, dst: lt, }]
+}
+
+/// Points where `needle` meets the outline of the obstacle: the crossing of two segments,
+/// the crossings of the rectangle edges or the circle rim.
+fn contact_points(needle: &Segment, obstacle: &Segment, shape: Shape) -> Vec<Point> {
+    match shape {
+        Shape::Segment =>
+            segment_intersection(needle, obstacle).into_iter().collect(),
+        Shape::Rect =>
+            rect_edges(obstacle).iter().filter_map(|edge| segment_intersection(needle, edge)).collect(),
+        Shape::Circle => {
+            let (dx, dy) = (needle.dst.x - needle.src.x, needle.dst.y - needle.src.y);
+            let (fx, fy) = (needle.src.x - obstacle.src.x, needle.src.y - obstacle.src.y);
+            let a = dx * dx + dy * dy;
+            let b = 2. * (fx * dx + fy * dy);
+            let c = fx * fx + fy * fy - obstacle.length() * obstacle.length();
+            let discriminant = b * b - 4. * a * c;
+            if a == 0. || discriminant < 0. {
+                return Vec::new();
+            }
+            let root = discriminant.sqrt();
+            let mut ts = vec![(-b - root) / (2. * a)];
+            if root > 0. {
+                ts.push((-b + root) / (2. * a));
+            }
+            ts.into_iter()
+                .filter(|t| (0. ..= 1.).contains(t))
+                .map(|t| Point { x: needle.src.x + t * dx, y: needle.src.y + t * dy, })
+                .collect()
+        },
+    }
+}
+
+fn segment_segment_distance(a: &Segment, b: &Segment) -> f64 {
+    closest_points(a, b).length()
+}
+
+/// Exact distance from `needle` to the obstacle, zero when it reaches into a rectangle or a circle.
+fn obstacle_distance(needle: &Segment, obstacle: &Segment, shape: Shape) -> f64 {
+    match shape {
+        Shape::Segment =>
+            segment_segment_distance(needle, obstacle),
+        Shape::Rect => {
+            let inside = bound_to_bound_dist(&get_bounding_volume(&Segment { src: needle.src, dst: needle.src, }), &get_bounding_volume(obstacle)) == 0.;
+            if inside {
+                0.
+            } else {
+                rect_edges(obstacle).iter().map(|edge| segment_segment_distance(needle, edge)).fold(f64::INFINITY, f64::min)
+            }
+        },
+        Shape::Circle => {
+            let closest = closest_point_on_segment(&obstacle.src, needle);
+            (Segment { src: obstacle.src, dst: closest, }.length() - obstacle.length()).max(0.)
+        },
+    }
+}
+
+/// Replaces bounding volume distances of `neighbours` with exact weighted distances of their obstacles and reorders them.
+fn refine_neighbours(neighbours: Vec<Neighbour>, needle: &Segment, scene: &Scene) -> Vec<Neighbour> {
+    let mut refined: Vec<_> = neighbours.into_iter()
+        .map(|neighbour| {
+            let dist = scene.distance(neighbour.shape, needle);
+            Neighbour { dist: dist / scene.meta[neighbour.shape].weight, ..neighbour }
+        })
+        .collect();
+    refined.sort_by(|a, b| a.dist.partial_cmp(&b.dist).unwrap_or(Ordering::Equal).then(a.shape.cmp(&b.shape)));
+    refined
+}
+
+/// Shortest segment from a point of `a` to a point of `b`, zero length where they cross.
+fn closest_points(a: &Segment, b: &Segment) -> Segment {
+    if let Some(crossing) = segment_intersection(a, b) {
+        return Segment { src: crossing, dst: crossing, };
+    }
+    // disjoint segments are closest at an end of one of them
+    let candidates = [
+        Segment { src: a.src, dst: closest_point_on_segment(&a.src, b), },
+        Segment { src: a.dst, dst: closest_point_on_segment(&a.dst, b), },
+        Segment { src: closest_point_on_segment(&b.src, a), dst: b.src, },
+        Segment { src: closest_point_on_segment(&b.dst, a), dst: b.dst, },
+    ];
+    candidates.iter()
+        .fold(candidates[0], |best, candidate| if candidate.length() < best.length() { *candidate } else { best })
+}
+
+/// Crossing point of two segments, `None` for disjoint ones. Collinear segments may overlap along
+/// a whole stretch, the point of it closest to `a.src` is reported then.
+fn segment_intersection(a: &Segment, b: &Segment) -> Option<Point> {
+    let (adx, ady) = (a.dst.x - a.src.x, a.dst.y - a.src.y);
+    let (bdx, bdy) = (b.dst.x - b.src.x, b.dst.y - b.src.y);
+    let denom = adx * bdy - ady * bdx;
+    if denom == 0. {
+        return collinear_overlap(a, b);
+    }
+    let (ox, oy) = (b.src.x - a.src.x, b.src.y - a.src.y);
+    let t = (ox * bdy - oy * bdx) / denom;
+    let u = (ox * ady - oy * adx) / denom;
+    if (0. ..= 1.).contains(&t) && (0. ..= 1.).contains(&u) {
+        Some(Point { x: a.src.x + t * adx, y: a.src.y + t * ady, })
+    } else {
+        None
+    }
+}
+
+/// Order independent hash of the scene: every obstacle is hashed by its shape, geometry and attributes with
+/// numbers quantized to `SCENE_HASH_QUANTUM`. Segments match regardless of their direction and rectangles
+/// regardless of the diagonal they were drawn by, circles by their center and radius.
+fn scene_hash(scene: &Scene) -> u64 {
+    fn fnv1a(hash: u64, value: i64) -> u64 {
+        value.to_le_bytes().iter()
+            .fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+    }
+    fn quantize(value: f64) -> i64 {
+        (value / SCENE_HASH_QUANTUM).round() as i64
+    }
+
+    scene.obstacles.iter().zip(scene.meta.iter())
+        .map(|(segment, meta)| {
+            let geometry = match meta.shape {
+                Shape::Segment => {
+                    let (a, b) = ((quantize(segment.src.x), quantize(segment.src.y)), (quantize(segment.dst.x), quantize(segment.dst.y)));
+                    let (a, b) = if a <= b { (a, b) } else { (b, a) };
+                    [0, a.0, a.1, b.0, b.1]
+                },
+                Shape::Rect => {
+                    let Bound { lt, rb, } = get_bounding_volume(segment);
+                    [1, quantize(lt.x), quantize(lt.y), quantize(rb.x), quantize(rb.y)]
+                },
+                Shape::Circle =>
+                    [2, quantize(segment.src.x), quantize(segment.src.y), quantize(segment.length()), 0],
+            };
+            let attributes = [quantize(meta.width), quantize(meta.weight), meta.group as i64];
+            geometry.iter().chain(attributes.iter()).fold(0xcbf29ce484222325, |hash, &value| fnv1a(hash, value))
+        })
+        .fold(0, |hash: u64, obstacle_hash| hash.wrapping_add(obstacle_hash))
+}
+
+/// Rectangle bounds of all the obstacles by index, circles included, see `obstacle_bound`.
+fn obstacle_bounds<F>(obstacles: &[Segment], shape_of: F, parallel: bool) -> Vec<Bound> where F: Fn(usize) -> Shape {
+    let mut bounds = bounding_volumes(obstacles, get_bounding_volume, parallel);
+    for (i, bound) in bounds.iter_mut().enumerate().filter(|&(i, _)| shape_of(i) == Shape::Circle) {
+        *bound = get_circle_obstacle_bound(&obstacles[i]);
+    }
+    bounds
+}
+
+/// Computes bounding volumes for all obstacles up front, so the tree build only indexes into them.
+fn bounding_volumes<B, F>(obstacles: &[Segment], get_bv: F, parallel: bool) -> Vec<B>
+    where F: Fn(&Segment) -> B + Sync + Send, B: Send
+{
+    if parallel {
+        obstacles.par_iter().map(get_bv).collect()
+    } else {
+        obstacles.iter().map(get_bv).collect()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum VolumeKind { Aabb, Circle, }
+
+/// Fragment bounding volume of either supported kind, ready for rendering.
+#[derive(Clone, Debug)]
+enum Volume {
+    Aabb(Bound),
+    Circle(circle::BoundingCircle),
+}
+
+impl Volume {
+    /// Exact bit pattern of the volume, equal for identical fragments.
+    fn key(&self) -> [u64; 4] {
+        match *self {
+            Volume::Aabb(Bound { lt, rb, }) =>
+                [lt.x.to_bits(), lt.y.to_bits(), rb.x.to_bits(), rb.y.to_bits()],
+            Volume::Circle(circle::BoundingCircle { center, radius, }) =>
+                [center.x.to_bits(), center.y.to_bits(), radius.to_bits(), u64::MAX],
+        }
+    }
+}
+
+/// Shape and needle fragments taking part in `collisions`, each distinct one listed once in the order met.
+/// A fragment meeting several fragments of the other side is yielded by every such intersection.
+fn unique_fragments(collisions: &[Collision]) -> (Vec<&Volume>, Vec<&Volume>) {
+    let (mut shape_keys, mut needle_keys) = (HashSet::new(), HashSet::new());
+    let shape_fragments = collisions.iter()
+        .map(|collision| &collision.shape_fragment)
+        .filter(|fragment| shape_keys.insert(fragment.key()))
+        .collect();
+    let needle_fragments = collisions.iter()
+        .map(|collision| &collision.needle_fragment)
+        .filter(|fragment| needle_keys.insert(fragment.key()))
+        .collect();
+    (shape_fragments, needle_fragments)
+}
+
+/// Overall shape of a built tree.
+struct TreeStats {
+    nodes: usize,
+    /// Number of levels, a lone root counts as one.
+    depth: usize,
+}
+
+/// Sizes up the tree from its `nodes` as listed by `SceneTree::nodes`.
+fn tree_stats(nodes: &[(usize, Option<Bound>)]) -> TreeStats {
+    TreeStats {
+        nodes: nodes.len(),
+        depth: nodes.iter().map(|&(depth, _)| depth + 1).max().unwrap_or(0),
+    }
+}
+
+/// Finds the smallest bounds over the fragments of a leaf among `nodes` (preorder depths with their
+/// own fragments bounds, see `SceneTree::nodes`) containing `point`, `None` when no leaf covers it.
+fn leaf_region(nodes: &[(usize, Option<Bound>)], point: &Point) -> Option<Bound> {
+    let area = |bound: &Bound| (bound.rb.x - bound.lt.x) * (bound.rb.y - bound.lt.y);
+    nodes.iter()
+        .enumerate()
+        // in preorder a node is a leaf unless the next one is deeper
+        .filter(|&(i, &(depth, _))| nodes.get(i + 1).is_none_or(|&(next_depth, _)| next_depth <= depth))
+        .filter_map(|(_, (_, bound))| bound.as_ref())
+        .filter(|bound| bound.lt.x <= point.x && point.x <= bound.rb.x && bound.lt.y <= point.y && point.y <= bound.rb.y)
+        .min_by(|a, b| area(a).partial_cmp(&area(b)).unwrap_or(Ordering::Equal))
+        .cloned()
+}
+
+/// Tree callbacks a single intersection query made, counted while the tree was descending.
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+struct QueryStats {
+    /// Needle fragment corners compared against node cut points and obstacle fragment boxes.
+    comparisons: usize,
+    /// Needle fragments split in two, at node cut points it straddles or while refining against an obstacle fragment.
+    needle_cuts: usize,
+    /// Cut points picked to refine a needle fragment overlapping an obstacle fragment box.
+    cut_picks: usize,
+}
+
+/// Passes cut point picks on to `cutter`, counting them.
+struct CountingCutter<'a> {
+    cutter: &'a mut PointsCutter,
+    picks: usize,
+}
+
+impl kdvtree::GetCutPoint<Axis, Point> for &mut CountingCutter<'_> {
+    fn cut_point<I>(&mut self, cut_axis: &Axis, points: I) -> Option<Point> where I: Iterator<Item = Point> {
+        self.picks += 1;
+        kdvtree::GetCutPoint::cut_point(&mut &mut *self.cutter, cut_axis, points)
+    }
+}
+
+struct Collision {
+    shape: usize,
+    shape_fragment: Volume,
+    needle_fragment: Volume,
+}
+
+struct Neighbour {
+    dist: f64,
+    shape: usize,
+    shape_fragment: Volume,
+}
+
+/// Built tree along with the cut limit it was built with, needles are cut with the same one,
+/// and the metric of its nearest queries.
+enum SceneTree {
+    Aabb(kdvtree::KdvTree<Axis, Point, Bound, usize>, f64, Metric),
+    Circle(kdvtree::KdvTree<Axis, Point, circle::BoundingCircle, usize>, f64, Metric),
+    /// Main tree and a small one over obstacles added after it was built, queried together.
+    Overflow(Box<SceneTree>, Box<SceneTree>),
+}
+
+/// Maps a fragment cut failure during the tree `query` into an `Error`.
+fn cut_failed(query: &'static str) -> impl Fn(()) -> Error {
+    move |()| Error::CutFragment { query, }
+}
+
+impl SceneTree {
+    /// Builds the tree over `obstacles`, precomputing shape bounding volumes once (on the rayon pool when `parallel` is set),
+    /// fragments narrower than `cut_limit` are not split any further.
+    fn build<C>(kind: VolumeKind, obstacles: &[Segment], cutter: C, cut_limit: f64, parallel: bool) -> Result<SceneTree, Error>
+        where C: kdvtree::GetCutPoint<Axis, Point>
+    {
+        let shapes: Vec<_> = (0 .. obstacles.len()).collect();
+        SceneTree::build_subset(kind, obstacles, &shapes, cutter, cut_limit, parallel)
+    }
+
+    /// Builds the tree over `shapes` indices only, query results still refer to `obstacles` positions.
+    fn build_subset<C>(kind: VolumeKind, obstacles: &[Segment], shapes: &[usize], cutter: C, cut_limit: f64, parallel: bool) -> Result<SceneTree, Error>
+        where C: kdvtree::GetCutPoint<Axis, Point>
+    {
+        SceneTree::build_shapes(kind, obstacles, |_| Shape::Segment, shapes, cutter, cut_limit, parallel)
+    }
+
+    /// Same as `build_subset` for obstacles of any `Shape` given by `shape_of` their index.
+    fn build_shapes<C, F>(
+        kind: VolumeKind,
+        obstacles: &[Segment],
+        shape_of: F,
+        shapes: &[usize],
+        cutter: C,
+        cut_limit: f64,
+        parallel: bool,
+    )
+        -> Result<SceneTree, Error>
+        where C: kdvtree::GetCutPoint<Axis, Point>, F: Fn(usize) -> Shape
+    {
+        match kind {
+            VolumeKind::Aabb =>
+                SceneTree::build_aabb(obstacles, &obstacle_bounds(obstacles, &shape_of, parallel), shape_of, shapes, cutter, cut_limit),
+            VolumeKind::Circle =>
+                SceneTree::build_circle(obstacles, &obstacle_bounds(obstacles, &shape_of, parallel), shape_of, shapes, cutter, cut_limit),
+        }
+    }
+
+    /// Circle tree over `obstacles`, rectangles are put into circles around their `bounds` from `obstacle_bounds`.
+    fn build_circle<C, F>(obstacles: &[Segment], bounds: &[Bound], shape_of: F, shapes: &[usize], cutter: C, cut_limit: f64) -> Result<SceneTree, Error>
+        where C: kdvtree::GetCutPoint<Axis, Point>, F: Fn(usize) -> Shape
+    {
+        let tree = kdvtree::KdvTree::build(
+            iter::once(Axis::X).chain(iter::once(Axis::Y)),
+            shapes.iter().cloned(),
+            cmp_points,
+            |&shape_index: &usize| match shape_of(shape_index) {
+                Shape::Segment => circle::get_bounding_circle(&obstacles[shape_index]),
+                Shape::Rect => circle::get_bounding_circle(&Segment { src: bounds[shape_index].lt, dst: bounds[shape_index].rb, }),
+                Shape::Circle => circle::get_circle_obstacle_circle(&obstacles[shape_index]),
+            },
+            cutter,
+            |&shape_index: &_, fragment: &_, cut_axis: &_, cut_point: &_| match shape_of(shape_index) {
+                Shape::Segment => circle::cut_segment_circle(&obstacles[shape_index], fragment, cut_axis, cut_point, cut_limit),
+                // a circle fragment does not tell which part of the rectangle it covers,
+                // so rectangles stay whole within their circumscribed circle
+                Shape::Rect => Ok(None),
+                // a circle is its own bounding circle, so there is nothing to refine
+                Shape::Circle => Ok(None),
+            },
+        );
+        Ok(SceneTree::Circle(tree.map_err(cut_failed("build"))?, cut_limit, Metric::Euclidean))
+    }
+
+    /// Rectangle tree over `obstacles` reusing their `bounds` from `obstacle_bounds`.
+    fn build_aabb<C, F>(obstacles: &[Segment], bounds: &[Bound], shape_of: F, shapes: &[usize], cutter: C, cut_limit: f64) -> Result<SceneTree, Error>
+        where C: kdvtree::GetCutPoint<Axis, Point>, F: Fn(usize) -> Shape
+    {
+        let tree = kdvtree::KdvTree::build(
+            iter::once(Axis::X).chain(iter::once(Axis::Y)),
+            shapes.iter().cloned(),
+            cmp_points,
+            |&shape_index: &usize| bounds[shape_index].clone(),
+            cutter,
+            |&shape_index: &_, fragment: &_, cut_axis: &_, cut_point: &_| match shape_of(shape_index) {
+                Shape::Segment => cut_segment_fragment(&obstacles[shape_index], fragment, cut_axis, cut_point, cut_limit),
+                // box halves of a circle are loose near the rim, but still cover every part of it
+                Shape::Rect | Shape::Circle => cut_rect_fragment(fragment, cut_axis, cut_point, cut_limit),
+            },
+        );
+        Ok(SceneTree::Aabb(tree.map_err(cut_failed("build"))?, cut_limit, Metric::Euclidean))
+    }
+
+    /// Tree over no obstacles at all, with nothing to cut building it cannot fail.
+    fn empty(kind: VolumeKind, cut_limit: f64) -> SceneTree {
+        let axis = || iter::once(Axis::X).chain(iter::once(Axis::Y));
+        match kind {
+            VolumeKind::Aabb => {
+                let tree = kdvtree::KdvTree::build(
+                    axis(),
+                    iter::empty(),
+                    cmp_points,
+                    |&shape_index: &usize| -> Bound { unreachable!("obstacle {} in an empty tree", shape_index) },
+                    &mut PointsCutter::default(),
+                    |_: &usize, _: &Bound, _: &Axis, _: &Point| Ok::<_, Infallible>(None),
+                );
+                match tree {
+                    Ok(tree) => SceneTree::Aabb(tree, cut_limit, Metric::Euclidean),
+                    Err(never) => match never {},
+                }
+            },
+            VolumeKind::Circle => {
+                let tree = kdvtree::KdvTree::build(
+                    axis(),
+                    iter::empty(),
+                    cmp_points,
+                    |&shape_index: &usize| -> circle::BoundingCircle { unreachable!("obstacle {} in an empty tree", shape_index) },
+                    &mut PointsCutter::default(),
+                    |_: &usize, _: &circle::BoundingCircle, _: &Axis, _: &Point| Ok::<_, Infallible>(None),
+                );
+                match tree {
+                    Ok(tree) => SceneTree::Circle(tree, cut_limit, Metric::Euclidean),
+                    Err(never) => match never {},
+                }
+            },
+        }
+    }
+
+    fn intersects(&self, needle: &Segment, cutter: &mut PointsCutter) -> Result<Vec<Collision>, Error> {
+        self.intersects_counted(needle, cutter, &mut QueryStats::default())
+    }
+
+    /// Same as `intersects`, adding the tree callbacks the query made to `stats`.
+    fn intersects_counted(&self, needle: &Segment, cutter: &mut PointsCutter, stats: &mut QueryStats) -> Result<Vec<Collision>, Error> {
+        let comparisons = Cell::new(0);
+        let cmp = |axis: &Axis, a: &Point, b: &Point| {
+            comparisons.set(comparisons.get() + 1);
+            cmp_points(axis, a, b)
+        };
+        let mut needle_cuts = 0;
+        let mut counting_cutter = CountingCutter { cutter, picks: 0, };
+        let collisions = match *self {
+            SceneTree::Aabb(ref tree, cut_limit, _) =>
+                tree.intersects(needle, &cmp, get_bounding_volume, &mut counting_cutter, |shape: &_, fragment: &_, cut_axis: &_, cut_point: &_| {
+                    needle_cuts += 1;
+                    cut_segment_fragment(shape, fragment, cut_axis, cut_point, cut_limit)
+                })
+                .map(|maybe_intersection| {
+                    let kdvtree::Intersection { shape: &shape, shape_fragment, needle_fragment, } = maybe_intersection
+                        .map_err(cut_failed("intersects"))?;
+                    Ok(Collision {
+                        shape,
+                        shape_fragment: Volume::Aabb(shape_fragment.clone()),
+                        needle_fragment: Volume::Aabb(needle_fragment),
+                    })
+                })
+                .collect(),
+            SceneTree::Circle(ref tree, cut_limit, _) =>
+                tree.intersects(needle, &cmp, circle::get_bounding_circle, &mut counting_cutter, |shape: &_, fragment: &_, cut_axis: &_, cut_point: &_| {
+                    needle_cuts += 1;
+                    circle::cut_segment_circle(shape, fragment, cut_axis, cut_point, cut_limit)
+                })
+                .map(|maybe_intersection| {
+                    let kdvtree::Intersection { shape: &shape, shape_fragment, needle_fragment, } = maybe_intersection
+                        .map_err(cut_failed("intersects"))?;
+                    Ok(Collision {
+                        shape,
+                        shape_fragment: Volume::Circle(shape_fragment.clone()),
+                        needle_fragment: Volume::Circle(needle_fragment),
+                    })
+                })
+                .collect(),
+            SceneTree::Overflow(ref tree, ref overflow) => {
+                let mut collisions = tree.intersects_counted(needle, counting_cutter.cutter, stats)?;
+                collisions.extend(overflow.intersects_counted(needle, counting_cutter.cutter, stats)?);
+                Ok(collisions)
+            },
+        };
+        stats.comparisons += comparisons.get();
+        stats.needle_cuts += needle_cuts;
+        stats.cut_picks += counting_cutter.picks;
+        collisions
+    }
+
+    /// Tree pruned search for obstacle fragments overlapping the `area` rectangle, which is split
+    /// along the tree cuts the same way rectangle obstacles are.
+    fn overlaps(&self, area: &Bound, cutter: &mut PointsCutter) -> Result<Vec<usize>, Error> {
+        let get_bv = |area: &Bound| area.clone();
+        match *self {
+            SceneTree::Aabb(ref tree, cut_limit, _) =>
+                tree.intersects(area, cmp_points, get_bv, cutter, |_: &_, fragment: &_, cut_axis: &_, cut_point: &_| {
+                    cut_rect_fragment(fragment, cut_axis, cut_point, cut_limit)
+                })
+                .map(|maybe_intersection| maybe_intersection.map(|intersection| *intersection.shape).map_err(cut_failed("overlaps")))
+                .collect(),
+            SceneTree::Circle(ref tree, cut_limit, _) =>
+                tree.intersects(area, cmp_points, get_bv, cutter, |_: &_, fragment: &_, cut_axis: &_, cut_point: &_| {
+                    cut_rect_fragment(fragment, cut_axis, cut_point, cut_limit)
+                })
+                .map(|maybe_intersection| maybe_intersection.map(|intersection| *intersection.shape).map_err(cut_failed("overlaps")))
+                .collect(),
+            SceneTree::Overflow(ref tree, ref overflow) => {
+                let mut shapes = tree.overlaps(area, cutter)?;
+                shapes.extend(overflow.overlaps(area, cutter)?);
+                Ok(shapes)
+            },
+        }
+    }
+
+    /// Accumulates indices of obstacles hit by at least one of the `needles`.
+    fn collided_by(&self, needles: &[Segment], cutter: &mut PointsCutter) -> Result<HashSet<usize>, Error> {
+        let mut collided = HashSet::new();
+        for needle in needles {
+            collided.extend(self.intersects(needle, cutter)?.into_iter().map(|collision| collision.shape));
+        }
+        Ok(collided)
+    }
+
+    fn metric(&self) -> Metric {
+        match *self {
+            SceneTree::Aabb(_, _, metric) | SceneTree::Circle(_, _, metric) => metric,
+            SceneTree::Overflow(ref tree, _) => tree.metric(),
+        }
+    }
+
+    /// Switches nearest queries to `metric`, trees are built with the euclidean one.
+    fn with_metric(self, metric: Metric) -> SceneTree {
+        match self {
+            SceneTree::Aabb(tree, cut_limit, _) => SceneTree::Aabb(tree, cut_limit, metric),
+            SceneTree::Circle(tree, cut_limit, _) => SceneTree::Circle(tree, cut_limit, metric),
+            SceneTree::Overflow(tree, overflow) =>
+                SceneTree::Overflow(Box::new(tree.with_metric(metric)), Box::new(overflow.with_metric(metric))),
+        }
+    }
+
+    /// Queries `overflow` along with the main tree, replacing any overflow tree attached before.
+    fn with_overflow(self, overflow: SceneTree) -> SceneTree {
+        let metric = self.metric();
+        SceneTree::Overflow(Box::new(self.into_main()), Box::new(overflow.with_metric(metric)))
+    }
+
+    /// The tree without obstacles added to its overflow tree.
+    fn into_main(self) -> SceneTree {
+        match self {
+            SceneTree::Overflow(tree, _) => tree.into_main(),
+            tree => tree,
+        }
+    }
+
+    fn nearest<'a>(&'a self, needle: &'a Segment) -> Box<dyn Iterator<Item = Result<Neighbour, Error>> + 'a> {
+        match *self {
+            SceneTree::Aabb(ref tree, cut_limit, metric) =>
+                Box::new(tree.nearest(
+                    needle,
+                    cmp_points,
+                    get_bounding_volume,
+                    move |shape: &_, fragment: &_, cut_axis: &_, cut_point: &_| cut_segment_fragment(shape, fragment, cut_axis, cut_point, cut_limit),
+                    bound_to_cut_point_dist,
+                    metric.bound_dist(),
+                ).map(|maybe_neighbour| {
+                    let kdvtree::NearestShape { dist, shape: &shape, shape_fragment, } =
+                        maybe_neighbour.map_err(cut_failed("nearest"))?;
+                    Ok(Neighbour { dist, shape, shape_fragment: Volume::Aabb(shape_fragment.clone()), })
+                })),
+            SceneTree::Circle(ref tree, cut_limit, metric) =>
+                Box::new(tree.nearest(
+                    needle,
+                    cmp_points,
+                    circle::get_bounding_circle,
+                    move |shape: &_, fragment: &_, cut_axis: &_, cut_point: &_| circle::cut_segment_circle(shape, fragment, cut_axis, cut_point, cut_limit),
+                    circle::circle_to_cut_point_dist,
+                    metric.circle_dist(),
+                ).map(|maybe_neighbour| {
+                    let kdvtree::NearestShape { dist, shape: &shape, shape_fragment, } =
+                        maybe_neighbour.map_err(cut_failed("nearest"))?;
+                    Ok(Neighbour { dist, shape, shape_fragment: Volume::Circle(shape_fragment.clone()), })
+                })),
+            SceneTree::Overflow(ref tree, ref overflow) => {
+                // both streams come ascending by distance, so merging them keeps the order
+                let mut main = tree.nearest(needle).peekable();
+                let mut extra = overflow.nearest(needle).peekable();
+                Box::new(iter::from_fn(move || {
+                    let take_extra = match (main.peek(), extra.peek()) {
+                        (Some(Ok(a)), Some(Ok(b))) => b.dist < a.dist,
+                        (_, Some(Err(_))) | (None, Some(_)) => true,
+                        _ => false,
+                    };
+                    if take_extra { extra.next() } else { main.next() }
+                }))
+            },
+        }
+    }
+
+    /// Lists tree nodes in preorder as their depths and bounds over the fragments stored directly in them.
+    fn nodes(&self) -> Vec<(usize, Option<Bound>)> {
+        fn node_bound<'a, B, I>(fragments: I) -> Option<Bound> where B: kdvtree::BoundingVolume<Point> + 'a, I: Iterator<Item = &'a B> {
+            fragments
+                .map(|fragment| Bound { lt: fragment.min_corner(), rb: fragment.max_corner(), })
+                .fold(None, |acc, bound| Some(match acc { Some(acc) => merge_bounds(&acc, &bound), None => bound, }))
+        }
+        match *self {
+            SceneTree::Aabb(ref tree, _, _) =>
+                tree.iter().map(|node| (node.depth(), node_bound(node.shapes().map(|(_, fragment)| fragment)))).collect(),
+            SceneTree::Circle(ref tree, _, _) =>
+                tree.iter().map(|node| (node.depth(), node_bound(node.shapes().map(|(_, fragment)| fragment)))).collect(),
+            SceneTree::Overflow(ref tree, ref overflow) => {
+                let mut nodes = tree.nodes();
+                nodes.extend(overflow.nodes());
+                nodes
+            },
+        }
+    }
+
+    fn nearest_one(&self, point: Point) -> Result<Option<Neighbour>, Error> {
+        self.k_nearest(&Segment { src: point, dst: point, }, 1).map(|mut neighbours| neighbours.pop())
+    }
+
+    /// Collects up to `k` nearest distinct obstacles into an owned vector sorted ascending by distance,
+    /// ties broken by obstacle index. Each obstacle is reported once with its closest fragment, and
+    /// `k` larger than the obstacles count yields them all.
+    fn k_nearest(&self, needle: &Segment, k: usize) -> Result<Vec<Neighbour>, Error> {
+        self.k_nearest_where(needle, k, |_| true)
+    }
+
+    /// Same as `k_nearest` but only obstacles accepted by `filter` count towards `k`. The tree is still
+    /// traversed over all obstacles, so a rare match far away costs a scan of everything nearer.
+    fn k_nearest_where<F>(&self, needle: &Segment, k: usize, mut filter: F) -> Result<Vec<Neighbour>, Error> where F: FnMut(usize) -> bool {
+        let mut seen = HashSet::new();
+        let mut neighbours: Vec<_> = self.nearest(needle)
+            .filter(|maybe_neighbour| maybe_neighbour.as_ref().map_or(true, |neighbour| filter(neighbour.shape) && seen.insert(neighbour.shape)))
+            .take(k)
+            .collect::<Result<_, _>>()?;
+        neighbours.sort_by(|a, b| a.dist.partial_cmp(&b.dist).unwrap_or(Ordering::Equal).then(a.shape.cmp(&b.shape)));
+        Ok(neighbours)
+    }
+}
+
+/// Pair of trees for scenes where some obstacles move every frame: the static tree is built once over
+/// the obstacles not marked as dynamic, and only the small dynamic one is rebuilt after they move.
+/// Query results of both trees are merged.
+struct SplitTree {
+    kind: VolumeKind,
+    /// Obstacle shapes by index as they were when the trees were built.
+    shapes: Vec<Shape>,
+    strategy: CutStrategy,
+    cut_limit: f64,
+    static_tree: SceneTree,
+    dynamic_shapes: Vec<usize>,
+    dynamic_tree: SceneTree,
+}
+
+impl SplitTree {
+    fn build(kind: VolumeKind, scene: &Scene, strategy: CutStrategy, cut_limit: f64) -> Result<SplitTree, Error> {
+        let (dynamic_shapes, static_shapes): (Vec<_>, Vec<_>) = (0 .. scene.obstacles.len())
+            .partition(|&index| scene.meta[index].dynamic);
+        let shapes: Vec<_> = scene.meta.iter().map(|meta| meta.shape).collect();
+        let mut cutter = PointsCutter::with_strategy(strategy);
+        Ok(SplitTree {
+            kind,
+            static_tree: SceneTree::build_shapes(kind, &scene.obstacles, |i| shapes[i], &static_shapes, &mut cutter, cut_limit, false)?,
+            dynamic_tree: SceneTree::build_shapes(kind, &scene.obstacles, |i| shapes[i], &dynamic_shapes, &mut cutter, cut_limit, false)?,
+            shapes,
+            strategy,
+            cut_limit,
+            dynamic_shapes,
+        })
+    }
+
+    /// Catches up with moved dynamic obstacles, static ones must stay intact.
+    fn rebuild_dynamic(&mut self, obstacles: &[Segment]) -> Result<(), Error> {
+        let mut cutter = PointsCutter::with_strategy(self.strategy);
+        let shapes = &self.shapes;
+        self.dynamic_tree = SceneTree::build_shapes(self.kind, obstacles, |i| shapes[i], &self.dynamic_shapes, &mut cutter, self.cut_limit, false)?;
+        Ok(())
+    }
+
+    fn intersects(&self, needle: &Segment, cutter: &mut PointsCutter) -> Result<Vec<Collision>, Error> {
+        let mut collisions = self.static_tree.intersects(needle, cutter)?;
+        collisions.extend(self.dynamic_tree.intersects(needle, cutter)?);
+        Ok(collisions)
+    }
+}
+
+/// Collide mode query: tree intersections of `probe` with the obstacles of `scene`, counting the query work into `stats`.
+fn scene_collisions(tree: &SceneTree, scene: &Scene, probe: &Segment, cutter: &mut PointsCutter, stats: &mut QueryStats) -> Result<Vec<Collision>, Error> {
+    let mut collisions = tree.intersects_counted(probe, cutter, stats)?;
+    // circle and capsule boxes are loose, so check the actual distance to them
+    collisions.retain(|collision| {
+        let (obstacle, meta) = (&scene.obstacles[collision.shape], &scene.meta[collision.shape]);
+        match (meta.shape, meta.capsule_radius()) {
+            (Shape::Circle, _) => segment_hits_circle(probe, obstacle),
+            (_, Some(radius)) => hits_capsule(probe, obstacle, radius),
+            _ => true,
+        }
+    });
+    Ok(collisions)
+}
+
+/// Whether `probe` comes within `radius` of the `obstacle` segment.
+fn hits_capsule(probe: &Segment, obstacle: &Segment, radius: f64) -> bool {
+    segment_segment_distance(probe, obstacle) <= radius
+}
+
+/// Indices of the `active_shapes` obstacles `probe` actually hits, checked one by one without the tree, ascending.
+fn exact_collisions(scene: &Scene, active_shapes: &[usize], probe: &Segment) -> Vec<usize> {
+    let mut hits: Vec<_> = active_shapes.iter()
+        .cloned()
+        .filter(|&i| scene.hit_dist(i, probe).is_some())
+        .collect();
+    hits.sort();
+    hits
+}
+
+/// Whether the outlines of two obstacles meet: a rectangle is crossed by its edges and a circle by its rim.
+fn obstacles_touch(a: &Segment, a_shape: Shape, b: &Segment, b_shape: Shape) -> bool {
+    match (a_shape, b_shape) {
+        (Shape::Circle, Shape::Circle) => {
+            let centers = Segment { src: a.src, dst: b.src, }.length();
+            (a.length() - b.length()).abs() <= centers && centers <= a.length() + b.length()
+        },
+        (Shape::Circle, _) =>
+            obstacles_touch(b, b_shape, a, a_shape),
+        (Shape::Rect, _) =>
+            rect_edges(a).iter().any(|edge| !contact_points(edge, b, b_shape).is_empty()),
+        (Shape::Segment, _) =>
+            !contact_points(a, b, b_shape).is_empty(),
+    }
+}
+
+/// Sorted pairs `(i, j)` with `i < j` of the `shapes` obstacles crossing each other. Every obstacle is used as a
+/// tree needle in turn: segments directly, other shapes and capsules through their bounding rectangle.
+fn intersecting_pairs(tree: &SceneTree, scene: &Scene, shapes: &[usize], cutter: &mut PointsCutter) -> Result<Vec<(usize, usize)>, Error> {
+    let mut pairs = Vec::new();
+    for &i in shapes {
+        let candidates = match scene.meta[i].indexed_shape() {
+            Shape::Segment =>
+                tree.intersects(&scene.obstacles[i], cutter)?.into_iter().map(|collision| collision.shape).collect(),
+            Shape::Rect | Shape::Circle =>
+                tree.overlaps(&scene.bound(i), cutter)?,
+        };
+        pairs.extend(candidates.into_iter()
+            .filter(|&j| j != i)
+            .filter(|&j| scene.touch(i, j))
+            .map(|j| (i.min(j), i.max(j))));
+    }
+    pairs.sort();
+    pairs.dedup();
+    Ok(pairs)
+}
+
+/// Sorted indices of the obstacles whose bounding volumes overlap the `area` rectangle. Candidates come from
+/// the tree, so segments are only reported when their own tighter fragments reach into the rectangle.
+fn obstacles_in_range(tree: &SceneTree, bounds: &[Bound], area: &Bound, cutter: &mut PointsCutter) -> Result<Vec<usize>, Error> {
+    let mut shapes = tree.overlaps(area, cutter)?;
+    shapes.sort();
+    shapes.dedup();
+    // circle tree fragments are tested by their boxes, which stick out of the obstacles
+    shapes.retain(|&shape| bound_to_bound_dist(&bounds[shape], area) == 0.);
+    Ok(shapes)
+}
+
+/// Segment from `origin` through `through` long enough to leave the `bound` area,
+/// `None` when both points coincide and there is no direction to cast along, or the area is just the `origin` point.
+fn cast_ray(origin: &Point, through: &Point, bound: &Bound) -> Option<Segment> {
+    let direction = Segment { src: *origin, dst: *through, }.direction()?;
+    let reach = [bound.lt, bound.rb, Point { x: bound.lt.x, y: bound.rb.y, }, Point { x: bound.rb.x, y: bound.lt.y, }].iter()
+        .map(|corner| Segment { src: *origin, dst: *corner, }.length())
+        .fold(0., f64::max);
+    if reach == 0. {
+        return None;
+    }
+    Some(Segment { src: *origin, dst: Point { x: origin.x + direction.x * reach, y: origin.y + direction.y * reach, }, })
+}
+
+/// Distance along `ray` from its `src` to the first contact with the obstacle, zero when the ray starts inside of it.
+fn ray_hit_dist(ray: &Segment, obstacle: &Segment, shape: Shape) -> Option<f64> {
+    let along = |point: Point| Segment { src: ray.src, dst: point, }.length();
+    match shape {
+        Shape::Segment =>
+            segment_intersection(ray, obstacle).map(along),
+        Shape::Rect => {
+            let Bound { lt, rb, } = get_bounding_volume(obstacle);
+            if (lt.x ..= rb.x).contains(&ray.src.x) && (lt.y ..= rb.y).contains(&ray.src.y) {
+                return Some(0.);
+            }
+            contact_points(ray, obstacle, Shape::Rect).into_iter()
+                .map(along)
+                .fold(None, |acc: Option<f64>, dist| Some(acc.map_or(dist, |acc| acc.min(dist))))
+        },
+        Shape::Circle => {
+            let direction = ray.direction()?;
+            let (fx, fy) = (ray.src.x - obstacle.src.x, ray.src.y - obstacle.src.y);
+            let b = fx * direction.x + fy * direction.y;
+            let c = fx * fx + fy * fy - obstacle.length() * obstacle.length();
+            if c <= 0. {
+                return Some(0.);
+            }
+            let discriminant = b * b - c;
+            let dist = -b - discriminant.sqrt();
+            if discriminant >= 0. && dist >= 0. && dist <= ray.length() { Some(dist) } else { None }
+        },
+    }
+}
+
+/// Distance along `ray` from its `src` to the first point within `radius` of the `obstacle` segment.
+fn capsule_hit_dist(ray: &Segment, obstacle: &Segment, radius: f64) -> Option<f64> {
+    let start_dist = Segment { src: ray.src, dst: closest_point_on_segment(&ray.src, obstacle), }.length();
+    if start_dist <= radius {
+        return Some(0.);
+    }
+    // the capsule outline is made of two sides along the obstacle and circles around its ends
+    let end_circle = |center: Point| (Segment { src: center, dst: Point { x: center.x + radius, y: center.y, }, }, Shape::Circle);
+    let mut outline = vec![end_circle(obstacle.src), end_circle(obstacle.dst)];
+    if let Some(direction) = obstacle.direction() {
+        for &side in [radius, -radius].iter() {
+            let offset = |point: Point| Point { x: point.x - direction.y * side, y: point.y + direction.x * side, };
+            outline.push((Segment { src: offset(obstacle.src), dst: offset(obstacle.dst), }, Shape::Segment));
+        }
+    }
+    outline.iter()
+        .filter_map(|&(part, shape)| ray_hit_dist(ray, &part, shape))
+        .fold(None, |acc: Option<f64>, dist| Some(acc.map_or(dist, |acc| acc.min(dist))))
+}
+
+/// Closest obstacle hit along `ray` out of the tree intersections, as its index and the distance to the contact.
+fn first_hit(tree: &SceneTree, scene: &Scene, ray: &Segment, cutter: &mut PointsCutter) -> Result<Option<(usize, f64)>, Error> {
+    Ok(tree.intersects(ray, cutter)?.into_iter()
+        .filter_map(|collision| scene.hit_dist(collision.shape, ray).map(|dist| (collision.shape, dist)))
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal).then(a.0.cmp(&b.0))))
+}
+
+/// Builds an AABB tree over `obstacles` and returns every intersection with `probe` as
+/// the shape index along with the shape and the probe fragment bounds, without any rendering.
+fn all_intersections(obstacles: &[Segment], probe: &Segment, strategy: CutStrategy, cut_limit: f64) -> Result<Vec<(usize, Bound, Bound)>, Error> {
+    let mut cutter = PointsCutter::with_strategy(strategy);
+    let tree = SceneTree::build(VolumeKind::Aabb, obstacles, &mut cutter, cut_limit, false)?;
+    Ok(tree.intersects(probe, &mut cutter)?.into_iter()
+        .filter_map(|collision| match collision {
+            Collision { shape, shape_fragment: Volume::Aabb(shape_bound), needle_fragment: Volume::Aabb(needle_bound), } =>
+                Some((shape, shape_bound, needle_bound)),
+            _ =>
+                None,
+        })
+        .collect())
+}
+
+/// Indices of obstacles appended to the scene since a tree was built over `built_shapes` of `built_obstacles`,
+/// or `None` when the scene changed in any other way.
+fn appended_shapes(built_obstacles: &[Segment], built_shapes: &[usize], obstacles: &[Segment], shapes: &[usize]) -> Option<Vec<usize>> {
+    // circles are not symmetric in their ends, so reversed segments count as changed
+    let unchanged = obstacles.len() >= built_obstacles.len() &&
+        built_obstacles.iter().zip(obstacles)
+            .all(|(a, b)| points_approx_eq(&a.src, &b.src, 0.) && points_approx_eq(&a.dst, &b.dst, 0.)) &&
+        shapes.starts_with(built_shapes);
+    if !unchanged {
+        return None;
+    }
+    let appended = &shapes[built_shapes.len() ..];
+    if appended.iter().all(|&i| i >= built_obstacles.len()) {
+        Some(appended.to_vec())
+    } else {
+        None
+    }
+}
+
+/// Whether a tree built over `built_shapes` of `built_obstacles` with `built_bounds` may keep answering queries
+/// about the `job` scene: every obstacle it indexes is still in place with the same bounds, only appended ones are missing.
+fn old_tree_usable(built_obstacles: &[Segment], built_bounds: &[Bound], built_shapes: &[usize], job: &TreeBuildJob) -> bool {
+    appended_shapes(built_obstacles, built_shapes, &job.obstacles, &job.active_shapes).is_some() &&
+        built_bounds.len() <= job.bounds.len() &&
+        built_bounds.iter().zip(job.bounds.iter())
+            .all(|(a, b)| points_approx_eq(&a.lt, &b.lt, 0.) && points_approx_eq(&a.rb, &b.rb, 0.))
+}
+
+/// Everything needed to build the tree of a scene snapshot, on this thread or a background one.
+struct TreeBuildJob {
+    kind: VolumeKind,
+    obstacles: Vec<Segment>,
+    /// Obstacle bounds from `obstacle_bounds`, reused by rectangle trees.
+    bounds: Vec<Bound>,
+    shapes: Vec<Shape>,
+    active_shapes: Vec<usize>,
+    strategy: CutStrategy,
+    metric: Metric,
+    cut_limit: f64,
+}
+
+struct TreeBuild {
+    active_shapes: Vec<usize>,
+    tree: Result<SceneTree, Error>,
+    /// Cuts made while building the tree, for the mesh display.
+    cutter: VisualCutter,
+    /// Scene snapshot the tree was built over.
+    obstacles: Vec<Segment>,
+    bounds: Vec<Bound>,
+}
+
+impl TreeBuildJob {
+    fn run(self) -> TreeBuild {
+        let mut cutter = VisualCutter::with_strategy(self.strategy);
+        let shapes = self.shapes;
+        let tree = match self.kind {
+            VolumeKind::Aabb =>
+                SceneTree::build_aabb(&self.obstacles, &self.bounds, |i| shapes[i], &self.active_shapes, &mut cutter, self.cut_limit),
+            VolumeKind::Circle =>
+                SceneTree::build_circle(&self.obstacles, &self.bounds, |i| shapes[i], &self.active_shapes, &mut cutter, self.cut_limit),
+        };
+        let metric = self.metric;
+        let tree = tree.map(|tree| tree.with_metric(metric));
+        TreeBuild { active_shapes: self.active_shapes, tree, cutter, obstacles: self.obstacles, bounds: self.bounds, }
+    }
+
+    /// Builds the small tree over just the `appended` obstacles, its cuts are not visualised.
+    fn run_overflow(&self, appended: &[usize]) -> Result<SceneTree, Error> {
+        let mut cutter = PointsCutter::with_strategy(self.strategy);
+        let shapes = &self.shapes;
+        let tree = match self.kind {
+            VolumeKind::Aabb =>
+                SceneTree::build_aabb(&self.obstacles, &self.bounds, |i| shapes[i], appended, &mut cutter, self.cut_limit)?,
+            VolumeKind::Circle =>
+                SceneTree::build_circle(&self.obstacles, &self.bounds, |i| shapes[i], appended, &mut cutter, self.cut_limit)?,
+        };
+        Ok(tree.with_metric(self.metric))
+    }
+
+    /// Runs the job on a new thread, the build arrives on the returned channel.
+    fn spawn(self) -> mpsc::Receiver<TreeBuild> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            // the receiver is gone when the scene changed again meanwhile
+            let _ = tx.send(self.run());
+        });
+        rx
+    }
+}
+
+struct VisualCutter {
+    cuts: Vec<(Segment, Axis)>,
+    base_cutter: PointsCutter,
+}
+
+impl VisualCutter {
+    fn with_strategy(strategy: CutStrategy) -> VisualCutter {
+        VisualCutter {
+            cuts: Vec::new(),
+            base_cutter: PointsCutter::with_strategy(strategy),
+        }
+    }
+
+    /// Forgets collected cuts while keeping the allocated buffer for the next build.
+    fn clear(&mut self) {
+        self.cuts.clear();
+    }
+}
+
+impl<'s> kdvtree::GetCutPoint<Axis, Point> for &'s mut VisualCutter {
+    fn cut_point<I>(&mut self, cut_axis: &Axis, points: I) -> Option<Point> where I: Iterator<Item = Point> {
+        if let Some(point_mid) = kdvtree::GetCutPoint::cut_point(&mut &mut self.base_cutter, cut_axis, points) {
+            if let (Some(pmin), Some(pmax)) = (self.base_cutter.point_min, self.base_cutter.point_max) {
+                let cut_seg = match cut_axis {
+                    &Axis::X => Segment {
+                        src: Point { x: point_mid.x, y: pmin.y, },
+                        dst: Point { x: point_mid.x, y: pmax.y, },
+                    },
+                    &Axis::Y => Segment {
+                        src: Point { x: pmin.x, y: point_mid.y, },
+                        dst: Point { x: pmax.x, y: point_mid.y, },
+                    },
+                };
+                self.cuts.push((cut_seg, cut_axis.clone()));
+            }
+            Some(point_mid)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter;
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+    use piston_window::{Event, Input, Button, ButtonArgs, ButtonState, MouseButton, Motion, Key};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use super::{Point, Segment, Scene, SceneTree, SplitTree, VolumeKind, VisualCutter, PointsCutter, ObstacleMeta, POINT_EPSILON, SCREEN_WIDTH, SCREEN_HEIGHT};
+    use super::{bench, capture, console, input_log, scene_file, selftest, svg, theme, circle, Axis, Bound, Business, Env, Shape};
+    use super::{scene_hash, cut_rect_fragment, segment_hits_circle, get_circle_obstacle_bound, QueryStats, get_bounding_volume, nearest_regions, index_color, duplicate_obstacles, all_intersections, parse_color, contrast_color, explain_collisions, length_gradient, adaptive_max_dist, points_approx_eq, segment_approx_eq, probe_grid, coverage_percent, nearest_endpoint, joined_endpoints, Endpoint, GRAB_DISTANCE, snap_point, grid_lines, FrameTimer, FRAME_TIME_SAMPLES, tree_stats, leaf_region, ZOOM_STEP, MIN_ZOOM, screen_side_clamped, MIN_SCREEN_SIDE, MAX_SCREEN_SIDE, cut_segment_fragment, KDTREE_CUT_LIMIT, k_nearest_exact, obstacles_in_range, bound_to_bound_dist, cast_ray, first_hit, ray_hit_dist, bound_to_cut_point_dist, CutStrategy, unique_fragments, merge_bounds, scene_collisions, Error, closest_points, segment_intersection, contact_points, distance_field, heat_color, intersecting_pairs, label_worthy, constrain_angle, TreeBuildJob, obstacle_bounds, obstacle_bound, Metric, segment_segment_distance, obstacle_distance, refine_neighbours, appended_shapes, exact_collisions, old_tree_usable, TreeBuild};
+
+    fn seg(x1: f64, y1: f64, x2: f64, y2: f64) -> Segment {
+        Segment { src: Point { x: x1, y: y1, }, dst: Point { x: x2, y: y2, }, }
+    }
+
+    fn scene_of(obstacles: &[(Segment, ObstacleMeta)]) -> Scene {
+        let mut scene = Scene::new();
+        for &(segment, meta) in obstacles {
+            scene.push(segment, meta);
+        }
+        scene
+    }
+
+    #[test]
+    fn scene_hash_ignores_order() {
+        let obstacles = [seg(10., 10., 100., 100.), seg(50., 20., 50., 300.), seg(0.5, 7.25, 33., 1.)];
+        let segments = |obstacles: &[Segment]| -> Vec<_> { obstacles.iter().map(|&segment| (segment, Default::default())).collect() };
+        let mut reordered = vec![obstacles[2], obstacles[0], obstacles[1]];
+        assert_eq!(scene_hash(&scene_of(&segments(&obstacles))), scene_hash(&scene_of(&segments(&reordered))));
+        reordered[1] = seg(100., 100., 10., 10.);
+        assert_eq!(scene_hash(&scene_of(&segments(&obstacles))), scene_hash(&scene_of(&segments(&reordered))));
+        // a rectangle is the same whichever diagonal it was drawn by
+        let rect = ObstacleMeta { shape: Shape::Rect, ..Default::default() };
+        assert_eq!(scene_hash(&scene_of(&[(seg(10., 10., 100., 100.), rect)])), scene_hash(&scene_of(&[(seg(10., 100., 100., 10.), rect)])));
+    }
+
+    #[test]
+    fn scene_hash_detects_moves() {
+        let plain = |a: Segment, b: Segment| scene_of(&[(a, Default::default()), (b, Default::default())]);
+        let scene = plain(seg(10., 10., 100., 100.), seg(50., 20., 50., 300.));
+        assert_ne!(scene_hash(&scene), scene_hash(&plain(seg(10., 10., 100., 100.), seg(50., 20., 51., 300.))));
+        assert_eq!(scene_hash(&scene), scene_hash(&plain(seg(10., 10., 100., 100.), seg(50., 20., 50. + 1e-6, 300.))));
+    }
+
+    #[test]
+    fn scene_hash_tells_shapes_and_attributes_apart() {
+        let diagonal = seg(10., 10., 100., 100.);
+        let hash = |meta: ObstacleMeta| scene_hash(&scene_of(&[(diagonal, meta)]));
+        let plain = hash(Default::default());
+        assert_ne!(plain, hash(ObstacleMeta { shape: Shape::Rect, ..Default::default() }));
+        assert_ne!(plain, hash(ObstacleMeta { shape: Shape::Circle, ..Default::default() }));
+        assert_ne!(plain, hash(ObstacleMeta { width: 4., ..Default::default() }));
+        assert_ne!(plain, hash(ObstacleMeta { weight: 2., ..Default::default() }));
+        assert_ne!(plain, hash(ObstacleMeta { group: 1, ..Default::default() }));
+        // center and rim swapped make another circle
+        let circle = ObstacleMeta { shape: Shape::Circle, ..Default::default() };
+        let swapped = seg(100., 100., 10., 10.);
+        assert_ne!(hash(circle), scene_hash(&scene_of(&[(swapped, circle)])));
+        assert_eq!(hash(circle), scene_hash(&scene_of(&[(seg(10., 10., 10., 10. + 90. * 2f64.sqrt()), circle)])));
+    }
+
+    fn cross(o: &Point, a: &Point, b: &Point) -> f64 {
+        (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+    }
+
+    fn segments_cross(a: &Segment, b: &Segment) -> bool {
+        let d1 = cross(&b.src, &b.dst, &a.src);
+        let d2 = cross(&b.src, &b.dst, &a.dst);
+        let d3 = cross(&a.src, &a.dst, &b.src);
+        let d4 = cross(&a.src, &a.dst, &b.dst);
+        d1 * d2 < 0. && d3 * d4 < 0.
+    }
+
+    fn collided(kind: VolumeKind, obstacles: &[Segment], probe: &Segment) -> HashSet<usize> {
+        let mut visual_cutter = VisualCutter::with_strategy(CutStrategy::Mean);
+        let tree = SceneTree::build(kind, obstacles, &mut visual_cutter, KDTREE_CUT_LIMIT, false).unwrap();
+        let mut cutter = PointsCutter::default();
+        tree.intersects(probe, &mut cutter).unwrap().into_iter().map(|collision| collision.shape).collect()
+    }
+
+    #[test]
+    fn circle_and_aabb_find_same_isolated_crossings() {
+        let obstacles = vec![seg(100., 100., 200., 180.), seg(120., 190., 190., 90.), seg(400., 300., 600., 460.)];
+        let probe = seg(90., 140., 210., 150.);
+        let expected: HashSet<_> = [0, 1].iter().cloned().collect();
+        assert_eq!(collided(VolumeKind::Aabb, &obstacles, &probe), expected);
+        assert_eq!(collided(VolumeKind::Circle, &obstacles, &probe), expected);
+    }
+
+    #[test]
+    fn circle_and_aabb_cover_all_crossings() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([17, 29, 31, 43]);
+        let random_seg = |rng: &mut XorShiftRng| seg(
+            rng.gen_range(0., 640.), rng.gen_range(0., 480.), rng.gen_range(0., 640.), rng.gen_range(0., 480.),
+        );
+        let obstacles: Vec<_> = (0 .. 64).map(|_| random_seg(&mut rng)).collect();
+        for _ in 0 .. 32 {
+            let probe = random_seg(&mut rng);
+            let crossings: HashSet<_> = obstacles.iter().enumerate()
+                .filter(|&(_, obstacle)| segments_cross(obstacle, &probe))
+                .map(|(index, _)| index)
+                .collect();
+            let aabb = collided(VolumeKind::Aabb, &obstacles, &probe);
+            let circle = collided(VolumeKind::Circle, &obstacles, &probe);
+            assert!(aabb.is_superset(&crossings), "aabb {:?} misses some of {:?}", aabb, crossings);
+            assert!(circle.is_superset(&crossings), "circle {:?} misses some of {:?}", circle, crossings);
+        }
+    }
+
+    fn brute_force_collisions(obstacles: &[Segment], probe: &Segment) -> HashSet<usize> {
+        obstacles.iter().enumerate()
+            .filter(|&(_, obstacle)| segments_cross(obstacle, probe))
+            .map(|(index, _)| index)
+            .collect()
+    }
+
+    #[test]
+    fn tree_finds_all_brute_force_collisions() {
+        for seed in 1 .. 17 {
+            let mut rng: XorShiftRng = SeedableRng::from_seed([seed, seed * 7, seed * 13, 101]);
+            let random_seg = |rng: &mut XorShiftRng| seg(
+                rng.gen_range(0., 640.), rng.gen_range(0., 480.), rng.gen_range(0., 640.), rng.gen_range(0., 480.),
+            );
+            let total = rng.gen_range(10, 200);
+            let obstacles: Vec<_> = (0 .. total).map(|_| random_seg(&mut rng)).collect();
+            for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+                let mut visual_cutter = VisualCutter::with_strategy(CutStrategy::Mean);
+                let tree = SceneTree::build(kind, &obstacles, &mut visual_cutter, KDTREE_CUT_LIMIT, false).unwrap();
+                let mut cutter = PointsCutter::default();
+                for _ in 0 .. 32 {
+                    let probe = random_seg(&mut rng);
+                    // tree reports bounding volume overlaps, so only confirmed crossings are compared
+                    let found: HashSet<_> = tree.intersects(&probe, &mut cutter).unwrap().into_iter()
+                        .map(|collision| collision.shape)
+                        .filter(|&index| segments_cross(&obstacles[index], &probe))
+                        .collect();
+                    assert_eq!(found, brute_force_collisions(&obstacles, &probe), "seed {}, {:?} probe {:?}", seed, kind, probe);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parallel_build_matches_serial() {
+        let mut rng = bench::seeded_rng(407);
+        let obstacles = bench::random_scene(&mut rng, 2_000, 640., 480., 64.);
+        let probes = bench::random_scene(&mut rng, 32, 640., 480., 64.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let serial = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let parallel = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, true).unwrap();
+            let mut cutter = PointsCutter::default();
+            for probe in probes.iter() {
+                let collisions = |tree: &SceneTree, cutter: &mut PointsCutter| -> Vec<_> {
+                    tree.intersects(probe, cutter).unwrap().into_iter()
+                        .map(|collision| format!("{} {:?} {:?}", collision.shape, collision.shape_fragment, collision.needle_fragment))
+                        .collect()
+                };
+                assert_eq!(collisions(&serial, &mut cutter), collisions(&parallel, &mut cutter));
+                let neighbours = |tree: &SceneTree| -> Vec<_> {
+                    tree.nearest(probe).map(Result::unwrap).take(16)
+                        .map(|neighbour| format!("{} {} {:?}", neighbour.shape, neighbour.dist, neighbour.shape_fragment))
+                        .collect()
+                };
+                assert_eq!(neighbours(&serial), neighbours(&parallel));
+            }
+        }
+    }
+
+    #[test]
+    fn huge_coordinates_keep_distances_finite() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([1e9 as u32, 15, 1015, 7]);
+        let huge = 1e15;
+        // short segments scattered far apart, as long ones would be cut into countless fragments
+        let random_seg = |rng: &mut XorShiftRng| {
+            let (x, y) = (rng.gen_range(-huge, huge), rng.gen_range(-huge, huge));
+            seg(x, y, x + rng.gen_range(-64., 64.), y + rng.gen_range(-64., 64.))
+        };
+        let obstacles: Vec<_> = (0 .. 64).map(|_| random_seg(&mut rng)).collect();
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let mut cutter = PointsCutter::default();
+            for _ in 0 .. 8 {
+                let probe = random_seg(&mut rng);
+                tree.intersects(&probe, &mut cutter).unwrap();
+                let dists: Vec<_> = tree.nearest(&probe).map(|neighbour| neighbour.unwrap().dist).collect();
+                assert!(!dists.is_empty());
+                assert!(dists.iter().all(|dist| dist.is_finite()), "{:?} distances {:?}", kind, dists);
+            }
+            let far = Point { x: 1e300, y: -1e300, };
+            assert!(tree.nearest_one(far).unwrap().is_some_and(|neighbour| neighbour.dist.is_finite()));
+        }
+    }
+
+    #[test]
+    fn k_nearest_returns_sorted_distinct_obstacles() {
+        let obstacles = vec![
+            seg(100., 100., 100., 300.),
+            seg(400., 100., 400., 300.),
+            seg(200., 100., 200., 300.),
+            seg(0., 200., 600., 200.),
+        ];
+        let probe = seg(150., 150., 150., 160.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let shapes = |k| -> Vec<_> { tree.k_nearest(&probe, k).unwrap().into_iter().map(|neighbour| neighbour.shape).collect() };
+            assert_eq!(shapes(0), Vec::<usize>::new());
+            assert_eq!(shapes(2).len(), 2);
+            let all = tree.k_nearest(&probe, 100).unwrap();
+            assert_eq!(all.len(), obstacles.len(), "{:?}", kind);
+            assert_eq!(all.last().map(|neighbour| neighbour.shape), Some(1));
+            assert!(all.windows(2).all(|pair| (pair[0].dist, pair[0].shape) <= (pair[1].dist, pair[1].shape)));
+        }
+    }
+
+    #[test]
+    fn probe_grid_covers_crossing_obstacles() {
+        let probes = probe_grid(Point { x: 0., y: 0., }, 640., 480., 40.);
+        assert_eq!(probes.len(), 16 * 12);
+        assert!(probes.iter().all(|probe| probe.dst.x <= 640. && probe.dst.y <= 480.));
+        let obstacles = vec![seg(0., 215., 640., 215.), seg(315., 0., 315., 480.), seg(1000., 1000., 1100., 1100.)];
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let hit = tree.collided_by(&probes, &mut PointsCutter::default()).unwrap();
+            let expected: HashSet<_> = [0, 1].iter().cloned().collect();
+            assert_eq!(hit, expected, "{:?}", kind);
+            assert!((coverage_percent(hit.len(), obstacles.len()) - 200. / 3.).abs() < 1e-9);
+        }
+        assert_eq!(coverage_percent(0, 0), 0.);
+    }
+
+    #[test]
+    fn scene_file_round_trip() {
+        let obstacles = vec![seg(10., 20.5, 300., 400.), seg(-1e-3, 7., 1e15, 0.1)];
+        let layers = scene_file::SceneLayers {
+            obstacles,
+            rects: vec![seg(50., 60., 70., 80.)],
+            circles: vec![seg(320., 240., 330., 240.)],
+            ghosts: vec![seg(1., 2., 3., 4.)],
+            meta: vec![
+                ObstacleMeta { weight: 2.5, width: 6., group: 3, polyline: Some(7), ..Default::default() },
+                ObstacleMeta { polyline: Some(7), ..Default::default() },
+                ObstacleMeta { shape: Shape::Rect, weight: 0.5, ..Default::default() },
+                ObstacleMeta { shape: Shape::Circle, group: 1, ..Default::default() },
+            ],
+        };
+        let text = scene_file::to_json(&layers);
+        assert!(text.contains("\"version\": 5"));
+        let loaded = scene_file::from_json(&text).unwrap();
+        assert_eq!(loaded.obstacles.len(), layers.obstacles.len());
+        assert_eq!(loaded.rects.len(), layers.rects.len());
+        assert_eq!(loaded.circles.len(), layers.circles.len());
+        assert_eq!(loaded.ghosts.len(), layers.ghosts.len());
+        let all = |layers: &scene_file::SceneLayers| -> Vec<Segment> {
+            layers.obstacles.iter().chain(layers.rects.iter()).chain(layers.circles.iter()).chain(layers.ghosts.iter()).cloned().collect()
+        };
+        for (a, b) in all(&layers).iter().zip(all(&loaded).iter()) {
+            assert!(segment_approx_eq(a, b, 0.));
+        }
+        let attributes = |meta: &[ObstacleMeta]| -> Vec<_> {
+            meta.iter().map(|meta| (meta.shape, meta.weight, meta.width, meta.group, meta.polyline)).collect()
+        };
+        assert_eq!(attributes(&loaded.meta), attributes(&layers.meta));
+        let empty = scene_file::from_json(&scene_file::to_json(&Default::default())).unwrap();
+        assert!(all(&empty).is_empty());
+        let v1 = scene_file::from_json(r#"{ "version": 1, "obstacles": [[1, 2, 3, 4]] }"#).unwrap();
+        assert_eq!((v1.obstacles.len(), v1.rects.len(), v1.ghosts.len()), (1, 0, 0));
+        let v2 = scene_file::from_json(r#"{ "version": 2, "obstacles": [], "ghosts": [[1, 2, 3, 4]] }"#).unwrap();
+        assert_eq!((v2.obstacles.len(), v2.rects.len(), v2.ghosts.len()), (0, 0, 1));
+        let v3 = scene_file::from_json(r#"{ "version": 3, "obstacles": [], "rects": [[1, 2, 3, 4]], "ghosts": [] }"#).unwrap();
+        assert_eq!((v3.rects.len(), v3.circles.len()), (1, 0));
+        let v4 = scene_file::from_json(r#"{ "version": 4, "obstacles": [], "rects": [], "circles": [[1, 2, 3, 4]], "ghosts": [] }"#).unwrap();
+        assert_eq!((v4.circles.len(), v4.meta.len()), (1, 0));
+        // metadata has to describe every obstacle with the shape of its layer
+        for meta in [r#"[]"#, r#"[{ "shape": "rect" }]"#, r#"[{ "weight": -1 }]"#].iter() {
+            let text = format!(r#"{{ "version": 5, "obstacles": [[1, 2, 3, 4]], "rects": [], "circles": [], "ghosts": [], "meta": {} }}"#, meta);
+            assert!(matches!(scene_file::from_json(&text), Err(scene_file::Error::InvalidField("meta"))), "{}", meta);
+        }
+    }
+
+    #[test]
+    fn scene_file_save_and_load() {
+        let path = std::env::temp_dir().join(format!("kdtree-demo-scene-{}.json", std::process::id()));
+        let obstacles = vec![seg(10., 20., 30., 40.), seg(0.5, 0.25, 600., 470.)];
+        let layers = scene_file::SceneLayers { obstacles: obstacles.clone(), ..Default::default() };
+        scene_file::save(&path, &layers).unwrap();
+        let loaded = scene_file::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.obstacles.len(), obstacles.len());
+        assert!(obstacles.iter().zip(loaded.obstacles.iter()).all(|(a, b)| segment_approx_eq(a, b, 0.)));
+        match scene_file::save(path.join("missing-dir").join("scene.json"), &layers) {
+            Err(scene_file::Error::Write { .. }) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn recorded_input_replays_in_order() {
+        let path = std::env::temp_dir().join(format!("kdtree-demo-input-{}.log", std::process::id()));
+        let events = vec![
+            Event::Input(Input::Move(Motion::MouseCursor(12.5, 40.))),
+            Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Left), state: ButtonState::Press, scancode: None, })),
+            Event::Input(Input::Button(ButtonArgs { button: Button::Mouse(MouseButton::Left), state: ButtonState::Release, scancode: None, })),
+            Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::M), state: ButtonState::Release, scancode: Some(50), })),
+            Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::C), state: ButtonState::Release, scancode: None, })),
+        ];
+        {
+            let mut recorder = input_log::Recorder::create(&path).unwrap();
+            for event in &events {
+                recorder.record(event).unwrap();
+            }
+            // only the replayable subset is written down
+            recorder.record(&Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::R), state: ButtonState::Release, scancode: None, }))).unwrap();
+            recorder.record(&Event::Input(Input::Button(ButtonArgs { button: Button::Keyboard(Key::M), state: ButtonState::Press, scancode: None, }))).unwrap();
+        }
+        let mut replay = input_log::Replay::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let later = Instant::now() + Duration::from_secs(60);
+        let replayed: Vec<_> = iter::from_fn(|| replay.next_due(later)).collect();
+        assert!(replay.is_finished());
+        let logged = |events: &[Event]| events.iter().map(|event| input_log::InputEvent::from_event(event).unwrap()).collect::<Vec<_>>();
+        assert_eq!(logged(&replayed), logged(&events));
+
+        assert_eq!(input_log::parse_line("1500 move 3.5 -2").unwrap(), (Duration::from_millis(1500), input_log::InputEvent::Cursor(3.5, -2.)));
+        assert_eq!(input_log::parse_line("20 key Q").unwrap(), (Duration::from_millis(20), input_log::InputEvent::Key(Key::Q)));
+        for malformed in &["move 1 2", "10 move 1", "10 move 1 NaN", "10 key R", "10 press 1", "10 scroll"] {
+            assert!(input_log::parse_line(malformed).is_err(), "{:?} parsed", malformed);
+        }
+    }
+
+    #[test]
+    fn failed_save_shows_error_banner() {
+        let mut env = Env::new(Business::Construct, false, false);
+        let mut scene = Scene::new();
+        scene.push(seg(10., 20., 30., 40.), Default::default());
+        let path = std::env::temp_dir().join(format!("kdtree-demo-missing-{}", std::process::id())).join("scene.json");
+        env.save_scene(&path, &scene);
+        assert!(env.current_error().is_some_and(|message| message.contains("failed to save")));
+        env.error_message = None;
+        let path = std::env::temp_dir().join(format!("kdtree-demo-banner-{}.json", std::process::id()));
+        env.save_scene(&path, &scene);
+        std::fs::remove_file(&path).unwrap();
+        assert!(env.current_error().is_none());
+    }
+
+    #[test]
+    fn theme_file_overrides_defaults() {
+        assert_eq!(theme::from_toml("").unwrap(), theme::Theme::default());
+        let custom = theme::from_toml("# colorblind friendly\n[colors]\nobstacle = [0, 0.45, 0.7]\ncursor_ray = [1, 1, 1, 0.5] # translucent\n").unwrap();
+        assert_eq!(custom.obstacle, [0., 0.45, 0.7, 1.]);
+        assert_eq!(custom.cursor(&Business::Ray), [1., 1., 1., 0.5]);
+        assert_eq!(custom.cut_x, theme::Theme::default().cut_x);
+        assert!(matches!(theme::from_toml("obstacle = [1, 2, 3]"), Err(theme::Error::InvalidColor(ref key)) if key == "obstacle"));
+        assert!(matches!(theme::from_toml("[colors]\ncut_y = [1, 0]"), Err(theme::Error::InvalidColor(ref key)) if key == "cut_y"));
+        assert!(matches!(theme::from_toml("\nobstacle [1, 0, 0]"), Err(theme::Error::Parse(..))));
+        assert!(matches!(theme::from_toml("obstacle = \"red\""), Err(theme::Error::Parse(..))));
+        assert!(matches!(theme::from_toml("background = [1, 0, 0]"), Err(theme::Error::UnknownKey(ref key)) if key == "background"));
+        // real TOML rather than a line format: multiline arrays and quoted keys
+        let multiline = theme::from_toml("[colors]\n\"highlight\" = [\n  1,\n  0.5,\n  0,\n]\n").unwrap();
+        assert_eq!(multiline.highlight, [1., 0.5, 0., 1.]);
+    }
+
+    #[test]
+    fn scene_file_rejects_unknown_version() {
+        match scene_file::from_json(r#"{ "version": 6, "shapes": [{ "polygon": [1, 2, 3] }] }"#) {
+            Err(scene_file::Error::UnsupportedSceneVersion(6)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match scene_file::from_json(r#"{ "obstacles": [] }"#) {
+            Err(scene_file::Error::MissingField("version")) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        match scene_file::from_json(r#"{ "version": 1, "obstacles": [[1, 2, 3]] }"#) {
+            Err(scene_file::Error::InvalidField("obstacles")) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(scene_file::from_json(r#"{ "version": 1, "obstacles": [ }"#).is_err());
+        // a nesting bomb is a parse error rather than a stack overflow
+        match scene_file::from_json(&"[".repeat(200_000)) {
+            Err(scene_file::Error::Parse(..)) => (),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn console_parses_commands() {
+        assert!(matches!(console::parse("clear"), Ok(console::Command::Clear)));
+        assert!(matches!(console::parse("  save  "), Ok(console::Command::Save(None))));
+        match console::parse("save scene.json") {
+            Ok(console::Command::Save(Some(path))) => assert_eq!(path.to_str(), Some("scene.json")),
+            _ => panic!("save with a file expected"),
+        }
+        assert!(matches!(console::parse("mode collide"), Ok(console::Command::Mode(Business::Collide))));
+        assert!(matches!(console::parse("k 8"), Ok(console::Command::Nearest(Some(8)))));
+        assert!(matches!(console::parse("k all"), Ok(console::Command::Nearest(None))));
+        assert!(matches!(console::parse("group 3"), Ok(console::Command::Group(3))));
+        assert!(matches!(console::parse("only 2"), Ok(console::Command::Only(Some(2)))));
+        assert!(matches!(console::parse("only all"), Ok(console::Command::Only(None))));
+        assert!(matches!(console::parse("width 4.5"), Ok(console::Command::Width(width)) if width == 4.5));
+        for bad in ["", "k 0", "k -1", "mode flying", "clear now", "explode", "group walls", "width -1", "width inf"].iter() {
+            assert!(console::parse(bad).is_err(), "{:?} accepted", bad);
+        }
+
+        let mut console = console::Console::new();
+        console.type_text("`k 1x");
+        console.backspace();
+        assert_eq!(console.input, "k 1");
+        assert!(matches!(console.submit(), Some(console::Command::Nearest(Some(1)))));
+        assert!(console.input.is_empty());
+        console.type_text("k");
+        assert!(console.submit().is_none());
+        assert!(console.status.is_some());
+    }
+
+    #[test]
+    fn length_gradient_spans_blue_to_red() {
+        let colors = length_gradient(&[seg(0., 0., 30., 40.), seg(0., 0., 10., 0.), seg(5., 5., 5., 35.)]);
+        assert_eq!(colors[0], [1., 0., 0., 1.]);
+        assert_eq!(colors[1], [0., 0.25, 1., 1.]);
+        assert!(colors[2][0] > 0. && colors[2][0] < 1. && colors[2][2] > 0. && colors[2][2] < 1.);
+        assert_eq!(length_gradient(&[seg(0., 0., 1., 1.)]), vec![[0., 0.25, 1., 1.]]);
+        assert!(length_gradient(&[]).is_empty());
+    }
+
+    #[test]
+    fn promoted_ghosts_join_the_tree() {
+        let mut scene = Scene::new();
+        scene.push(seg(0., 100., 640., 100.), Default::default());
+        scene.ghosts.push(seg(0., 300., 640., 300.));
+        let probe = seg(320., 0., 320., 480.);
+        let hits = |scene: &Scene| -> HashSet<_> {
+            let tree = SceneTree::build(VolumeKind::Aabb, &scene.obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            tree.intersects(&probe, &mut PointsCutter::default()).unwrap().into_iter().map(|collision| collision.shape).collect()
+        };
+        assert_eq!(hits(&scene), [0].iter().cloned().collect());
+        assert_eq!(scene.promote_ghosts(), 1);
+        assert!(scene.ghosts.is_empty());
+        assert_eq!(scene.meta.len(), scene.obstacles.len());
+        assert_eq!(hits(&scene), [0, 1].iter().cloned().collect());
+    }
+
+    #[test]
+    fn explain_lists_every_collision() {
+        let obstacles = vec![seg(100., 100., 300., 300.), seg(100., 300., 300., 100.), seg(500., 100., 500., 120.)];
+        let probe = seg(100., 200., 300., 200.);
+        let tree = SceneTree::build(VolumeKind::Aabb, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        let collisions = tree.intersects(&probe, &mut PointsCutter::default()).unwrap();
+        let lines = explain_collisions(&collisions, &obstacles, &probe);
+        assert_eq!(lines.len(), collisions.len());
+        assert!(lines.iter().any(|line| line.starts_with("#0 ") && line.ends_with("at (200.0, 200.0)")), "{:?}", lines);
+        assert!(lines.iter().any(|line| line.starts_with("#1 ") && line.ends_with("at (200.0, 200.0)")), "{:?}", lines);
+        assert!(lines.iter().all(|line| !line.starts_with("#2 ")));
+    }
+
+    #[test]
+    fn hidden_groups_stay_out_of_the_tree() {
+        let mut scene = Scene::new();
+        scene.push(seg(0., 100., 640., 100.), ObstacleMeta { group: 0, ..Default::default() });
+        scene.push(seg(0., 200., 640., 200.), ObstacleMeta { group: 1, ..Default::default() });
+        scene.push(seg(0., 300., 640., 300.), ObstacleMeta { group: 0, ..Default::default() });
+        let probe = seg(320., 0., 320., 480.);
+        let active: HashSet<_> = [0].iter().cloned().collect();
+        let shapes = scene.active_shapes(&active);
+        assert_eq!(shapes, vec![0, 2]);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build_subset(kind, &scene.obstacles, &shapes, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let hits: HashSet<_> = tree.intersects(&probe, &mut PointsCutter::default()).unwrap().into_iter().map(|collision| collision.shape).collect();
+            assert_eq!(hits, shapes.iter().cloned().collect(), "{:?}", kind);
+            assert!(tree.nearest(&probe).all(|neighbour| neighbour.unwrap().shape != 1));
+        }
+    }
+
+    #[test]
+    fn background_color_parsing() {
+        assert_eq!(parse_color("255, 0,51"), Some([1., 0., 0.2, 1.]));
+        for bad in ["", "1,2", "1,2,3,4", "256,0,0", "-1,0,0", "red"].iter() {
+            assert_eq!(parse_color(bad), None, "{:?} accepted", bad);
+        }
+        assert_eq!(contrast_color([1., 1., 1., 1.]), [0., 0., 0., 1.]);
+        assert_eq!(contrast_color([0., 0., 0.2, 1.]), [1., 1., 1., 1.]);
+    }
+
+    #[test]
+    fn all_intersections_reports_known_crossings() {
+        let obstacles = vec![seg(100., 100., 300., 300.), seg(100., 300., 300., 100.), seg(400., 100., 600., 100.), seg(200., 50., 200., 350.)];
+        let probe = seg(50., 200., 350., 200.);
+        let intersections = all_intersections(&obstacles, &probe, CutStrategy::Mean, KDTREE_CUT_LIMIT).unwrap();
+        let shapes: HashSet<_> = intersections.iter().map(|&(shape, _, _)| shape).collect();
+        assert_eq!(shapes, [0, 1, 3].iter().cloned().collect());
+        for (_, shape_bound, needle_bound) in intersections.iter() {
+            // fragments overlap, so the probe line must pass through the shape fragment
+            assert!(shape_bound.lt.y <= 200. && shape_bound.rb.y >= 200.);
+            assert!(needle_bound.lt.x <= shape_bound.rb.x && shape_bound.lt.x <= needle_bound.rb.x);
+        }
+        assert!(all_intersections(&obstacles, &seg(0., 450., 640., 450.), CutStrategy::Mean, KDTREE_CUT_LIMIT).unwrap().is_empty());
+        assert!(all_intersections(&[], &probe, CutStrategy::Mean, KDTREE_CUT_LIMIT).unwrap().is_empty());
+    }
+
+    #[test]
+    fn split_tree_matches_full_rebuild() {
+        let mut rng = bench::seeded_rng(431);
+        let mut scene = Scene::new();
+        for segment in bench::random_scene(&mut rng, 500, 640., 480., 64.) {
+            scene.push(segment, Default::default());
+        }
+        for index in (0 .. scene.obstacles.len()).step_by(10) {
+            scene.set_dynamic(index, true);
+        }
+        let probes = bench::random_scene(&mut rng, 32, 640., 480., 128.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let mut split = SplitTree::build(kind, &scene, CutStrategy::Mean, KDTREE_CUT_LIMIT).unwrap();
+            for frame in 0 .. 3 {
+                for index in (0 .. scene.obstacles.len()).step_by(10) {
+                    let segment = &mut scene.obstacles[index];
+                    segment.src.x += 7. * frame as f64;
+                    segment.dst.y -= 5. * frame as f64;
+                }
+                split.rebuild_dynamic(&scene.obstacles).unwrap();
+                let full = SceneTree::build(kind, &scene.obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+                let mut cutter = PointsCutter::default();
+                for probe in probes.iter() {
+                    let expected: HashSet<_> = full.intersects(probe, &mut cutter).unwrap().into_iter().map(|collision| collision.shape).collect();
+                    let found: HashSet<_> = split.intersects(probe, &mut cutter).unwrap().into_iter().map(|collision| collision.shape).collect();
+                    // both trees report bounding volumes overlaps, so compare actual crossings only
+                    let crossing = |shapes: HashSet<usize>| -> HashSet<usize> {
+                        shapes.into_iter().filter(|&index| segments_cross(&scene.obstacles[index], probe)).collect()
+                    };
+                    assert_eq!(crossing(found), crossing(expected), "{:?} frame {}", kind, frame);
+                }
+            }
+        }
+        // a probe inside a rectangle misses its diagonal but still hits the rectangle
+        let mut scene = Scene::new();
+        scene.push(seg(0., 0., 100., 100.), ObstacleMeta { shape: Shape::Rect, ..Default::default() });
+        scene.push(seg(200., 0., 200., 100.), ObstacleMeta { dynamic: true, ..Default::default() });
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let split = SplitTree::build(kind, &scene, CutStrategy::Mean, 1.).unwrap();
+            let found: Vec<_> = split.intersects(&seg(10., 80., 30., 80.), &mut PointsCutter::default()).unwrap().into_iter().map(|collision| collision.shape).collect();
+            assert!(found.contains(&0), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn segment_length_midpoint_and_direction() {
+        let segment = seg(10., 20., 40., 60.);
+        assert_eq!(segment.length(), 50.);
+        assert!(points_approx_eq(&segment.midpoint(), &Point { x: 25., y: 40., }, POINT_EPSILON));
+        let direction = segment.direction().unwrap();
+        assert!(points_approx_eq(&direction, &Point { x: 0.6, y: 0.8, }, POINT_EPSILON));
+        let reversed = seg(40., 60., 10., 20.).direction().unwrap();
+        assert!(points_approx_eq(&reversed, &Point { x: -0.6, y: -0.8, }, POINT_EPSILON));
+
+        let point = seg(5., 5., 5., 5.);
+        assert_eq!(point.length(), 0.);
+        assert!(points_approx_eq(&point.midpoint(), &Point { x: 5., y: 5., }, POINT_EPSILON));
+        assert!(point.direction().is_none());
+    }
+
+    #[test]
+    fn query_stats_count_tree_callbacks() {
+        let mut rng = bench::seeded_rng(434);
+        let obstacles = bench::random_scene(&mut rng, 1_000, 640., 480., 32.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let query = |probe: &Segment| {
+                let mut stats = QueryStats::default();
+                tree.intersects_counted(probe, &mut PointsCutter::default(), &mut stats).unwrap();
+                stats
+            };
+            let everything = query(&seg(-100., -100., 740., 580.));
+            let nothing = query(&seg(5000., 5000., 5100., 5100.));
+            let corner = query(&seg(10., 10., 40., 40.));
+            // a probe beside the scene follows one branch down and never gets cut
+            assert_eq!((nothing.needle_cuts, nothing.cut_picks), (0, 0), "{:?}", kind);
+            assert!(nothing.comparisons < corner.comparisons && corner.comparisons < everything.comparisons, "{:?}", kind);
+            assert!(corner.needle_cuts < everything.needle_cuts, "{:?}", kind);
+            // counters start over with every query
+            assert_eq!(query(&seg(10., 10., 40., 40.)), corner, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn env_dirty_only_after_obstacle_push() {
+        let mut env = Env::new(Business::Construct, false, false);
+        let mut scene = Scene::new();
+        env.set_cursor(100., 100.);
+        env.toggle_obj(&mut scene);
+        assert!(!env.take_dirty());
+        env.set_cursor(200., 150.);
+        env.toggle_obj(&mut scene);
+        assert_eq!(scene.obstacles.len(), 1);
+        assert!(env.take_dirty());
+        assert!(!env.take_dirty());
+
+        env.clear_scene(&mut scene);
+        assert!(env.take_dirty());
+    }
+
+    #[test]
+    fn undo_redo_constructed_obstacles() {
+        let mut env = Env::new(Business::Construct, false, false);
+        let mut scene = Scene::new();
+        let construct = |env: &mut Env, scene: &mut Scene, x1, y1, x2, y2| {
+            env.set_cursor(x1, y1);
+            env.toggle_obj(scene);
+            env.set_cursor(x2, y2);
+            env.toggle_obj(scene);
+        };
+        construct(&mut env, &mut scene, 100., 100., 200., 100.);
+        construct(&mut env, &mut scene, 100., 200., 200., 200.);
+        env.take_dirty();
+
+        // a pending start point is cancelled first
+        env.set_cursor(300., 300.);
+        env.toggle_obj(&mut scene);
+        env.undo(&mut scene);
+        assert_eq!(scene.obstacles.len(), 2);
+        assert!(!env.take_dirty());
+
+        env.undo(&mut scene);
+        env.undo(&mut scene);
+        assert!(scene.obstacles.is_empty() && scene.meta.is_empty());
+        assert!(env.take_dirty());
+        env.undo(&mut scene);
+        assert!(!env.take_dirty());
+
+        env.redo(&mut scene);
+        assert!(env.take_dirty());
+        assert_eq!(scene.obstacles.len(), 1);
+        assert!(segment_approx_eq(&scene.obstacles[0], &seg(100., 100., 200., 100.), POINT_EPSILON));
+
+        // constructing a new obstacle drops the redo stack
+        construct(&mut env, &mut scene, 100., 300., 200., 300.);
+        env.redo(&mut scene);
+        assert_eq!(scene.obstacles.len(), 2);
+        assert!(segment_approx_eq(&scene.obstacles[1], &seg(100., 300., 200., 300.), POINT_EPSILON));
+    }
+
+    #[test]
+    fn polyline_is_committed_and_undone_as_a_whole() {
+        let mut env = Env::new(Business::Construct, false, false);
+        let mut scene = Scene::new();
+        scene.push(seg(0., 0., 10., 10.), Default::default());
+        env.toggle_polyline();
+        for &(x, y) in [(100., 100.), (200., 100.), (200., 100.), (200., 200.), (300., 200.)].iter() {
+            env.set_cursor(x, y);
+            env.toggle_obj(&mut scene);
+        }
+        // the last point is dropped before anything is committed
+        env.undo(&mut scene);
+        assert_eq!(env.polyline.as_ref().map(|points| points.len()), Some(3));
+        env.commit_polyline(&mut scene);
+        assert!(env.take_dirty());
+        assert_eq!(scene.obstacles.len(), 3);
+        assert!(segment_approx_eq(&scene.obstacles[2], &seg(200., 100., 200., 200.), POINT_EPSILON));
+        assert_eq!(scene.polyline_members(2), [1, 2]);
+        assert_eq!(scene.polyline_members(0), [0]);
+
+        env.set_cursor(400., 400.);
+        env.toggle_obj(&mut scene);
+        env.commit_polyline(&mut scene);
+        assert_eq!(scene.obstacles.len(), 3);
+
+        env.undo(&mut scene);
+        assert_eq!(scene.obstacles.len(), 1);
+        env.redo(&mut scene);
+        assert_eq!(scene.obstacles.len(), 3);
+        assert!(segment_approx_eq(&scene.obstacles[1], &seg(100., 100., 200., 100.), POINT_EPSILON));
+        assert_eq!(scene.next_polyline_id(), 1);
+    }
+
+    #[test]
+    fn random_scene_is_reproducible_by_seed() {
+        let generate = |seed, count| {
+            let mut env = Env::new(Business::Construct, false, false);
+            let mut scene = Scene::new();
+            scene.push(seg(0., 0., 10., 10.), Default::default());
+            env.random_scene = (seed, count);
+            env.randomize_scene(&mut scene);
+            assert!(env.take_dirty());
+            assert_eq!(env.random_scene, (seed + 1, count));
+            assert!(env.current_notice().is_some_and(|notice| notice.contains(&format!("--seed {}", seed))));
+            scene.obstacles
+        };
+        let obstacles = generate(511, 50);
+        assert_eq!(obstacles.len(), 50);
+        assert!(obstacles.iter().all(|s| [s.src, s.dst].iter().all(|p| (0. ..= SCREEN_WIDTH as f64).contains(&p.x) && (0. ..= SCREEN_HEIGHT as f64).contains(&p.y))));
+        assert!(obstacles.iter().zip(generate(511, 50).iter()).all(|(a, b)| segment_approx_eq(a, b, 0.)));
+        assert!(!obstacles.iter().zip(generate(512, 50).iter()).all(|(a, b)| segment_approx_eq(a, b, 0.)));
+        assert!(generate(511, 0).is_empty());
+    }
+
+    #[test]
+    fn delete_nearest_obstacle_with_its_polyline() {
+        let mut env = Env::new(Business::Construct, false, false);
+        let mut scene = Scene::new();
+        scene.push(seg(100., 100., 200., 100.), Default::default());
+        scene.push(seg(100., 300., 200., 300.), ObstacleMeta { polyline: Some(0), ..Default::default() });
+        scene.push(seg(200., 300., 200., 400.), ObstacleMeta { polyline: Some(0), ..Default::default() });
+        scene.push(seg(100., 200., 200., 200.), ObstacleMeta { weight: 2., ..Default::default() });
+        let shapes: Vec<_> = (0 .. scene.obstacles.len()).collect();
+        let tree = SceneTree::build_subset(VolumeKind::Aabb, &scene.obstacles, &shapes, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        let nearest = tree.nearest_one(Point { x: 210., y: 390., }).unwrap().unwrap();
+        assert_eq!(nearest.shape, 2);
+
+        env.delete_obstacle(&mut scene, nearest.shape);
+        assert!(env.take_dirty());
+        assert_eq!(scene.obstacles.len(), 2);
+        assert_eq!(scene.meta.len(), 2);
+        assert!(segment_approx_eq(&scene.obstacles[1], &seg(100., 200., 200., 200.), POINT_EPSILON));
+        assert_eq!(scene.meta[1].weight, 2.);
+        env.delete_obstacle(&mut scene, 0);
+        assert!(segment_approx_eq(&scene.obstacles[0], &seg(100., 200., 200., 200.), POINT_EPSILON));
+    }
+
+    #[test]
+    fn drag_obstacle_endpoint_in_edit_mode() {
+        let mut scene = Scene::new();
+        scene.push(seg(100., 100., 200., 100.), Default::default());
+        scene.push(seg(203., 100., 300., 200.), Default::default());
+        let shapes = [0, 1];
+        assert_eq!(nearest_endpoint(&scene.obstacles, &shapes, &Point { x: 199., y: 101., }, GRAB_DISTANCE), Some((0, Endpoint::Dst)));
+        assert_eq!(nearest_endpoint(&scene.obstacles, &shapes, &Point { x: 204., y: 101., }, GRAB_DISTANCE), Some((1, Endpoint::Src)));
+        assert_eq!(nearest_endpoint(&scene.obstacles, &shapes[.. 1], &Point { x: 204., y: 101., }, GRAB_DISTANCE), Some((0, Endpoint::Dst)));
+        assert_eq!(nearest_endpoint(&scene.obstacles, &shapes, &Point { x: 150., y: 100., }, GRAB_DISTANCE), None);
+
+        let mut env = Env::new(Business::Edit, false, false);
+        env.set_cursor(105., 95.);
+        env.grab_endpoint(&scene);
+        assert_eq!(env.grabbed, Some((0, Endpoint::Src)));
+        // clicks do not construct anything in edit mode
+        env.toggle_obj(&mut scene);
+        assert_eq!(scene.obstacles.len(), 2);
+        env.set_cursor(50., 60.);
+        env.drag_endpoint(&mut scene);
+        assert!(segment_approx_eq(&scene.obstacles[0], &seg(50., 60., 200., 100.), POINT_EPSILON));
+        assert!(!env.take_dirty());
+        env.set_cursor(40., 60.);
+        env.release_endpoint(&mut scene);
+        assert!(segment_approx_eq(&scene.obstacles[0], &seg(40., 60., 200., 100.), POINT_EPSILON));
+        assert!(env.grabbed.is_none() && env.take_dirty());
+
+        env.set_mode(Business::Construct);
+        env.set_cursor(200., 100.);
+        env.grab_endpoint(&scene);
+        assert!(env.grabbed.is_none());
+    }
+
+    #[test]
+    fn drag_keeps_polyline_joined_and_undoes() {
+        let mut scene = Scene::new();
+        // a lone obstacle next to the polyline corner, it is not joined to it
+        scene.push(seg(201., 100., 300., 100.), Default::default());
+        scene.push(seg(100., 100., 200., 100.), ObstacleMeta { polyline: Some(0), ..Default::default() });
+        scene.push(seg(200., 100., 200., 200.), ObstacleMeta { polyline: Some(0), ..Default::default() });
+        assert_eq!(joined_endpoints(&scene, 2, Endpoint::Src), vec![(1, Endpoint::Dst), (2, Endpoint::Src)]);
+        assert_eq!(joined_endpoints(&scene, 0, Endpoint::Src), vec![(0, Endpoint::Src)]);
+
+        let mut env = Env::new(Business::Edit, false, false);
+        env.set_cursor(199., 99.);
+        env.grab_endpoint(&scene);
+        env.set_cursor(250., 150.);
+        env.release_endpoint(&mut scene);
+        let dragged = [seg(201., 100., 300., 100.), seg(100., 100., 250., 150.), seg(250., 150., 200., 200.)];
+        let original = [seg(201., 100., 300., 100.), seg(100., 100., 200., 100.), seg(200., 100., 200., 200.)];
+        let scene_is = |scene: &Scene, expected: &[Segment]| scene.obstacles.len() == expected.len() &&
+            scene.obstacles.iter().zip(expected.iter()).all(|(a, b)| segment_approx_eq(a, b, POINT_EPSILON));
+        assert!(scene_is(&scene, &dragged));
+
+        // an obstacle constructed after the drag is taken back first
+        env.set_mode(Business::Construct);
+        for &(x, y) in [(400., 400.), (500., 400.)].iter() {
+            env.set_cursor(x, y);
+            env.toggle_obj(&mut scene);
+        }
+        env.undo(&mut scene);
+        assert!(scene_is(&scene, &dragged));
+        env.undo(&mut scene);
+        assert!(scene_is(&scene, &original));
+        env.redo(&mut scene);
+        assert!(scene_is(&scene, &dragged));
+        env.undo(&mut scene);
+        env.undo(&mut scene);
+        assert!(scene_is(&scene, &original[.. 1]));
+    }
+
+    #[test]
+    fn snap_cursor_to_grid() {
+        assert!(points_approx_eq(&snap_point(&Point { x: 47., y: -17., }, 32.), &Point { x: 32., y: -32., }, 0.));
+        assert!(points_approx_eq(&snap_point(&Point { x: 49., y: 15.9, }, 32.), &Point { x: 64., y: 0., }, 0.));
+
+        let mut env = Env::new(Business::Construct, false, false);
+        let mut scene = Scene::new();
+        env.set_cursor(101., 99.);
+        env.toggle_obj(&mut scene);
+        env.toggle_snap();
+        assert!(env.obj_start.is_some_and(|start| points_approx_eq(&start, &Point { x: 96., y: 96., }, 0.)));
+        assert!(env.cursor.is_some_and(|cursor| points_approx_eq(&cursor, &Point { x: 96., y: 96., }, 0.)));
+        env.set_cursor(203., 90.);
+        env.toggle_obj(&mut scene);
+        assert!(segment_approx_eq(&scene.obstacles[0], &seg(192., 96., 96., 96.), 0.));
+        env.toggle_snap();
+        env.set_cursor(203., 90.);
+        assert!(env.cursor.is_some_and(|cursor| points_approx_eq(&cursor, &Point { x: 203., y: 90., }, 0.)));
+    }
+
+    #[test]
+    fn cursor_readout_in_world_coordinates() {
+        let mut env = Env::new(Business::Construct, false, false);
+        assert_eq!(env.cursor_readout(), None);
+        env.camera = Point { x: 100., y: 50., };
+        env.zoom = 2.;
+        env.set_cursor(301., 251.);
+        assert_eq!(env.cursor_readout().as_deref(), Some("x: 100.5 y: 100.5"));
+        env.snap_size = 32.;
+        env.toggle_snap();
+        assert_eq!(env.cursor_readout().as_deref(), Some("x: 100.5 y: 100.5 | cell 3, 3 at 96.0, 96.0"));
+    }
+
+    #[test]
+    fn alt_constrains_segment_angle() {
+        let start = Point { x: 100., y: 100., };
+        assert!(points_approx_eq(&constrain_angle(&start, &Point { x: 200., y: 110., }), &Point { x: 200., y: 100., }, 0.));
+        assert!(points_approx_eq(&constrain_angle(&start, &Point { x: 95., y: 20., }), &Point { x: 100., y: 20., }, 0.));
+        assert!(points_approx_eq(&constrain_angle(&start, &Point { x: 160., y: 140., }), &Point { x: 150., y: 150., }, POINT_EPSILON));
+        assert!(points_approx_eq(&constrain_angle(&start, &Point { x: 40., y: 160., }), &Point { x: 40., y: 160., }, POINT_EPSILON));
+
+        let mut env = Env::new(Business::Construct, false, false);
+        let mut scene = Scene::new();
+        env.set_cursor(100., 100.);
+        env.toggle_obj(&mut scene);
+        env.set_cursor(203., 90.);
+        env.set_angle_modifier(true);
+        assert!(env.cursor.is_some_and(|cursor| points_approx_eq(&cursor, &Point { x: 203., y: 100., }, 0.)));
+        env.toggle_obj(&mut scene);
+        assert!(segment_approx_eq(&scene.obstacles[0], &seg(203., 100., 100., 100.), 0.));
+        // nothing to constrain against without a segment started
+        env.set_cursor(203., 90.);
+        assert!(env.cursor.is_some_and(|cursor| points_approx_eq(&cursor, &Point { x: 203., y: 90., }, 0.)));
+    }
+
+    #[test]
+    fn grid_lines_cover_view_at_step() {
+        let lines = grid_lines(&Point { x: -10., y: 0., }, &Point { x: 64., y: 40., }, 32.);
+        let expected = [
+            seg(0., 0., 0., 40.),
+            seg(32., 0., 32., 40.),
+            seg(64., 0., 64., 40.),
+            seg(-10., 0., 64., 0.),
+            seg(-10., 32., 64., 32.),
+        ];
+        assert_eq!(lines.len(), expected.len());
+        assert!(lines.iter().zip(expected.iter()).all(|(a, b)| segment_approx_eq(a, b, 0.)));
+    }
+
+    #[test]
+    fn frame_timer_rolling_average() {
+        let mut timer = FrameTimer::new();
+        let start = Instant::now();
+        timer.tick(start);
+        assert_eq!(timer.mean(), None);
+        timer.tick(start + Duration::from_millis(10));
+        timer.tick(start + Duration::from_millis(40));
+        assert_eq!(timer.mean(), Some(Duration::from_millis(20)));
+        // old samples fall out of the window
+        let mut now = start + Duration::from_millis(40);
+        for _ in 0 .. FRAME_TIME_SAMPLES {
+            now += Duration::from_millis(5);
+            timer.tick(now);
+        }
+        assert_eq!(timer.mean(), Some(Duration::from_millis(5)));
+    }
+
+    #[test]
+    fn empty_tree_keeps_metric() {
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::empty(kind, KDTREE_CUT_LIMIT).with_metric(Metric::Manhattan);
+            assert_eq!(tree.metric(), Metric::Manhattan, "{:?}", kind);
+            assert!(tree.intersects(&seg(0., 0., 100., 100.), &mut PointsCutter::default()).unwrap().is_empty(), "{:?}", kind);
+            assert!(tree.k_nearest(&seg(0., 0., 100., 100.), 1).unwrap().is_empty(), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn metric_picks_nearest_obstacle() {
+        // the diagonal neighbour is nearer in a straight line, the one to the right is nearer along the axes
+        let obstacles = [seg(107., 107., 107.5, 107.5), seg(111., 99.5, 111., 100.5)];
+        let probe = seg(100., 100., 100., 100.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let nearest = tree.k_nearest(&probe, 1).unwrap();
+            assert_eq!(nearest[0].shape, 0, "{:?}", kind);
+            let tree = tree.with_metric(Metric::Manhattan);
+            let nearest = tree.k_nearest(&probe, 2).unwrap();
+            assert_eq!(nearest.iter().map(|neighbour| neighbour.shape).collect::<Vec<_>>(), vec![1, 0], "{:?}", kind);
+        }
+        let at = |x, y| Bound { lt: Point { x, y, }, rb: Point { x: x + 10., y: y + 10., }, };
+        assert_eq!(Metric::Euclidean.bound_dist()(&at(0., 0.), &at(13., 14.)), 5.);
+        assert_eq!(Metric::Manhattan.bound_dist()(&at(0., 0.), &at(13., 14.)), 7.);
+        assert_eq!(Metric::Manhattan.bound_dist()(&at(0., 0.), &at(5., 20.)), 10.);
+        assert_eq!(Metric::Manhattan.bound_dist()(&at(0., 0.), &at(5., 5.)), 0.);
+        let circle = |x, y, radius| circle::BoundingCircle { center: Point { x, y, }, radius, };
+        let manhattan = Metric::Manhattan.circle_dist();
+        // along an axis the rim is reached straight away
+        assert!((manhattan(&circle(0., 0., 1.), &circle(10., 0., 1.)) - 8.).abs() < 1e-9);
+        // diagonally both coordinates shrink by the radius over the square root of two
+        assert!((manhattan(&circle(0., 0., 1.), &circle(10., 10., 1.)) - (20. - 2. * 2f64.sqrt())).abs() < 1e-9);
+        assert_eq!(manhattan(&circle(0., 0., 5.), &circle(3., 4., 1.)), 0.);
+        assert_eq!(Metric::Euclidean.circle_dist()(&circle(0., 0., 1.), &circle(10., 0., 1.)), 8.);
+    }
+
+    #[test]
+    fn obstacle_bounds_follow_shapes() {
+        let obstacles = [seg(10., 20., 0., 0.), seg(100., 100., 110., 100.), seg(50., 50., 60., 70.)];
+        let shapes = [Shape::Segment, Shape::Circle, Shape::Rect];
+        for &parallel in [false, true].iter() {
+            let bounds = obstacle_bounds(&obstacles, |i| shapes[i], parallel);
+            assert_eq!(bounds.len(), obstacles.len());
+            for (i, bound) in bounds.iter().enumerate() {
+                let expected = obstacle_bound(&obstacles[i], shapes[i]);
+                assert!(points_approx_eq(&bound.lt, &expected.lt, 0.) && points_approx_eq(&bound.rb, &expected.rb, 0.), "{}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn background_tree_build_matches_foreground_one() {
+        let mut rng = bench::seeded_rng(548);
+        let obstacles = bench::random_scene(&mut rng, 300, 640., 480., 32.);
+        let job = || TreeBuildJob {
+            kind: VolumeKind::Aabb,
+            obstacles: obstacles.clone(),
+            bounds: obstacle_bounds(&obstacles, |_| Shape::Segment, false),
+            shapes: vec![Shape::Segment; obstacles.len()],
+            active_shapes: (0 .. obstacles.len()).collect(),
+            strategy: CutStrategy::Mean,
+            metric: Metric::Euclidean,
+            cut_limit: KDTREE_CUT_LIMIT,
+        };
+        let foreground = job().run();
+        let background = job().spawn().recv().unwrap();
+        assert_eq!(background.active_shapes, foreground.active_shapes);
+        assert_eq!(background.cutter.cuts.len(), foreground.cutter.cuts.len());
+        let (background, foreground) = (tree_stats(&background.tree.unwrap().nodes()), tree_stats(&foreground.tree.unwrap().nodes()));
+        assert_eq!((background.nodes, background.depth), (foreground.nodes, foreground.depth));
+    }
+
+    #[test]
+    fn old_tree_is_dropped_once_indices_shift() {
+        let mut scene = Scene::new();
+        for segment in bench::random_scene(&mut bench::seeded_rng(548), 2_100, 640., 480., 64.) {
+            scene.push(segment, Default::default());
+        }
+        let job = |scene: &Scene| TreeBuildJob {
+            kind: VolumeKind::Aabb,
+            obstacles: scene.obstacles.clone(),
+            bounds: scene.bounds(false),
+            shapes: scene.meta.iter().map(ObstacleMeta::indexed_shape).collect(),
+            active_shapes: (0 .. scene.obstacles.len()).collect(),
+            strategy: CutStrategy::Mean,
+            metric: Metric::Euclidean,
+            cut_limit: KDTREE_CUT_LIMIT,
+        };
+        let TreeBuild { active_shapes, obstacles, bounds, .. } = job(&scene).run();
+        scene.push(seg(1., 1., 2., 2.), Default::default());
+        assert!(old_tree_usable(&obstacles, &bounds, &active_shapes, &job(&scene)));
+        scene.pop();
+        // a wider obstacle keeps its place but not its bounds
+        scene.meta[5].width = 4.;
+        assert!(!old_tree_usable(&obstacles, &bounds, &active_shapes, &job(&scene)));
+        scene.meta[5].width = 0.;
+
+        // a short probe across the middle of obstacle 1, which becomes obstacle 0
+        let (mid, normal) = (scene.obstacles[1].midpoint(), scene.obstacles[1].direction().unwrap());
+        let probe = seg(mid.x - normal.y, mid.y + normal.x, mid.x + normal.y, mid.y - normal.x);
+        scene.remove(&[0]);
+        let rebuild = job(&scene);
+        assert!(!old_tree_usable(&obstacles, &bounds, &active_shapes, &rebuild));
+        let shapes: Vec<_> = (0 .. scene.obstacles.len()).collect();
+        let tree = rebuild.run().tree.unwrap();
+        let found: HashSet<_> = scene_collisions(&tree, &scene, &probe, &mut PointsCutter::default(), &mut QueryStats::default()).unwrap().into_iter()
+            .map(|collision| collision.shape)
+            .filter(|&shape| segments_cross(&scene.obstacles[shape], &probe))
+            .collect();
+        assert!(found.contains(&0));
+        assert_eq!(found, exact_collisions(&scene, &shapes, &probe).into_iter().collect());
+    }
+
+    #[test]
+    fn overflow_tree_serves_appended_obstacles() {
+        let mut rng = bench::seeded_rng(553);
+        let obstacles = bench::random_scene(&mut rng, 300, 640., 480., 32.);
+        let built: Vec<_> = (0 .. 290).collect();
+        let all: Vec<_> = (0 .. 300).collect();
+        let appended = appended_shapes(&obstacles[.. 290], &built, &obstacles, &all).unwrap();
+        assert_eq!(appended, (290 .. 300).collect::<Vec<_>>());
+        let job = TreeBuildJob {
+            kind: VolumeKind::Aabb,
+            obstacles: obstacles.clone(),
+            bounds: obstacle_bounds(&obstacles, |_| Shape::Segment, false),
+            shapes: vec![Shape::Segment; obstacles.len()],
+            active_shapes: built,
+            strategy: CutStrategy::Mean,
+            metric: Metric::Euclidean,
+            cut_limit: KDTREE_CUT_LIMIT,
+        };
+        let main_nodes = job.run_overflow(&job.active_shapes).unwrap().nodes().len();
+        let overflow_nodes = job.run_overflow(&appended).unwrap().nodes().len();
+        let tree = job.run_overflow(&job.active_shapes).unwrap()
+            .with_overflow(job.run_overflow(&appended[.. 5]).unwrap())
+            .with_overflow(job.run_overflow(&appended).unwrap());
+        // an attached overflow tree is replaced rather than stacked
+        assert_eq!(tree.nodes().len(), main_nodes + overflow_nodes);
+
+        let needle = seg(320., 240., 330., 250.);
+        let dists: Vec<_> = tree.nearest(&needle).map(|neighbour| neighbour.unwrap()).collect();
+        assert!(dists.windows(2).all(|pair| pair[0].dist <= pair[1].dist));
+        let mut cutter = PointsCutter::default();
+        for &i in &appended {
+            assert!(tree.collided_by(&obstacles[i .. i + 1], &mut cutter).unwrap().contains(&i));
+            assert_eq!(tree.nearest_one(obstacles[i].src).unwrap().unwrap().dist, 0.);
+        }
+
+        // moved or re-enabled older obstacles need a full build
+        let mut moved = obstacles.clone();
+        moved[7] = seg(0., 0., 1., 1.);
+        assert!(appended_shapes(&obstacles[.. 290], &all[.. 290], &moved, &all).is_none());
+        let reenabled: Vec<_> = (1 .. 291).chain(0 .. 1).collect();
+        assert!(appended_shapes(&obstacles[.. 291], &all[1 .. 291], &obstacles, &reenabled).is_none());
+    }
+
+    #[test]
+    fn tree_stats_count_nodes_and_levels() {
+        let mut rng = bench::seeded_rng(517);
+        let obstacles = bench::random_scene(&mut rng, 200, 640., 480., 32.);
+        let nodes = SceneTree::build(VolumeKind::Aabb, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap().nodes();
+        let stats = tree_stats(&nodes);
+        assert_eq!(stats.nodes, nodes.len());
+        assert!(stats.nodes > 1);
+        // a binary tree cannot be shallower than log2 of its nodes count
+        assert!(stats.depth > 1 && (1 << stats.depth) > stats.nodes);
+        let empty = tree_stats(&[]);
+        assert_eq!((empty.nodes, empty.depth), (0, 0));
+        let root = tree_stats(&[(0, None)]);
+        assert_eq!((root.nodes, root.depth), (1, 1));
+    }
+
+    #[test]
+    fn leaf_region_contains_point() {
+        let bound = |l, t, r, b| Some(Bound { lt: Point { x: l, y: t, }, rb: Point { x: r, y: b, }, });
+        let nodes = [
+            (0, bound(0., 0., 100., 100.)),
+            (1, bound(0., 0., 40., 100.)),
+            (2, bound(0., 0., 40., 40.)),
+            (2, None),
+            (1, bound(50., 0., 100., 100.)),
+        ];
+        let at = |x, y| leaf_region(&nodes, &Point { x, y, }).map(|b| (b.lt.x, b.lt.y, b.rb.x, b.rb.y));
+        assert_eq!(at(10., 10.), Some((0., 0., 40., 40.)));
+        assert_eq!(at(60., 90.), Some((50., 0., 100., 100.)));
+        // inner nodes are not leaves
+        assert_eq!(at(10., 60.), None);
+        assert_eq!(at(45., 50.), None);
+        assert_eq!(leaf_region(&[], &Point { x: 0., y: 0., }).map(|b| b.lt.x), None);
+
+        let mut rng = bench::seeded_rng(518);
+        let obstacles = bench::random_scene(&mut rng, 100, 640., 480., 32.);
+        let nodes = SceneTree::build(VolumeKind::Aabb, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap().nodes();
+        let point = obstacles[0].midpoint();
+        assert!(leaf_region(&nodes, &point).is_some());
+    }
+
+    #[test]
+    fn zoom_keeps_point_under_mouse() {
+        let mut env = Env::new(Business::Construct, false, false);
+        env.set_cursor(300., 200.);
+        let before = env.cursor.unwrap();
+        env.zoom_at_mouse(3.);
+        assert!((env.zoom - ZOOM_STEP.powi(3)).abs() < 1e-9);
+        assert!(env.cursor.is_some_and(|cursor| points_approx_eq(&cursor, &before, 1e-9)));
+        // moving the mouse covers less of the world when zoomed in
+        env.set_cursor(300. + env.zoom * 10., 200.);
+        assert!(env.cursor.is_some_and(|cursor| points_approx_eq(&cursor, &Point { x: before.x + 10., y: before.y, }, 1e-9)));
+        env.zoom_at_mouse(-1000.);
+        assert_eq!(env.zoom, MIN_ZOOM);
+
+        // panning moves the world under the cursor, home brings the view back
+        env.start_pan();
+        env.set_cursor(340., 250.);
+        env.stop_pan();
+        env.set_cursor(340., 250.);
+        assert!(env.cursor.is_some_and(|cursor| !points_approx_eq(&cursor, &Point { x: 340., y: 250., }, 1e-9)));
+        env.reset_view();
+        assert_eq!(env.zoom, 1.);
+        assert!(env.cursor.is_some_and(|cursor| points_approx_eq(&cursor, &Point { x: 340., y: 250., }, 1e-9)));
+        env.zoom_at_mouse(-1000.);
+
+        let mut scene = Scene::new();
+        env.set_cursor(100., 100.);
+        env.toggle_obj(&mut scene);
+        env.set_cursor(116., 100.);
+        env.toggle_obj(&mut scene);
+        assert!((scene.obstacles[0].length() - 16. / MIN_ZOOM).abs() < 1e-9);
+    }
+
+    #[test]
+    fn screen_size_is_clamped_and_used_for_random_scenes() {
+        assert_eq!(screen_side_clamped("width", 1024), 1024);
+        assert_eq!(screen_side_clamped("width", 0), MIN_SCREEN_SIDE);
+        assert_eq!(screen_side_clamped("height", 100_000), MAX_SCREEN_SIDE);
+
+        let mut env = Env::new(Business::Construct, false, false);
+        let mut scene = Scene::new();
+        env.reset(1600, 300);
+        env.random_scene = (521, 500);
+        env.randomize_scene(&mut scene);
+        let points: Vec<_> = scene.obstacles.iter().flat_map(|s| vec![s.src, s.dst]).collect();
+        assert!(points.iter().all(|p| p.x <= 1600. && p.y <= 300.));
+        assert!(points.iter().any(|p| p.x > SCREEN_WIDTH as f64));
+    }
+
+    #[test]
+    fn smaller_cut_limit_splits_more() {
+        let diagonal = seg(0., 0., 512., 512.);
+        let fragment = get_bounding_volume(&seg(0., 0., 20., 20.));
+        assert!(matches!(cut_segment_fragment(&diagonal, &fragment, &Axis::X, &Point { x: 10., y: 0., }, KDTREE_CUT_LIMIT), Ok(None)));
+        assert!(matches!(cut_segment_fragment(&diagonal, &fragment, &Axis::X, &Point { x: 10., y: 0., }, 8.), Ok(Some(..))));
+
+        let fragments = |kind, cut_limit| {
+            let tree = SceneTree::build_shapes(kind, &[diagonal], |_| Shape::Segment, &[0], &mut PointsCutter::default(), cut_limit, false).unwrap();
+            let probe = seg(0., 256., 512., 256.);
+            (tree_stats(&tree.nodes()).nodes, tree.intersects(&probe, &mut PointsCutter::default()).unwrap().len())
+        };
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let (coarse_nodes, coarse_hits) = fragments(kind, KDTREE_CUT_LIMIT);
+            let (fine_nodes, fine_hits) = fragments(kind, 4.);
+            assert!(fine_nodes > coarse_nodes, "{:?}: {} <= {}", kind, fine_nodes, coarse_nodes);
+            assert!(fine_hits >= 1 && coarse_hits >= 1, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn k_nearest_exact_keeps_best_k() {
+        let mut rng = bench::seeded_rng(523);
+        let mut scene = Scene::new();
+        for (i, segment) in bench::random_scene(&mut rng, 300, 640., 480., 32.).into_iter().enumerate() {
+            scene.push(segment, ObstacleMeta { weight: [1., 0.5, 4., 16.][i % 4], ..Default::default() });
+        }
+        let probe = seg(300., 200., 340., 260.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            for &metric in [Metric::Euclidean, Metric::Manhattan].iter() {
+                let tree = SceneTree::build(kind, &scene.obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap().with_metric(metric);
+                let everything = refine_neighbours(tree.k_nearest(&probe, scene.obstacles.len()).unwrap(), &probe, &scene);
+                let mut seen = HashSet::new();
+                let ranked: Vec<_> = everything.iter().map(|neighbour| neighbour.shape).filter(|&shape| seen.insert(shape)).collect();
+                for &k in [1, 5, 12].iter() {
+                    let best = k_nearest_exact(tree.nearest(&probe), &probe, &scene, metric, k).unwrap();
+                    assert_eq!(best.iter().map(|neighbour| neighbour.shape).collect::<Vec<_>>(), &ranked[.. k], "{:?} {:?} k={}", kind, metric, k);
+                    assert!(best.windows(2).all(|pair| pair[0].dist <= pair[1].dist));
+                }
+                assert!(k_nearest_exact(tree.nearest(&probe), &probe, &scene, metric, 0).unwrap().is_empty());
+            }
+        }
+
+        let mut env = Env::new(Business::Neighbours, false, false);
+        env.step_neighbours_limit(true);
+        env.step_neighbours_limit(true);
+        assert_eq!(env.neighbours_limit, Some(2));
+        env.step_neighbours_limit(false);
+        env.step_neighbours_limit(false);
+        assert_eq!(env.neighbours_limit, None);
+    }
+
+    #[test]
+    fn range_query_finds_obstacles_in_rectangle() {
+        let mut rng = bench::seeded_rng(524);
+        let mut scene = Scene::new();
+        for segment in bench::random_scene(&mut rng, 400, 640., 480., 32.) {
+            scene.push(segment, Default::default());
+        }
+        let areas = [
+            Bound { lt: Point { x: 100., y: 100., }, rb: Point { x: 260., y: 180., }, },
+            Bound { lt: Point { x: 0., y: 0., }, rb: Point { x: 640., y: 480., }, },
+            Bound { lt: Point { x: 700., y: 700., }, rb: Point { x: 800., y: 800., }, },
+        ];
+        let touches = |segment: &Segment, area: &Bound| {
+            let inside = |p: &Point| p.x >= area.lt.x && p.x <= area.rb.x && p.y >= area.lt.y && p.y <= area.rb.y;
+            let (lb, rt) = (Point { x: area.lt.x, y: area.rb.y, }, Point { x: area.rb.x, y: area.lt.y, });
+            inside(&segment.src) || inside(&segment.dst) ||
+                [seg(area.lt.x, area.lt.y, rt.x, rt.y), seg(rt.x, rt.y, area.rb.x, area.rb.y), seg(area.rb.x, area.rb.y, lb.x, lb.y), seg(lb.x, lb.y, area.lt.x, area.lt.y)]
+                    .iter().any(|side| segments_cross(segment, side))
+        };
+        let bounds = obstacle_bounds(&scene.obstacles, |i| scene.meta[i].shape, false);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &scene.obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            for area in areas.iter() {
+                let found: HashSet<_> = obstacles_in_range(&tree, &bounds, area, &mut PointsCutter::default()).unwrap().into_iter().collect();
+                for (i, segment) in scene.obstacles.iter().enumerate() {
+                    let overlaps = bound_to_bound_dist(&get_bounding_volume(segment), area) == 0.;
+                    assert!(!found.contains(&i) || overlaps, "{:?} reported {} outside of the area", kind, i);
+                    assert!(found.contains(&i) || !touches(segment, area), "{:?} missed {} crossing the area", kind, i);
+                }
+            }
+            assert_eq!(obstacles_in_range(&tree, &bounds, &areas[1], &mut PointsCutter::default()).unwrap().len(), scene.obstacles.len());
+            assert!(obstacles_in_range(&tree, &bounds, &areas[2], &mut PointsCutter::default()).unwrap().is_empty());
+        }
+        assert!(matches!("range".parse(), Ok(Business::Range)));
+    }
+
+    #[test]
+    fn ray_reports_first_hit_only() {
+        let mut scene = Scene::new();
+        scene.push(seg(300., 0., 300., 100.), Default::default());
+        scene.push(seg(200., 0., 200., 100.), Default::default());
+        scene.push(seg(100., 40., 120., 60.), ObstacleMeta { shape: Shape::Rect, ..Default::default() });
+        scene.push(seg(400., 50., 400., 60.), ObstacleMeta { shape: Shape::Circle, ..Default::default() });
+        scene.push(seg(0., 200., 500., 200.), Default::default());
+        let bound = Bound { lt: Point { x: 0., y: 0., }, rb: Point { x: 500., y: 200., }, };
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build_shapes(kind, &scene.obstacles, |i| scene.meta[i].shape, &[0, 1, 2, 3, 4], &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let hit = |origin: Point, through: Point| {
+                let ray = cast_ray(&origin, &through, &bound).unwrap();
+                first_hit(&tree, &scene, &ray, &mut PointsCutter::default()).unwrap()
+            };
+            // the ray goes past the cursor up to the rectangle
+            let (shape, dist) = hit(Point { x: 0., y: 50., }, Point { x: 10., y: 50., }).unwrap();
+            assert_eq!(shape, 2);
+            assert!((dist - 100.).abs() < 1e-9, "{:?} {}", kind, dist);
+            let (shape, dist) = hit(Point { x: 150., y: 50., }, Point { x: 160., y: 50., }).unwrap();
+            assert_eq!((shape, dist), (1, 50.));
+            let (shape, dist) = hit(Point { x: 350., y: 50., }, Point { x: 360., y: 50., }).unwrap();
+            assert_eq!(shape, 3);
+            assert!((dist - 40.).abs() < 1e-9, "{:?} {}", kind, dist);
+            assert_eq!(hit(Point { x: 250., y: 50., }, Point { x: 250., y: 40., }), None);
+        }
+        assert!(cast_ray(&Point { x: 1., y: 1., }, &Point { x: 1., y: 1., }, &bound).is_none());
+        // a lone point obstacle under the cursor leaves nothing to cast through
+        let point = Bound { lt: Point { x: 32., y: 32., }, rb: Point { x: 32., y: 32., }, };
+        assert!(cast_ray(&Point { x: 32., y: 32., }, &Point { x: 40., y: 32., }, &point).is_none());
+        assert_eq!(ray_hit_dist(&seg(110., 50., 200., 50.), &seg(100., 40., 120., 60.), Shape::Rect), Some(0.));
+    }
+
+    #[test]
+    fn median_cut_point_ignores_outliers() {
+        let points = || [(0., 5.), (1., 6.), (2., 7.), (3., 8.), (100., 500.)].iter().map(|&(x, y)| Point { x, y, });
+        let mut mean = PointsCutter::default();
+        let cut = kdvtree::GetCutPoint::cut_point(&mut &mut mean, &Axis::X, points()).unwrap();
+        assert_eq!((cut.x, cut.y), (21.2, 105.2));
+        let mut median = PointsCutter::with_strategy(CutStrategy::Median);
+        let cut = kdvtree::GetCutPoint::cut_point(&mut &mut median, &Axis::X, points()).unwrap();
+        assert_eq!((cut.x, cut.y), (2., 7.));
+        let cut = kdvtree::GetCutPoint::cut_point(&mut &mut median, &Axis::X, points().take(4)).unwrap();
+        assert_eq!((cut.x, cut.y), (1.5, 6.5));
+        assert!(kdvtree::GetCutPoint::cut_point(&mut &mut median, &Axis::X, points().take(0)).is_none());
+
+        let mut visual_cutter = VisualCutter::with_strategy(CutStrategy::Median);
+        kdvtree::GetCutPoint::cut_point(&mut &mut visual_cutter, &Axis::X, points()).unwrap();
+        let (cut_seg, _) = &visual_cutter.cuts[0];
+        assert_eq!((cut_seg.src.x, cut_seg.src.y, cut_seg.dst.y), (2., 5., 500.));
+    }
+
+    #[test]
+    fn sah_cut_separates_cluster_from_outlier() {
+        let mut points = Vec::new();
+        for i in 0 .. 10 {
+            points.push(Point { x: i as f64, y: 0., });
+            points.push(Point { x: i as f64 + 1., y: 10., });
+        }
+        points.extend([Point { x: 90., y: 0., }, Point { x: 100., y: 10., }].iter().cloned());
+        let mut sah = PointsCutter::with_strategy(CutStrategy::Sah);
+        let cut = kdvtree::GetCutPoint::cut_point(&mut &mut sah, &Axis::X, points.iter().cloned()).unwrap();
+        assert_eq!((cut.x, cut.y), (100. / 9., 5.));
+        let cut = kdvtree::GetCutPoint::cut_point(&mut &mut sah, &Axis::Y, points.iter().cloned()).unwrap();
+        assert_eq!(cut.x, 50.);
+        assert!(cut.y > 0. && cut.y < 10.);
+
+        let mut rng = bench::seeded_rng(527);
+        let obstacles = bench::random_scene(&mut rng, 1_000, 640., 480., 32.);
+        let probes = bench::random_scene(&mut rng, 32, 640., 480., 64.);
+        let mean = SceneTree::build(VolumeKind::Aabb, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        let sah = SceneTree::build(VolumeKind::Aabb, &obstacles, &mut PointsCutter::with_strategy(CutStrategy::Sah), KDTREE_CUT_LIMIT, false).unwrap();
+        for probe in probes.iter() {
+            let crossed = brute_force_collisions(&obstacles, probe);
+            assert!(mean.collided_by(&[*probe], &mut PointsCutter::default()).unwrap().is_superset(&crossed));
+            assert!(sah.collided_by(&[*probe], &mut PointsCutter::default()).unwrap().is_superset(&crossed));
+        }
+    }
+
+    #[test]
+    fn axis_parallel_segments_cut_without_nan() {
+        let no_nan = |bound: &Bound| [bound.lt.x, bound.lt.y, bound.rb.x, bound.rb.y].iter().all(|v| !v.is_nan());
+        let vertical = seg(10., 0., 10., 100.);
+        let horizontal = seg(0., 10., 100., 10.);
+        for &cut_limit in [0., KDTREE_CUT_LIMIT].iter() {
+            let cut = |shape: &Segment, axis, x, y| cut_segment_fragment(shape, &get_bounding_volume(shape), &axis, &Point { x, y, }, cut_limit);
+            assert!(matches!(cut(&vertical, Axis::X, 10., 50.), Ok(None)));
+            assert!(matches!(cut(&horizontal, Axis::Y, 50., 10.), Ok(None)));
+            match (cut(&vertical, Axis::Y, 10., 40.), cut(&horizontal, Axis::X, 40., 10.)) {
+                (Ok(Some((upper, lower))), Ok(Some((left, right)))) => {
+                    assert!([&upper, &lower, &left, &right].iter().all(|bound| no_nan(bound)));
+                    assert_eq!((upper.lt.x, upper.rb.x, upper.rb.y, lower.lt.y), (10., 10., 40., 40.));
+                    assert_eq!((left.lt.y, left.rb.y, left.rb.x, right.lt.x), (10., 10., 40., 40.));
+                },
+                _ =>
+                    panic!("crossing cuts must split at cut limit {}", cut_limit),
+            }
+        }
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let obstacles = [vertical, horizontal, seg(50., 0., 50., 100.), seg(0., 50., 100., 50.)];
+            let tree = SceneTree::build_shapes(kind, &obstacles, |_| Shape::Segment, &[0, 1, 2, 3], &mut PointsCutter::default(), 1., false).unwrap();
+            assert!(tree.nodes().iter().filter_map(|(_, bound)| bound.as_ref()).all(no_nan));
+        }
+    }
+
+    #[test]
+    fn cut_point_dist_is_zero_inside_bound() {
+        let bound = Bound { lt: Point { x: 10., y: 20., }, rb: Point { x: 30., y: 60., }, };
+        let at = |x, y| Point { x, y, };
+        assert_eq!(bound_to_cut_point_dist(&Axis::X, &bound, &at(15., 0.)), 0.);
+        assert_eq!(bound_to_cut_point_dist(&Axis::X, &bound, &at(4., 0.)), 6.);
+        assert_eq!(bound_to_cut_point_dist(&Axis::X, &bound, &at(35., 0.)), 5.);
+        assert_eq!(bound_to_cut_point_dist(&Axis::Y, &bound, &at(0., 25.)), 0.);
+        assert_eq!(bound_to_cut_point_dist(&Axis::Y, &bound, &at(0., 12.)), 8.);
+        assert_eq!(bound_to_cut_point_dist(&Axis::Y, &bound, &at(0., 70.)), 10.);
+        let circle = circle::get_bounding_circle(&seg(0., 0., 20., 0.));
+        assert_eq!(circle::circle_to_cut_point_dist(&Axis::X, &circle, &at(12., 0.)), 0.);
+        assert_eq!(circle::circle_to_cut_point_dist(&Axis::X, &circle, &at(-5., 0.)), 5.);
+        assert_eq!(circle::circle_to_cut_point_dist(&Axis::Y, &circle, &at(0., 13.)), 3.);
+    }
+
+    #[test]
+    fn collided_fragments_are_listed_once() {
+        let obstacles: Vec<_> = (0 .. 20).map(|i| seg(10. + i as f64 * 5., 0., 10. + i as f64 * 5., 100.)).collect();
+        let probe = seg(0., 50., 200., 50.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let collisions = tree.intersects(&probe, &mut PointsCutter::default()).unwrap();
+            let (shape_fragments, needle_fragments) = unique_fragments(&collisions);
+            let distinct = |fragments: &[&super::Volume]| fragments.iter().map(|fragment| fragment.key()).collect::<HashSet<_>>().len();
+            assert_eq!(distinct(&shape_fragments), shape_fragments.len());
+            assert_eq!(distinct(&needle_fragments), needle_fragments.len());
+            // the probe is cut into fewer fragments than the obstacles it crosses
+            assert!(needle_fragments.len() < collisions.len(), "{:?}", kind);
+            let all_needles: Vec<_> = collisions.iter().map(|collision| &collision.needle_fragment).collect();
+            assert_eq!(distinct(&all_needles), needle_fragments.len());
+        }
+    }
+
+    #[test]
+    fn geometry_characterization() {
+        use super::geometry::{cmp_points, get_bounding_volume, cut_segment_fragment, cut_rect_fragment, bound_to_bound_dist, bound_to_cut_point_dist};
+        use std::cmp::Ordering;
+        let corners = |bound: &Bound| (bound.lt.x, bound.lt.y, bound.rb.x, bound.rb.y);
+        let (a, b) = (Point { x: 1., y: 5., }, Point { x: 3., y: 2., });
+        assert_eq!((cmp_points(&Axis::X, &a, &b), cmp_points(&Axis::Y, &a, &b), cmp_points(&Axis::X, &a, &a)), (Ordering::Less, Ordering::Greater, Ordering::Equal));
+        let shape = seg(80., 10., 0., 50.);
+        let bound = get_bounding_volume(&shape);
+        assert_eq!(corners(&bound), (0., 10., 80., 50.));
+        match cut_segment_fragment(&shape, &bound, &Axis::X, &Point { x: 20., y: 0., }, 1.) {
+            Ok(Some((left, right))) =>
+                assert_eq!((corners(&left), corners(&right)), ((0., 40., 20., 50.), (20., 10., 80., 40.))),
+            _ =>
+                panic!("segment must be split at x = 20"),
+        }
+        assert!(matches!(cut_segment_fragment(&shape, &bound, &Axis::X, &Point { x: 20., y: 0., }, 100.), Ok(None)));
+        match cut_rect_fragment(&bound, &Axis::Y, &Point { x: 0., y: 20., }, 1.) {
+            Ok(Some((upper, lower))) =>
+                assert_eq!((corners(&upper), corners(&lower)), ((0., 10., 80., 20.), (0., 20., 80., 50.))),
+            _ =>
+                panic!("rectangle must be split at y = 20"),
+        }
+        let far = Bound { lt: Point { x: 83., y: 54., }, rb: Point { x: 90., y: 60., }, };
+        assert_eq!(bound_to_bound_dist(&bound, &far), 5.);
+        assert_eq!(bound_to_cut_point_dist(&Axis::X, &bound, &Point { x: 90., y: 0., }), 10.);
+    }
+
+    #[test]
+    fn cut_segment_fragment_branches() {
+        let within = |inner: &Bound, outer: &Bound|
+            inner.lt.x >= outer.lt.x && inner.lt.y >= outer.lt.y && inner.rb.x <= outer.rb.x && inner.rb.y <= outer.rb.y;
+        // every direction of a diagonal along with a fragment taken out of its middle
+        let shapes = [seg(0., 0., 200., 100.), seg(200., 100., 0., 0.), seg(0., 100., 200., 0.), seg(200., 0., 0., 100.)];
+        for shape in shapes.iter() {
+            let whole = get_bounding_volume(shape);
+            let middle = match cut_segment_fragment(shape, &whole, &Axis::X, &Point { x: 50., y: 0., }, KDTREE_CUT_LIMIT) {
+                Ok(Some((_, right))) => match cut_segment_fragment(shape, &right, &Axis::X, &Point { x: 150., y: 0., }, KDTREE_CUT_LIMIT) {
+                    Ok(Some((middle, _))) => middle,
+                    _ => panic!("{:?} must be split at x = 150", shape),
+                },
+                _ => panic!("{:?} must be split at x = 50", shape),
+            };
+            for fragment in [whole.clone(), middle].iter() {
+                let center = Segment { src: fragment.lt, dst: fragment.rb, }.midpoint();
+                for axis in [Axis::X, Axis::Y].iter() {
+                    let (a, b) = match cut_segment_fragment(shape, fragment, axis, &center, KDTREE_CUT_LIMIT) {
+                        Ok(Some(halves)) => halves,
+                        _ => panic!("{:?} fragment {:?} must be split along {:?}", shape, fragment, axis),
+                    };
+                    assert!(within(&a, fragment) && within(&b, fragment));
+                    let merged = merge_bounds(&a, &b);
+                    assert!(points_approx_eq(&merged.lt, &fragment.lt, POINT_EPSILON) && points_approx_eq(&merged.rb, &fragment.rb, POINT_EPSILON));
+                    match *axis {
+                        Axis::X => assert!(a.rb.x == center.x && b.lt.x == center.x && a.lt.x == fragment.lt.x && b.rb.x == fragment.rb.x),
+                        Axis::Y => assert!(a.rb.y == center.y && b.lt.y == center.y && a.lt.y == fragment.lt.y && b.rb.y == fragment.rb.y),
+                    }
+                }
+                let outside = Point { x: fragment.rb.x + 1., y: fragment.rb.y + 1., };
+                assert!(matches!(cut_segment_fragment(shape, fragment, &Axis::X, &outside, KDTREE_CUT_LIMIT), Ok(None)));
+                assert!(matches!(cut_segment_fragment(shape, fragment, &Axis::Y, &outside, KDTREE_CUT_LIMIT), Ok(None)));
+            }
+        }
+        // narrower than the cut limit along the cut axis only
+        let narrow = seg(0., 0., KDTREE_CUT_LIMIT / 2., 100.);
+        let bound = get_bounding_volume(&narrow);
+        assert!(matches!(cut_segment_fragment(&narrow, &bound, &Axis::X, &Point { x: KDTREE_CUT_LIMIT / 4., y: 50., }, KDTREE_CUT_LIMIT), Ok(None)));
+        assert!(matches!(cut_segment_fragment(&narrow, &bound, &Axis::Y, &Point { x: KDTREE_CUT_LIMIT / 4., y: 50., }, KDTREE_CUT_LIMIT), Ok(Some(..))));
+    }
+
+    #[test]
+    fn closest_points_connect_nearest_ends() {
+        let contact = closest_points(&seg(0., 0., 10., 0.), &seg(5., 3., 5., 10.));
+        assert!(points_approx_eq(&contact.src, &Point { x: 5., y: 0., }, POINT_EPSILON));
+        assert!(points_approx_eq(&contact.dst, &Point { x: 5., y: 3., }, POINT_EPSILON));
+        let contact = closest_points(&seg(20., 5., 20., 5.), &seg(0., 0., 10., 0.));
+        assert!((contact.length() - 125_f64.sqrt()).abs() < POINT_EPSILON);
+        let crossing = closest_points(&seg(0., 0., 10., 10.), &seg(0., 10., 10., 0.));
+        assert!(crossing.length() == 0. && points_approx_eq(&crossing.src, &Point { x: 5., y: 5., }, POINT_EPSILON));
+    }
+
+    #[test]
+    fn exact_distances_rank_nearest_obstacles() {
+        assert_eq!(segment_segment_distance(&seg(0., 0., 10., 0.), &seg(5., 3., 5., 10.)), 3.);
+        assert_eq!(segment_segment_distance(&seg(0., 0., 10., 10.), &seg(0., 10., 10., 0.)), 0.);
+        assert!((segment_segment_distance(&seg(0., 0., 10., 0.), &seg(13., 4., 20., 4.)) - 5.).abs() < POINT_EPSILON);
+        let probe = seg(0., 0., 0., 0.);
+        assert_eq!(obstacle_distance(&probe, &seg(-5., -5., 5., 5.), Shape::Rect), 0.);
+        assert_eq!(obstacle_distance(&probe, &seg(3., -5., 8., 5.), Shape::Rect), 3.);
+        assert!((obstacle_distance(&probe, &seg(10., 0., 13., 4.), Shape::Circle) - 5.).abs() < POINT_EPSILON);
+        assert_eq!(obstacle_distance(&probe, &seg(1., 0., 6., 0.), Shape::Circle), 0.);
+
+        // the box around the diagonal starts nearer to the probe than the vertical segment, the diagonal itself does not
+        let mut scene = Scene::new();
+        scene.push(seg(1., 11., 11., 1.), Default::default());
+        scene.push(seg(4., -10., 4., 10.), Default::default());
+        let probe = seg(0., 0., 0., 0.);
+        let tree = SceneTree::build(VolumeKind::Aabb, &scene.obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        assert_eq!(tree.nearest(&probe).next().unwrap().unwrap().shape, 0);
+        let refined = k_nearest_exact(tree.nearest(&probe), &probe, &scene, Metric::Euclidean, 2).unwrap();
+        assert_eq!(refined.iter().map(|neighbour| neighbour.shape).collect::<Vec<_>>(), vec![1, 0]);
+        assert!((refined[0].dist - 4.).abs() < POINT_EPSILON);
+        assert!((refined[1].dist - 6. * 2f64.sqrt()).abs() < POINT_EPSILON);
+        // the diagonal box comes first, yet the single nearest obstacle is the vertical one
+        for &metric in [Metric::Euclidean, Metric::Manhattan].iter() {
+            let tree = SceneTree::build(VolumeKind::Aabb, &scene.obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap().with_metric(metric);
+            let nearest = k_nearest_exact(tree.nearest(&probe), &probe, &scene, metric, 1).unwrap();
+            assert_eq!(nearest.iter().map(|neighbour| (neighbour.shape, neighbour.dist)).collect::<Vec<_>>(), vec![(1, 4.)], "{:?}", metric);
+        }
+        let all = refine_neighbours(tree.nearest(&probe).map(Result::unwrap).collect(), &probe, &scene);
+        assert_eq!(all.iter().map(|neighbour| neighbour.shape).collect::<Vec<_>>(), vec![1, 0]);
+    }
+
+    #[test]
+    fn segment_intersection_special_cases() {
+        let at = |a: Segment, b: Segment| segment_intersection(&a, &b).map(|p| (p.x, p.y));
+        assert_eq!(at(seg(0., 0., 10., 10.), seg(0., 10., 10., 0.)), Some((5., 5.)));
+        // parallel apart and collinear apart
+        assert_eq!(at(seg(0., 0., 10., 0.), seg(0., 1., 10., 1.)), None);
+        assert_eq!(at(seg(0., 0., 10., 0.), seg(11., 0., 20., 0.)), None);
+        // collinear overlap is met at the point closest to the first segment start
+        assert_eq!(at(seg(0., 0., 10., 0.), seg(5., 0., 20., 0.)), Some((5., 0.)));
+        assert_eq!(at(seg(10., 0., 0., 0.), seg(5., 0., 20., 0.)), Some((10., 0.)));
+        assert_eq!(at(seg(2., 2., 4., 4.), seg(0., 0., 10., 10.)), Some((2., 2.)));
+        // touching ends and points
+        assert_eq!(at(seg(0., 0., 10., 0.), seg(10., 0., 20., 0.)), Some((10., 0.)));
+        assert_eq!(at(seg(5., 0., 5., 0.), seg(0., 0., 10., 0.)), Some((5., 0.)));
+        assert_eq!(at(seg(5., 1., 5., 1.), seg(0., 0., 10., 0.)), None);
+        assert_eq!(at(seg(3., 3., 3., 3.), seg(3., 3., 3., 3.)), Some((3., 3.)));
+    }
+
+    #[test]
+    fn contact_points_of_every_shape() {
+        let needle = seg(0., 50., 200., 50.);
+        let mut rect: Vec<_> = contact_points(&needle, &seg(50., 0., 100., 100.), Shape::Rect).iter().map(|p| (p.x, p.y)).collect();
+        rect.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(rect, vec![(50., 50.), (100., 50.)]);
+        let circle = contact_points(&needle, &seg(100., 50., 100., 80.), Shape::Circle);
+        assert_eq!(circle.iter().map(|p| (p.x, p.y)).collect::<Vec<_>>(), vec![(70., 50.), (130., 50.)]);
+        // a probe starting inside meets the rim once
+        assert_eq!(contact_points(&seg(100., 50., 200., 50.), &seg(100., 50., 100., 80.), Shape::Circle).len(), 1);
+        assert!(contact_points(&needle, &seg(0., 0., 200., 0.), Shape::Segment).is_empty());
+    }
+
+    #[test]
+    fn failed_fragment_cuts_are_reported() {
+        let broken = seg(f64::NAN, 10., 20., 30.);
+        assert!(cut_segment_fragment(&broken, &get_bounding_volume(&seg(0., 10., 20., 30.)), &Axis::X, &Point { x: 10., y: 20., }, 1.).is_err());
+        let mut obstacles = bench::random_scene(&mut bench::seeded_rng(537), 50, 640., 480., 64.);
+        let broken_probe = seg(100., 100., 500., f64::INFINITY);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            assert!(matches!(tree.intersects(&broken_probe, &mut PointsCutter::default()), Err(Error::CutFragment { query: "intersects", })));
+            assert!(matches!(tree.k_nearest(&broken_probe, 3), Err(Error::CutFragment { query: "nearest", })));
+        }
+        obstacles.push(broken);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            assert!(matches!(SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false), Err(Error::CutFragment { query: "build", })));
+        }
+        let mut env = Env::new(Business::Collide, false, false);
+        let tree = SceneTree::build(VolumeKind::Aabb, &obstacles[.. 50], &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        assert!(env.query_or_report(tree.intersects(&broken_probe, &mut PointsCutter::default())).is_empty());
+        assert!(env.current_error().is_some_and(|message| message.contains("intersects")));
+    }
+
+    #[test]
+    fn svg_has_obstacles_cuts_and_view_box() {
+        let obstacles = [
+            (seg(10., 20., 30., 40.), Shape::Segment),
+            (seg(100., 100., 200., 50.), Shape::Segment),
+            (seg(300., 300., 260., 340.), Shape::Rect),
+            (seg(400., 100., 430., 140.), Shape::Circle),
+        ];
+        let cuts = [(seg(50., 0., 50., 480.), Axis::X)];
+        let view = Bound { lt: Point { x: -5., y: 0., }, rb: Point { x: 635., y: 480., }, };
+        let svg = svg::render(&obstacles, &cuts, &view, [0., 0., 0., 1.], &theme::Theme::default());
+        assert!(svg.starts_with("<svg ") && svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains("viewBox=\"-5 0 640 480\""));
+        assert!(svg.contains("<line x1=\"10\" y1=\"20\" x2=\"30\" y2=\"40\" stroke=\"rgb(191,0,0)\""));
+        assert!(svg.contains("<line x1=\"50\" y1=\"0\" x2=\"50\" y2=\"480\" stroke=\"rgb(64,64,0)\""));
+        assert_eq!(svg.matches("<line ").count(), 3);
+        assert!(svg.contains("<rect x=\"260\" y=\"300\" width=\"40\" height=\"40\" fill=\"none\""));
+        assert!(svg.contains("<circle cx=\"400\" cy=\"100\" r=\"50\" fill=\"none\""));
+    }
+
+    #[test]
+    fn screenshot_path_is_timestamped_png() {
+        let path = capture::screenshot_path();
+        let name = path.to_str().unwrap();
+        assert!(name.starts_with("screenshot-") && name.ends_with(".png"), "{}", name);
+        let stamp: Vec<_> = name["screenshot-".len() .. name.len() - ".png".len()].split('-').collect();
+        assert_eq!(stamp.len(), 2);
+        assert!(stamp[0].parse::<u64>().unwrap() > 0);
+        assert!(stamp[1].len() == 3 && stamp[1].parse::<u32>().unwrap() < 1000);
+    }
+
+    #[test]
+    fn rect_fragment_cut_splits_bound() {
+        let fragment = get_bounding_volume(&seg(0., 0., 100., 50.));
+        match cut_rect_fragment(&fragment, &Axis::X, &Point { x: 40., y: 0., }, KDTREE_CUT_LIMIT) {
+            Ok(Some((left, right))) => {
+                assert!(segment_approx_eq(&Segment { src: left.lt, dst: left.rb, }, &seg(0., 0., 40., 50.), 0.));
+                assert!(segment_approx_eq(&Segment { src: right.lt, dst: right.rb, }, &seg(40., 0., 100., 50.), 0.));
+            },
+            _ => panic!("rect fragment should be split"),
+        }
+        assert!(matches!(cut_rect_fragment(&fragment, &Axis::Y, &Point { x: 0., y: 60., }, KDTREE_CUT_LIMIT), Ok(None)));
+        assert!(matches!(cut_rect_fragment(&get_bounding_volume(&seg(0., 0., 10., 10.)), &Axis::X, &Point { x: 5., y: 0., }, KDTREE_CUT_LIMIT), Ok(None)));
+    }
+
+    #[test]
+    fn rect_obstacle_collides_inside_area() {
+        let mut rng = bench::seeded_rng(508);
+        let mut obstacles = bench::random_scene(&mut rng, 300, 640., 480., 32.);
+        obstacles.push(seg(400., 100., 200., 300.));
+        let rect = obstacles.len() - 1;
+        let shape_of = |i| if i == rect { Shape::Rect } else { Shape::Segment };
+        let shapes: Vec<_> = (0 .. obstacles.len()).collect();
+        // far from the rectangle diagonal but within its area
+        let probe = seg(210., 110., 230., 120.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build_shapes(kind, &obstacles, shape_of, &shapes, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            assert!(tree.intersects(&probe, &mut PointsCutter::default()).unwrap().iter().any(|c| c.shape == rect), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn circle_obstacle_collides_by_distance() {
+        let circle = seg(100., 100., 130., 140.);
+        assert!(segment_hits_circle(&seg(0., 100., 200., 100.), &circle));
+        assert!(segment_hits_circle(&seg(90., 90., 95., 95.), &circle));
+        // inside the bounding square but outside of the circle itself
+        assert!(!segment_hits_circle(&seg(140., 140., 150., 150.), &circle));
+        assert!(segment_approx_eq(
+            &Segment { src: get_circle_obstacle_bound(&circle).lt, dst: get_circle_obstacle_bound(&circle).rb, },
+            &seg(50., 50., 150., 150.),
+            0.,
+        ));
+
+        let mut rng = bench::seeded_rng(509);
+        let mut obstacles = bench::random_scene(&mut rng, 300, 640., 480., 32.);
+        obstacles.push(circle);
+        let index = obstacles.len() - 1;
+        let shape_of = |i| if i == index { Shape::Circle } else { Shape::Segment };
+        let shapes: Vec<_> = (0 .. obstacles.len()).collect();
+        // crosses the circle away from its center to rim segment
+        let probe = seg(60., 120., 80., 90.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build_shapes(kind, &obstacles, shape_of, &shapes, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            assert!(tree.intersects(&probe, &mut PointsCutter::default()).unwrap().iter().any(|c| c.shape == index), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn headless_run_times_both_queries() {
+        let mut scene = Scene::new();
+        for segment in bench::random_scene(&mut bench::seeded_rng(534), 200, 640., 480., 32.) {
+            scene.push(segment, Default::default());
+        }
+        // circles are checked by the actual distance like in the window
+        scene.push(seg(320., 240., 330., 240.), ObstacleMeta { shape: Shape::Circle, ..Default::default() });
+        let tree = SceneTree::build_shapes(VolumeKind::Aabb, &scene.obstacles, |i| scene.meta[i].shape, &[200], &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        let near_box_corner = seg(311., 231., 312., 232.);
+        assert!(tree.intersects(&near_box_corner, &mut PointsCutter::default()).unwrap().iter().any(|collision| collision.shape == 200));
+        assert!(scene_collisions(&tree, &scene, &near_box_corner, &mut PointsCutter::default(), &mut QueryStats::default()).unwrap().is_empty());
+
+        let timings = bench::run_headless(&scene, VolumeKind::Aabb, CutStrategy::Mean, KDTREE_CUT_LIMIT, 534, (640, 480)).unwrap();
+        assert_eq!(timings.iter().map(|timing| (timing.size, timing.strategy)).collect::<Vec<_>>(), [(201, "intersect"), (201, "nearest")]);
+        assert!(timings.iter().all(|timing| timing.build_ms >= 0. && timing.query_us >= 0.));
+    }
+
+    #[test]
+    fn verify_finds_no_missed_collisions() {
+        let mut scene = Scene::new();
+        let mut rng = bench::seeded_rng(535);
+        for (i, segment) in bench::random_scene(&mut rng, 600, 640., 480., 32.).into_iter().enumerate() {
+            let shape = [Shape::Segment, Shape::Segment, Shape::Rect, Shape::Circle][i % 4];
+            scene.push(segment, ObstacleMeta { shape, ..Default::default() });
+        }
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            for &strategy in [CutStrategy::Mean, CutStrategy::Median, CutStrategy::Sah].iter() {
+                assert_eq!(bench::verify(&scene, kind, strategy, KDTREE_CUT_LIMIT, 535, (640, 480)).unwrap(), 0, "{:?} {:?}", kind, strategy);
+            }
+        }
+    }
+
+    #[test]
+    fn bench_csv_has_header_and_rows() {
+        let timings = [
+            bench::Timing { size: 100, strategy: "sequential-intersect", build_ms: 0.5, query_us: 12.25, },
+            bench::Timing { size: 100, strategy: "sequential-nearest", build_ms: 0.5, query_us: 3.5, },
+            bench::Timing { size: 100, strategy: "parallel-intersect", build_ms: 0.25, query_us: 11.75, },
+        ];
+        let csv = bench::to_csv(&timings);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines, [
+            "size,strategy,build_ms,query_us",
+            "100,sequential-intersect,0.500000,12.250000",
+            "100,sequential-nearest,0.500000,3.500000",
+            "100,parallel-intersect,0.250000,11.750000",
+        ]);
+    }
+
+    #[test]
+    fn selftest_passes() {
+        assert!(selftest::run());
+    }
+
+    #[test]
+    fn k_nearest_where_skips_filtered_obstacles() {
+        let obstacles: Vec<_> = (0 .. 10).map(|i| seg(50. + i as f64 * 40., 100., 50. + i as f64 * 40., 300.)).collect();
+        let probe = seg(10., 200., 10., 200.);
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build(kind, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let odd: Vec<_> = tree.k_nearest_where(&probe, 3, |shape| shape % 2 == 1).unwrap().into_iter().map(|neighbour| neighbour.shape).collect();
+            assert_eq!(odd, vec![1, 3, 5], "{:?}", kind);
+            assert_eq!(tree.k_nearest_where(&probe, 3, |shape| shape == 9).unwrap().len(), 1);
+            assert!(tree.k_nearest_where(&probe, 3, |_| false).unwrap().is_empty());
+        }
+    }
+
+    #[test]
+    fn nearest_regions_split_between_obstacles() {
+        let obstacles = vec![seg(100., 0., 100., 200.), seg(300., 0., 300., 200.)];
+        let tree = SceneTree::build(VolumeKind::Aabb, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        let cells = nearest_regions(&tree, Point { x: 0., y: 0., }, 400., 200., 50.).unwrap();
+        assert_eq!(cells.len(), 8 * 4);
+        for (i, cell) in cells.iter().enumerate() {
+            assert_eq!(*cell, Some(if i % 8 < 4 { 0 } else { 1 }), "cell {}", i);
+        }
+        let empty = SceneTree::build(VolumeKind::Aabb, &[], &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        assert!(nearest_regions(&empty, Point { x: 0., y: 0., }, 100., 100., 50.).unwrap().iter().all(Option::is_none));
+        assert_eq!(index_color(7, 0.5), index_color(7, 0.5));
+        assert_ne!(index_color(7, 0.5), index_color(8, 0.5));
+    }
+
+    #[test]
+    fn intersecting_pairs_of_every_shape() {
+        let mut scene = Scene::new();
+        scene.push(seg(0., 100., 200., 100.), Default::default());
+        scene.push(seg(100., 0., 100., 200.), Default::default());
+        scene.push(seg(300., 300., 400., 300.), Default::default());
+        scene.push(seg(350., 250., 450., 350.), ObstacleMeta { shape: Shape::Rect, ..Default::default() });
+        scene.push(seg(600., 100., 650., 100.), ObstacleMeta { shape: Shape::Circle, ..Default::default() });
+        scene.push(seg(680., 100., 730., 100.), ObstacleMeta { shape: Shape::Circle, ..Default::default() });
+        // nested in the first circle without touching its rim
+        scene.push(seg(600., 100., 610., 100.), ObstacleMeta { shape: Shape::Circle, ..Default::default() });
+        scene.push(seg(0., 400., 50., 450.), Default::default());
+        let shapes: Vec<_> = (0 .. scene.obstacles.len()).collect();
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let tree = SceneTree::build_shapes(kind, &scene.obstacles, |i| scene.meta[i].shape, &shapes, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+            let pairs = intersecting_pairs(&tree, &scene, &shapes, &mut PointsCutter::default()).unwrap();
+            assert_eq!(pairs, vec![(0, 1), (2, 3), (4, 5)], "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn exact_collisions_are_found_by_the_tree() {
+        let mut scene = Scene::new();
+        scene.push(seg(0., 100., 200., 100.), Default::default());
+        scene.push(seg(300., 0., 300., 50.), Default::default());
+        scene.push(seg(50., 50., 150., 150.), ObstacleMeta { shape: Shape::Rect, ..Default::default() });
+        scene.push(seg(400., 100., 450., 100.), ObstacleMeta { shape: Shape::Circle, ..Default::default() });
+        // crossed by the first probe but left out of the active ones
+        scene.push(seg(100., 0., 100., 300.), Default::default());
+        let active_shapes: Vec<_> = (0 .. 4).collect();
+        let tree = SceneTree::build_shapes(VolumeKind::Aabb, &scene.obstacles, |i| scene.meta[i].shape, &active_shapes, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        // the second probe only grazes the circle bounding box corner
+        for &(probe, ref expected) in [(seg(10., 120., 500., 120.), vec![2, 3]), (seg(445., 145., 460., 160.), vec![])].iter() {
+            let exact = exact_collisions(&scene, &active_shapes, &probe);
+            assert_eq!(&exact, expected);
+            let found: HashSet<_> = scene_collisions(&tree, &scene, &probe, &mut PointsCutter::default(), &mut QueryStats::default()).unwrap()
+                .into_iter()
+                .map(|collision| collision.shape)
+                .collect();
+            assert!(exact.iter().all(|shape| found.contains(shape)));
+        }
+    }
+
+    #[test]
+    fn capsules_collide_within_their_radius() {
+        let mut scene = Scene::new();
+        scene.push(seg(100., 100., 300., 100.), ObstacleMeta { width: 10., ..Default::default() });
+        scene.push(seg(100., 300., 300., 300.), Default::default());
+        // width of other shapes is ignored
+        scene.push(seg(400., 100., 450., 150.), ObstacleMeta { shape: Shape::Rect, width: 10., ..Default::default() });
+        let bounds = scene.bounds(false);
+        assert_eq!((bounds[0].lt.x, bounds[0].lt.y, bounds[0].rb.x, bounds[0].rb.y), (90., 90., 310., 110.));
+        assert_eq!((bounds[2].lt.x, bounds[2].rb.y), (400., 150.));
+        let shapes: Vec<_> = (0 .. scene.obstacles.len()).collect();
+        let probes = [
+            (seg(150., 95., 250., 95.), vec![0]),
+            // past the rounded end but inside the inflated box corner
+            (seg(308., 92., 309., 91.), vec![]),
+            (seg(305., 100., 320., 100.), vec![0]),
+            (seg(150., 295., 250., 295.), vec![]),
+            (seg(395., 160., 455., 160.), vec![]),
+        ];
+        for &(probe, ref expected) in probes.iter() {
+            assert_eq!(&exact_collisions(&scene, &shapes, &probe), expected, "{:?}", probe);
+        }
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let job = TreeBuildJob {
+                kind,
+                obstacles: scene.obstacles.clone(),
+                bounds: bounds.clone(),
+                shapes: scene.meta.iter().map(ObstacleMeta::indexed_shape).collect(),
+                active_shapes: shapes.clone(),
+                strategy: CutStrategy::Mean,
+                metric: Metric::Euclidean,
+                cut_limit: KDTREE_CUT_LIMIT,
+            };
+            let tree = job.run().tree.unwrap();
+            // circle trees keep loose plain segment hits, but never miss and check capsules exactly
+            for &(probe, ref expected) in probes.iter() {
+                let found: HashSet<_> = scene_collisions(&tree, &scene, &probe, &mut PointsCutter::default(), &mut QueryStats::default()).unwrap()
+                    .into_iter()
+                    .map(|collision| collision.shape)
+                    .collect();
+                assert!(expected.iter().all(|shape| found.contains(shape)), "{:?} {:?}", kind, probe);
+                assert_eq!(found.contains(&0), expected.contains(&0), "{:?} {:?}", kind, probe);
+            }
+        }
+    }
+
+    #[test]
+    fn capsules_stop_rays_and_nearest_at_their_radius() {
+        let mut scene = Scene::new();
+        scene.push(seg(100., 0., 100., 100.), ObstacleMeta { width: 10., ..Default::default() });
+        scene.push(seg(200., 0., 200., 100.), Default::default());
+        scene.push(seg(106., 30., 150., 30.), Default::default());
+        let shapes: Vec<_> = (0 .. scene.obstacles.len()).collect();
+        let bound = Bound { lt: Point { x: 0., y: 0., }, rb: Point { x: 300., y: 200., }, };
+        let near = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        for &kind in [VolumeKind::Aabb, VolumeKind::Circle].iter() {
+            let job = TreeBuildJob {
+                kind,
+                obstacles: scene.obstacles.clone(),
+                bounds: scene.bounds(false),
+                shapes: scene.meta.iter().map(ObstacleMeta::indexed_shape).collect(),
+                active_shapes: shapes.clone(),
+                strategy: CutStrategy::Mean,
+                metric: Metric::Euclidean,
+                cut_limit: KDTREE_CUT_LIMIT,
+            };
+            let tree = job.run().tree.unwrap();
+            let hit = |origin: Point, through: Point| {
+                let ray = cast_ray(&origin, &through, &bound).unwrap();
+                first_hit(&tree, &scene, &ray, &mut PointsCutter::default()).unwrap()
+            };
+            let (shape, dist) = hit(Point { x: 0., y: 50., }, Point { x: 10., y: 50., }).unwrap();
+            assert!(shape == 0 && near(dist, 90.), "{:?} {} {}", kind, shape, dist);
+            // the rounded end is entered later than the inflated box
+            let (shape, dist) = hit(Point { x: 0., y: 105., }, Point { x: 10., y: 105., }).unwrap();
+            assert!(shape == 0 && near(dist, 100. - 75_f64.sqrt()), "{:?} {} {}", kind, shape, dist);
+            assert_eq!(hit(Point { x: 50., y: 115., }, Point { x: 60., y: 115., }), None);
+            assert_eq!(hit(Point { x: 95., y: 50., }, Point { x: 96., y: 50., }), Some((0, 0.)));
+
+            let probe = seg(120., 50., 120., 50.);
+            let refined = k_nearest_exact(tree.nearest(&probe), &probe, &scene, Metric::Euclidean, 3).unwrap();
+            assert_eq!(refined.iter().map(|neighbour| neighbour.shape).collect::<Vec<_>>(), vec![0, 2, 1], "{:?}", kind);
+            assert!(near(refined[0].dist, 10.) && near(refined[1].dist, 20.), "{:?}", kind);
+
+            let pairs = intersecting_pairs(&tree, &scene, &shapes, &mut PointsCutter::default()).unwrap();
+            assert_eq!(pairs, vec![(0, 2)], "{:?}", kind);
+        }
+        assert_eq!(scene.distance(0, &seg(95., 50., 95., 50.)), 0.);
+    }
+
+    #[test]
+    fn small_obstacles_are_labelled_when_zoomed_in() {
+        let bound = get_bounding_volume(&seg(100., 100., 110., 104.));
+        assert!(!label_worthy(&bound, 1.));
+        assert!(label_worthy(&bound, 4.));
+        assert!(label_worthy(&get_bounding_volume(&seg(0., 0., 0., 30.)), 1.));
+        assert!(!label_worthy(&get_bounding_volume(&seg(0., 0., 0., 30.)), MIN_ZOOM));
+    }
+
+    #[test]
+    fn distance_field_grows_away_from_obstacles() {
+        let tree = SceneTree::build(VolumeKind::Aabb, &[seg(100., 0., 100., 200.)], &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        let dists = distance_field(&tree, Point { x: 0., y: 0., }, 400., 100., 50.).unwrap();
+        assert_eq!(dists.len(), 8 * 2);
+        let expected = [75., 25., 25., 75., 125., 175., 225., 275.];
+        for (i, dist) in dists.iter().enumerate() {
+            assert!(dist.is_some_and(|dist| (dist - expected[i % 8]).abs() < 1e-6), "cell {}: {:?}", i, dist);
+        }
+        let empty = SceneTree::build(VolumeKind::Aabb, &[], &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        assert!(distance_field(&empty, Point { x: 0., y: 0., }, 100., 100., 50.).unwrap().iter().all(Option::is_none));
+        assert_eq!(heat_color(0., 275.), [1., 0.25, 0., 0.45]);
+        assert_eq!(heat_color(275., 275.), [0., 0., 1., 0.45]);
+        assert_eq!(heat_color(10., 0.), heat_color(0., 0.));
+    }
+
+    #[test]
+    fn visual_cutter_reuses_cuts_buffer() {
+        let obstacles = vec![seg(10., 10., 600., 400.), seg(20., 400., 500., 30.), seg(300., 50., 320., 450.)];
+        let mut visual_cutter = VisualCutter::with_strategy(CutStrategy::Mean);
+        SceneTree::build(VolumeKind::Aabb, &obstacles, &mut visual_cutter, KDTREE_CUT_LIMIT, false).unwrap();
+        let cuts_count = visual_cutter.cuts.len();
+        let (buffer, capacity) = (visual_cutter.cuts.as_ptr(), visual_cutter.cuts.capacity());
+        assert!(cuts_count > 0);
+        visual_cutter.clear();
+        SceneTree::build(VolumeKind::Aabb, &obstacles, &mut visual_cutter, KDTREE_CUT_LIMIT, false).unwrap();
+        assert_eq!(visual_cutter.cuts.len(), cuts_count);
+        assert_eq!(visual_cutter.cuts.as_ptr(), buffer);
+        assert_eq!(visual_cutter.cuts.capacity(), capacity);
+    }
+
+    #[test]
+    fn equal_weights_keep_unweighted_order() {
+        let mut rng: XorShiftRng = SeedableRng::from_seed([5, 10, 15, 20]);
+        let obstacles: Vec<_> = (0 .. 48)
+            .map(|_| seg(rng.gen_range(0., 640.), rng.gen_range(0., 480.), rng.gen_range(0., 640.), rng.gen_range(0., 480.)))
+            .collect();
+        let mut visual_cutter = VisualCutter::with_strategy(CutStrategy::Mean);
+        let tree = SceneTree::build(VolumeKind::Aabb, &obstacles, &mut visual_cutter, KDTREE_CUT_LIMIT, false).unwrap();
+        let probe = seg(300., 200., 340., 260.);
+        let mut scene = Scene::new();
+        for &segment in obstacles.iter() {
+            scene.push(segment, Default::default());
+        }
+        let unweighted: Vec<_> = refine_neighbours(tree.nearest(&probe).map(Result::unwrap).collect(), &probe, &scene).into_iter()
+            .map(|neighbour| (neighbour.shape, neighbour.dist))
+            .collect();
+        for meta in scene.meta.iter_mut() {
+            meta.weight = 2.5;
+        }
+        let weighted: Vec<_> = refine_neighbours(tree.nearest(&probe).map(Result::unwrap).collect(), &probe, &scene).into_iter()
+            .map(|neighbour| (neighbour.shape, neighbour.dist * 2.5))
+            .collect();
+        assert_eq!(unweighted.len(), weighted.len());
+        for (&(shape_a, dist_a), &(shape_b, dist_b)) in unweighted.iter().zip(weighted.iter()) {
+            assert_eq!(shape_a, shape_b);
+            assert!((dist_a - dist_b).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn adaptive_gradient_follows_farthest_result() {
+        let obstacles = vec![seg(100., 100., 100., 120.), seg(130., 100., 130., 120.), seg(160., 100., 160., 120.)];
+        let tree = SceneTree::build(VolumeKind::Aabb, &obstacles, &mut PointsCutter::default(), KDTREE_CUT_LIMIT, false).unwrap();
+        let probe = seg(90., 110., 90., 110.);
+        let neighbours: Vec<_> = tree.nearest(&probe).map(Result::unwrap).collect();
+        let farthest = neighbours.iter().map(|neighbour| neighbour.dist).fold(0., f64::max);
+        assert!((adaptive_max_dist(&neighbours).unwrap() * 0.6 - farthest).abs() < 1e-9);
+        assert!(adaptive_max_dist(&[]).is_none());
+        let touching: Vec<_> = tree.nearest(&seg(100., 110., 100., 110.)).take(1).map(Result::unwrap).collect();
+        assert!(adaptive_max_dist(&touching).is_none());
+    }
+
+    #[test]
+    fn dedup_flags_near_duplicates_only() {
+        let obstacles = vec![
+            seg(10., 10., 100., 100.),
+            seg(50., 20., 50., 300.),
+            seg(100., 100. + 1e-9, 10., 10.),
+            seg(10., 10., 100., 100.5),
+            seg(50. + 1e-9, 20., 50., 300.),
+            seg(10., 10., 100., 100.),
+        ];
+        assert_eq!(duplicate_obstacles(&obstacles, POINT_EPSILON), vec![false, false, true, false, true, true]);
+        let kept = [obstacles[0], obstacles[1], obstacles[3]];
+        assert_eq!(duplicate_obstacles(&kept, POINT_EPSILON), vec![false; 3]);
+        assert_eq!(duplicate_obstacles(&kept, 1.), vec![false, false, true]);
+    }
+
+    #[test]
+    fn approx_eq_helpers() {
+        let a = Point { x: 10., y: 20., };
+        assert!(points_approx_eq(&a, &Point { x: 10. + 1e-9, y: 20. - 1e-9, }, POINT_EPSILON));
+        assert!(!points_approx_eq(&a, &Point { x: 10.1, y: 20., }, POINT_EPSILON));
+        assert!(points_approx_eq(&a, &Point { x: 10.1, y: 19.9, }, 0.2));
+
+        let s = seg(1., 2., 3., 4.);
+        assert!(segment_approx_eq(&s, &seg(1., 2., 3., 4. + 1e-9), POINT_EPSILON));
+        assert!(segment_approx_eq(&s, &seg(3., 4., 1., 2.), POINT_EPSILON));
+        assert!(!segment_approx_eq(&s, &seg(1., 2., 3., 5.), POINT_EPSILON));
+        assert!(!segment_approx_eq(&s, &seg(1., 2., 1., 2.), POINT_EPSILON));
+    }
+
+    #[test]
+    fn modes_cycle_from_any_starting_mode() {
+        for start in ["construct", "collide", "neighbours", "edit", "range", "ray"].iter() {
+            let mut env = Env::new(start.parse().unwrap(), false, false);
+            let mut visited = Vec::new();
+            for _ in 0 .. 6 {
+                env.toggle_mode();
+                visited.push(env.business.name());
+            }
+            assert_eq!(visited.last(), Some(start), "{}", start);
+            visited.sort();
+            visited.dedup();
+            assert_eq!(visited.len(), 6, "{}", start);
+        }
+    }
+
+    #[test]
+    fn mode_names_round_trip() {
+        let modes = [Business::Construct, Business::Collide, Business::Neighbours, Business::Edit, Business::Range, Business::Ray];
+        for mode in &modes {
+            assert_eq!(mode.name().parse::<Business>().map(|parsed| parsed.name()), Ok(mode.name()));
         }
+        let mut names: Vec<_> = modes.iter().map(Business::name).collect();
+        names.dedup();
+        assert_eq!(names.len(), modes.len());
     }
 }
@@ -0,0 +1,152 @@
+use std::{fs, io, iter};
+use std::path::Path;
+
+use serde::Deserialize;
+use serde_json::{self, Value};
+
+use super::{Point, Segment, Shape, ObstacleMeta};
+
+/// Current scene file format: version 1 is a plain list of obstacle segments,
+/// version 2 adds the planned `"ghosts"` segments, version 3 adds `"rects"` obstacles
+/// stored as their diagonals, version 4 adds `"circles"` stored as a center to rim segment
+/// and version 5 adds the `"meta"` of every obstacle. Bump it whenever the shape model changes.
+pub const SCENE_VERSION: u64 = 5;
+
+#[derive(Debug)]
+pub enum Error {
+    Read { file: String, error: io::Error, },
+    Write { file: String, error: io::Error, },
+    Parse(serde_json::Error),
+    MissingField(&'static str),
+    InvalidField(&'static str),
+    UnsupportedSceneVersion(u64),
+}
+
+/// Segments layers stored in a scene file.
+#[derive(Debug, Default)]
+pub struct SceneLayers {
+    pub obstacles: Vec<Segment>,
+    /// Rectangle obstacles given by a diagonal between opposite corners.
+    pub rects: Vec<Segment>,
+    /// Circle obstacles given by a segment from the center to a point on the circle.
+    pub circles: Vec<Segment>,
+    /// Planned segments which do not take part in the tree.
+    pub ghosts: Vec<Segment>,
+    /// Attributes of the `obstacles`, `rects` and `circles` in that order, empty for files older than version 5.
+    /// Empty metadata is written as the defaults for each layer shape.
+    pub meta: Vec<ObstacleMeta>,
+}
+
+/// Writes the layers to `path` as a JSON object tagged with the `SCENE_VERSION`.
+pub fn save<P>(path: P, layers: &SceneLayers) -> Result<(), Error> where P: AsRef<Path> {
+    fs::write(path.as_ref(), to_json(layers))
+        .map_err(|e| Error::Write { file: path.as_ref().to_string_lossy().to_string(), error: e, })
+}
+
+pub fn load<P>(path: P) -> Result<SceneLayers, Error> where P: AsRef<Path> {
+    let text = fs::read_to_string(path.as_ref())
+        .map_err(|e| Error::Read { file: path.as_ref().to_string_lossy().to_string(), error: e, })?;
+    from_json(&text)
+}
+
+pub fn to_json(layers: &SceneLayers) -> String {
+    fn coords(segments: &[Segment]) -> Vec<[f64; 4]> {
+        segments.iter().map(|s| [s.src.x, s.src.y, s.dst.x, s.dst.y]).collect()
+    }
+    let file = SceneFile {
+        version: SCENE_VERSION,
+        obstacles: coords(&layers.obstacles),
+        rects: coords(&layers.rects),
+        circles: coords(&layers.circles),
+        ghosts: coords(&layers.ghosts),
+        meta: if layers.meta.is_empty() {
+            layer_shapes(layers).map(|shape| ObstacleMeta { shape, ..Default::default() }).collect()
+        } else {
+            layers.meta.clone()
+        },
+    };
+    // plain arrays and numbers only, which always serialize
+    serde_json::to_string_pretty(&file).unwrap_or_else(|e| unreachable!("{:?}", e))
+}
+
+/// Layout of the current scene format as written by `to_json`.
+#[derive(Serialize)]
+struct SceneFile {
+    version: u64,
+    obstacles: Vec<[f64; 4]>,
+    rects: Vec<[f64; 4]>,
+    circles: Vec<[f64; 4]>,
+    ghosts: Vec<[f64; 4]>,
+    meta: Vec<ObstacleMeta>,
+}
+
+/// Parses a scene, dispatching on its `"version"` field, so files written by newer
+/// formats are rejected instead of being silently misread.
+pub fn from_json(text: &str) -> Result<SceneLayers, Error> {
+    // the parser gives up on deeply nested input instead of overflowing the stack
+    let root: Value = serde_json::from_str(text).map_err(Error::Parse)?;
+    let version = match root.get("version") {
+        Some(version) =>
+            version.as_u64().ok_or(Error::InvalidField("version"))?,
+        None =>
+            return Err(Error::MissingField("version")),
+    };
+    match version {
+        1 ..= SCENE_VERSION => {
+            let layer = |field, since| if version >= since { load_segments(&root, field) } else { Ok(Vec::new()) };
+            let mut layers = SceneLayers {
+                obstacles: layer("obstacles", 1)?,
+                ghosts: layer("ghosts", 2)?,
+                rects: layer("rects", 3)?,
+                circles: layer("circles", 4)?,
+                meta: Vec::new(),
+            };
+            if version >= 5 {
+                layers.meta = load_meta(&root, &layers)?;
+            }
+            Ok(layers)
+        },
+        other =>
+            Err(Error::UnsupportedSceneVersion(other)),
+    }
+}
+
+fn load_segments(root: &Value, field: &'static str) -> Result<Vec<Segment>, Error> {
+    let segments: Vec<[f64; 4]> = match root.get(field) {
+        Some(value) =>
+            Vec::deserialize(value).map_err(|_| Error::InvalidField(field))?,
+        None =>
+            return Err(Error::MissingField(field)),
+    };
+    segments.into_iter()
+        .map(|[x1, y1, x2, y2]| {
+            if [x1, y1, x2, y2].iter().all(|coord| coord.is_finite()) {
+                Ok(Segment { src: Point { x: x1, y: y1, }, dst: Point { x: x2, y: y2, }, })
+            } else {
+                Err(Error::InvalidField(field))
+            }
+        })
+        .collect()
+}
+
+/// Obstacle attributes which have to line up with the shape layers they describe.
+fn load_meta(root: &Value, layers: &SceneLayers) -> Result<Vec<ObstacleMeta>, Error> {
+    let meta: Vec<ObstacleMeta> = match root.get("meta") {
+        Some(value) =>
+            Vec::deserialize(value).map_err(|_| Error::InvalidField("meta"))?,
+        None =>
+            return Err(Error::MissingField("meta")),
+    };
+    let valid = meta.len() == layer_shapes(layers).count() &&
+        meta.iter().zip(layer_shapes(layers)).all(|(meta, shape)| {
+            meta.shape == shape && meta.weight.is_finite() && meta.weight > 0. && meta.width.is_finite() && meta.width >= 0.
+        });
+    if valid { Ok(meta) } else { Err(Error::InvalidField("meta")) }
+}
+
+/// Shape of every obstacle in the `obstacles`, `rects` and `circles` order.
+fn layer_shapes(layers: &SceneLayers) -> impl Iterator<Item = Shape> {
+    iter::repeat_n(Shape::Segment, layers.obstacles.len())
+        .chain(iter::repeat_n(Shape::Rect, layers.rects.len()))
+        .chain(iter::repeat_n(Shape::Circle, layers.circles.len()))
+}
@@ -0,0 +1,87 @@
+use super::{
+    Axis,
+    Bound,
+    Point,
+    Segment,
+    CutStrategy,
+    get_bounding_volume,
+    cut_segment_fragment,
+    bound_to_bound_dist,
+    segment_intersection,
+    all_intersections,
+    points_approx_eq,
+    POINT_EPSILON,
+    KDTREE_CUT_LIMIT,
+};
+
+fn seg(x1: f64, y1: f64, x2: f64, y2: f64) -> Segment {
+    Segment { src: Point { x: x1, y: y1, }, dst: Point { x: x2, y: y2, }, }
+}
+
+fn bound(l: f64, t: f64, r: f64, b: f64) -> Bound {
+    Bound { lt: Point { x: l, y: t, }, rb: Point { x: r, y: b, }, }
+}
+
+fn bounds_eq(a: &Bound, b: &Bound) -> bool {
+    points_approx_eq(&a.lt, &b.lt, POINT_EPSILON) && points_approx_eq(&a.rb, &b.rb, POINT_EPSILON)
+}
+
+fn checks() -> Vec<(&'static str, bool)> {
+    let diagonal = seg(100., 200., 0., 0.);
+    let cut = |axis, x, y| cut_segment_fragment(&diagonal, &get_bounding_volume(&diagonal), &axis, &Point { x, y, }, KDTREE_CUT_LIMIT);
+    vec![
+        ("bounding volume of a reversed segment",
+         bounds_eq(&get_bounding_volume(&diagonal), &bound(0., 0., 100., 200.))),
+        ("bounding volume of a point",
+         bounds_eq(&get_bounding_volume(&seg(5., 5., 5., 5.)), &bound(5., 5., 5., 5.))),
+        ("cut along x splits at the interpolated point",
+         match cut(Axis::X, 50., 0.) {
+             Ok(Some((ref left, ref right))) =>
+                 bounds_eq(left, &bound(0., 0., 50., 100.)) && bounds_eq(right, &bound(50., 100., 100., 200.)),
+             _ =>
+                 false,
+         }),
+        ("cut along y splits at the interpolated point",
+         match cut(Axis::Y, 0., 100.) {
+             Ok(Some((ref upper, ref lower))) =>
+                 bounds_eq(upper, &bound(0., 0., 50., 100.)) && bounds_eq(lower, &bound(50., 100., 100., 200.)),
+             _ =>
+                 false,
+         }),
+        ("cut outside of the fragment is skipped",
+         matches!(cut(Axis::X, 150., 0.), Ok(None))),
+        ("overlapping bounds are at zero distance",
+         bound_to_bound_dist(&bound(0., 0., 10., 10.), &bound(5., 5., 20., 20.)) == 0.),
+        ("side by side bounds distance",
+         bound_to_bound_dist(&bound(0., 0., 10., 10.), &bound(15., 0., 20., 10.)) == 5.),
+        ("diagonal bounds distance",
+         bound_to_bound_dist(&bound(0., 0., 10., 10.), &bound(13., 14., 20., 20.)) == 5.),
+        ("crossing segments intersection point",
+         segment_intersection(&seg(0., 0., 10., 10.), &seg(0., 10., 10., 0.))
+             .is_some_and(|p| points_approx_eq(&p, &Point { x: 5., y: 5., }, POINT_EPSILON))),
+        ("parallel segments do not intersect",
+         segment_intersection(&seg(0., 0., 10., 0.), &seg(0., 1., 10., 1.)).is_none()),
+        ("disjoint segments do not intersect",
+         segment_intersection(&seg(0., 0., 1., 1.), &seg(5., 0., 3., 2.)).is_none()),
+        ("tree intersections find the crossed obstacles",
+         {
+             let obstacles = [seg(100., 100., 300., 300.), seg(400., 100., 600., 100.), seg(200., 50., 200., 350.)];
+             let mut shapes: Vec<_> = all_intersections(&obstacles, &seg(50., 200., 350., 200.), CutStrategy::default(), KDTREE_CUT_LIMIT).unwrap_or_default().into_iter()
+                 .map(|(shape, _, _)| shape)
+                 .collect();
+             shapes.sort();
+             shapes.dedup();
+             shapes == [0, 2]
+         }),
+    ]
+}
+
+/// Runs the geometry checks printing a PASS/FAIL line for each one, returns whether all of them passed.
+pub fn run() -> bool {
+    let mut passed = true;
+    for (name, ok) in checks() {
+        println!("{} {}", if ok { "PASS" } else { "FAIL" }, name);
+        passed &= ok;
+    }
+    passed
+}
@@ -0,0 +1,69 @@
+use std::{fs, io};
+use std::path::Path;
+
+use super::{Axis, Bound, Segment, Shape, get_bounding_volume, get_circle_obstacle_bound};
+use super::theme::Theme;
+
+fn rgb(color: [f32; 4]) -> String {
+    let channel = |value: f32| (value.clamp(0., 1.) * 255.).round() as u8;
+    format!("rgb({},{},{})", channel(color[0]), channel(color[1]), channel(color[2]))
+}
+
+fn line(segment: &Segment, color: [f32; 4], width: f64) -> String {
+    format!(
+        "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+        segment.src.x, segment.src.y, segment.dst.x, segment.dst.y, rgb(color), width,
+    )
+}
+
+/// Outline of a rectangle or a circle obstacle, the same way `draw_obstacle` puts it on screen.
+fn outline(obstacle: &Segment, shape: Shape, color: [f32; 4], width: f64) -> String {
+    match shape {
+        Shape::Segment =>
+            line(obstacle, color, width),
+        Shape::Rect => {
+            let Bound { lt, rb, } = get_bounding_volume(obstacle);
+            format!(
+                "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+                lt.x, lt.y, rb.x - lt.x, rb.y - lt.y, rgb(color), width / 2.,
+            )
+        },
+        Shape::Circle => {
+            let Bound { lt, rb, } = get_circle_obstacle_bound(obstacle);
+            format!(
+                "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\" stroke=\"{}\" stroke-width=\"{}\"/>\n",
+                obstacle.src.x, obstacle.src.y, (rb.x - lt.x) / 2., rgb(color), width / 2.,
+            )
+        },
+    }
+}
+
+/// Renders obstacles of their shapes over the tree cuts mesh in world coordinates, `view` becomes the `viewBox`
+/// so the picture matches the window contents. Colors are the `theme` ones used on screen.
+pub fn render(obstacles: &[(Segment, Shape)], cuts: &[(Segment, Axis)], view: &Bound, background: [f32; 4], theme: &Theme) -> String {
+    let (width, height) = (view.rb.x - view.lt.x, view.rb.y - view.lt.y);
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"{} {} {} {}\">\n",
+        width, height, view.lt.x, view.lt.y, width, height,
+    );
+    svg.push_str(&format!(
+        "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"/>\n",
+        view.lt.x, view.lt.y, width, height, rgb(background),
+    ));
+    for (cut_seg, axis) in cuts {
+        let color = match *axis {
+            Axis::X => theme.cut_x,
+            Axis::Y => theme.cut_y,
+        };
+        svg.push_str(&line(cut_seg, color, 1.));
+    }
+    for &(ref obstacle, shape) in obstacles {
+        svg.push_str(&outline(obstacle, shape, theme.obstacle, 2.));
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+pub fn save<P>(path: P, svg: &str) -> io::Result<()> where P: AsRef<Path> {
+    fs::write(path, svg)
+}
@@ -0,0 +1,121 @@
+use std::{fs, io};
+use std::path::Path;
+use std::collections::BTreeMap;
+
+use toml;
+
+use super::Business;
+
+pub type Color = [f32; 4];
+
+/// Colors of the scene elements drawn on screen and exported to svg.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub obstacle: Color,
+    pub cut_x: Color,
+    pub cut_y: Color,
+    /// Collided, in range or otherwise picked out obstacles.
+    pub highlight: Color,
+    /// Fresh collisions flash with this color fading into `highlight`.
+    pub highlight_pulse: Color,
+    pub cursor_construct: Color,
+    pub cursor_collide: Color,
+    pub cursor_neighbours: Color,
+    pub cursor_edit: Color,
+    pub cursor_range: Color,
+    pub cursor_ray: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            obstacle: [0.75, 0., 0., 1.0],
+            cut_x: [0.25, 0.25, 0., 1.0],
+            cut_y: [0., 0.25, 0.25, 1.0],
+            highlight: [0.75, 0.75, 0., 1.0],
+            highlight_pulse: [1., 1., 0.6, 1.0],
+            cursor_construct: [1.0, 0., 0., 1.0],
+            cursor_collide: [0., 0.25, 0., 1.0],
+            cursor_neighbours: [0.824, 0.706, 0.549, 1.0],
+            cursor_edit: [0.4, 0.6, 1.0, 1.0],
+            cursor_range: [0.4, 0.8, 0.4, 1.0],
+            cursor_ray: [0.4, 0.6, 1.0, 1.0],
+        }
+    }
+}
+
+impl Theme {
+    pub fn cursor(&self, business: &Business) -> Color {
+        match *business {
+            Business::Construct => self.cursor_construct,
+            Business::Collide => self.cursor_collide,
+            Business::Neighbours => self.cursor_neighbours,
+            Business::Edit => self.cursor_edit,
+            Business::Range => self.cursor_range,
+            Business::Ray => self.cursor_ray,
+        }
+    }
+
+    fn color_mut(&mut self, key: &str) -> Option<&mut Color> {
+        match key {
+            "obstacle" => Some(&mut self.obstacle),
+            "cut_x" => Some(&mut self.cut_x),
+            "cut_y" => Some(&mut self.cut_y),
+            "highlight" => Some(&mut self.highlight),
+            "highlight_pulse" => Some(&mut self.highlight_pulse),
+            "cursor_construct" => Some(&mut self.cursor_construct),
+            "cursor_collide" => Some(&mut self.cursor_collide),
+            "cursor_neighbours" => Some(&mut self.cursor_neighbours),
+            "cursor_edit" => Some(&mut self.cursor_edit),
+            "cursor_range" => Some(&mut self.cursor_range),
+            "cursor_ray" => Some(&mut self.cursor_ray),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Read { file: String, error: io::Error, },
+    Parse(toml::de::Error),
+    UnknownKey(String),
+    InvalidColor(String),
+}
+
+/// Layout of a theme file: `key = [r, g, b]` or `key = [r, g, b, a]` colors at the top level
+/// or under a `[colors]` table.
+#[derive(Deserialize)]
+struct ThemeFile {
+    #[serde(default)]
+    colors: BTreeMap<String, Vec<f32>>,
+    #[serde(flatten)]
+    top: BTreeMap<String, Vec<f32>>,
+}
+
+pub fn load<P>(path: P) -> Result<Theme, Error> where P: AsRef<Path> {
+    let text = fs::read_to_string(path.as_ref())
+        .map_err(|e| Error::Read { file: path.as_ref().to_string_lossy().to_string(), error: e, })?;
+    from_toml(&text)
+}
+
+/// Reads theme colors with components from 0 to 1, keys left out keep their defaults.
+pub fn from_toml(text: &str) -> Result<Theme, Error> {
+    let file: ThemeFile = toml::from_str(text).map_err(Error::Parse)?;
+    let mut theme = Theme::default();
+    for (key, components) in file.top.into_iter().chain(file.colors) {
+        let color = to_color(&components).ok_or_else(|| Error::InvalidColor(key.clone()))?;
+        *theme.color_mut(&key).ok_or(Error::UnknownKey(key))? = color;
+    }
+    Ok(theme)
+}
+
+fn to_color(components: &[f32]) -> Option<Color> {
+    if !components.iter().all(|c| (0. ..= 1.).contains(c)) {
+        return None;
+    }
+    match *components {
+        [r, g, b] => Some([r, g, b, 1.0]),
+        [r, g, b, a] => Some([r, g, b, a]),
+        _ => None,
+    }
+}
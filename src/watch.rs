@@ -0,0 +1,77 @@
+use std::{fs, thread};
+use std::path::PathBuf;
+use std::time::Duration;
+use std::sync::mpsc;
+use std::io::{BufRead, BufReader};
+
+use super::{Point, Segment};
+
+const WATCH_POLL_MS: u64 = 100;
+
+/// Spawns a thread which follows `path` (a regular file or a FIFO) and sends every
+/// well-formed `x1 y1 x2 y2` line as a new obstacle segment.
+pub fn spawn(path: PathBuf) -> mpsc::Receiver<Segment> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // opening a FIFO blocks until some writer shows up
+        let file = match fs::File::open(&path) {
+            Ok(file) =>
+                file,
+            Err(e) => {
+                error!("failed to open watch file {:?}: {:?}", path, e);
+                return;
+            },
+        };
+        info!("watching {:?} for new obstacles", path);
+        let mut reader = BufReader::new(file);
+        let mut line = Vec::new();
+        loop {
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => {
+                    // no writers or no appended data yet: keep any partial line and retry later
+                    thread::sleep(Duration::from_millis(WATCH_POLL_MS));
+                    continue;
+                },
+                Ok(_) if line.last() != Some(&b'\n') =>
+                    continue,
+                Ok(_) =>
+                    (),
+                Err(e) => {
+                    error!("failed to read watch file {:?}: {:?}", path, e);
+                    return;
+                },
+            }
+            let text = String::from_utf8_lossy(&line).trim().to_string();
+            line.clear();
+            if text.is_empty() || text.starts_with('#') {
+                continue;
+            }
+            match parse_segment(&text) {
+                Some(segment) =>
+                    if tx.send(segment).is_err() {
+                        return;
+                    },
+                None =>
+                    warn!("skipping malformed watch line: {:?}", text),
+            }
+        }
+    });
+    rx
+}
+
+fn parse_segment(line: &str) -> Option<Segment> {
+    let mut coords = line
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(|word| word.parse::<f64>().ok().filter(|value| value.is_finite()));
+    let mut next = || coords.next().and_then(|coord| coord);
+    let segment = Segment {
+        src: Point { x: next()?, y: next()?, },
+        dst: Point { x: next()?, y: next()?, },
+    };
+    if coords.next().is_some() {
+        None
+    } else {
+        Some(segment)
+    }
+}